@@ -0,0 +1,58 @@
+//! Measure writing many small typed chapters.
+//!
+//! Each chapter is a value serialized with
+//! `BookWriter::write_chapter_value`, which reuses the writer's scratch
+//! buffer rather than allocating for every value.
+//!
+//! Run with `cargo bench --bench typed_chapters`. An optional argument
+//! sets the number of chapters (default 100000).
+
+use bookfile::BookWriter;
+use serde::Serialize;
+use std::convert::TryInto;
+use std::time::{Duration, Instant};
+
+/// A small value, similar to a typical index record.
+#[derive(Serialize)]
+struct Record {
+    key: u64,
+    generation: u32,
+    name: &'static str,
+}
+
+const RUNS: usize = 5;
+
+fn write_book(chapters: u64) -> Duration {
+    let start = Instant::now();
+    let mut book = BookWriter::new_in_memory(0x1234).unwrap();
+    for key in 0..chapters {
+        let record = Record {
+            key,
+            generation: 1,
+            name: "record",
+        };
+        book.write_chapter_value(key, &record).unwrap();
+    }
+    let buf = book.close().unwrap();
+    let elapsed = start.elapsed();
+    assert!(!buf.get_ref().is_empty());
+    elapsed
+}
+
+fn main() {
+    // `cargo bench` passes `--bench`; ignore any flags.
+    let chapters = std::env::args()
+        .skip(1)
+        .find(|arg| !arg.starts_with('-'))
+        .map(|arg| arg.parse().expect("chapter count"))
+        .unwrap_or(100_000);
+
+    let best = (0..RUNS).map(|_| write_book(chapters)).min().unwrap();
+    println!(
+        "typed_chapters: {} chapters in {:.1?} (best of {}), {:.0?} per chapter",
+        chapters,
+        best,
+        RUNS,
+        best / chapters.try_into().unwrap_or(u32::MAX),
+    );
+}
@@ -0,0 +1,8 @@
+#![no_main]
+
+use bookfile::Book;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = Book::parse(data);
+});
@@ -1,36 +1,347 @@
-use crate::read::BoundedReader;
-use crate::{BookError, Result};
-use aversion::group::{DataSink, DataSourceExt};
-use aversion::util::cbor::CborData;
-use aversion::{assign_message_ids, FromVersion, UpgradeLatest, Versioned};
+use crate::cache::ChapterCache;
+use crate::checksum::{ChapterHasher, Checksum, ChecksumKind, Crc32};
+use crate::compression::{self, Codec};
+#[cfg(feature = "gzip")]
+use crate::compression::{CompressedChapterWriter, Compression};
+#[cfg(feature = "encryption")]
+use crate::encryption::{self, EncryptedChapterWriter};
+use crate::read::{BoundedReader, CheckedChapterReader, TryClone};
+use crate::write::{CountingWriter, Durable};
+use crate::{BookError, Result, ResultExt};
+use aversion::group::{DataSink, DataSource, GroupHeader, UpgradeLatest};
+use aversion::util::cbor::{CborData, CborDataError};
+use aversion::{assign_message_ids, FromVersion, MessageId, UpgradeLatest, Versioned};
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+#[cfg(feature = "mmap")]
+use memmap2::Mmap;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
 use std::convert::TryInto;
-use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+use std::hash::Hasher;
+use std::io::{self, Cursor, IoSlice, Read, Seek, SeekFrom, Write};
 use std::num::NonZeroU64;
+#[cfg(feature = "mmap")]
+use std::ops::Range;
+use std::sync::{Mutex, MutexGuard, PoisonError};
 use std::thread::panicking;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 /// The version of BookWriter being used
 const BOOK_V1_MAGIC: u32 = 0xFF33_0001;
 
-/// The fixed size of a header block
+/// The magic number reserved for the next file format version.
+///
+/// Books in this format can't be read yet; they are recognized so that
+/// they're reported as an unsupported version, not as a foreign file.
+const BOOK_V2_MAGIC: u32 = 0xFF33_0002;
+
+/// The upper half of every `Book` format magic number. The lower half
+/// is the format version.
+const BOOK_MAGIC_FAMILY: u32 = 0xFF33_0000;
+
+/// The default size of a header block
 const HEADER_SIZE: usize = 4096;
 
+/// The smallest allowed header block
+const MIN_HEADER_SIZE: usize = 512;
+
+/// The largest allowed header block
+const MAX_HEADER_SIZE: usize = 0x10_0000; // 1MB
+
+/// A header flag indicating that space for the TOC was reserved
+/// immediately after the header.
+const FLAG_FRONT_TOC: u32 = 0x1;
+
+/// A header flag indicating that chapters were written in ascending
+/// id order, so the TOC can be binary-searched.
+const FLAG_SORTED_IDS: u32 = 0x2;
+
+/// A header flag indicating that the TOC at the end of the file is
+/// followed by a CRC-32 of its bytes, just before the TOC length.
+const FLAG_TOC_CRC: u32 = 0x4;
+
+/// A header flag indicating that the TOC at the end of the file is
+/// gzip-compressed. The TOC checksum and length describe the compressed
+/// bytes.
+const FLAG_TOC_GZIP: u32 = 0x8;
+
+/// A header flag indicating that the TOC at the end of the file is
+/// stored as a stream of entry records, so that appending chapters
+/// doesn't require re-serializing the existing entries.
+const FLAG_TOC_RECORDS: u32 = 0x10;
+
+/// The message id that starts a TOC stored as entry records.
+///
+/// This takes the place of the `Toc` message header, so it must not
+/// collide with the message ids assigned to `FileHeader` and `Toc`.
+const TOC_RECORDS_ID: u16 = 3;
+
 /// The maximum TOC size we will attempt to read
 const MAX_TOC_SIZE: u64 = 0x400_0000; // 64MB
 
+/// The maximum number of chapters in a book.
+///
+/// A TOC claiming more entries than this is rejected with
+/// [`BookError::TooManyChapters`] before any entries are parsed, so a
+/// small (e.g. compressed) TOC can't make a reader allocate without
+/// bound. Writers refuse to add more chapters than this.
+pub const MAX_CHAPTERS: usize = 10_000_000;
+
+/// The size of the buffer used when copying chapter data
+const COPY_BUFFER_SIZE: usize = 0x1_0000; // 64KB
+
 /// The `Book` file header struct.
 ///
 /// This is used to communicate that this file is in `Book`
 /// format, and what type of data it contains.
-#[derive(Debug, Versioned, UpgradeLatest, Serialize, Deserialize)]
+#[derive(Debug, Clone, Versioned, Serialize, Deserialize)]
 pub struct FileHeaderV1 {
     bookwriter_magic: u32,
+    /// The user-defined magic number.
+    pub user_magic: u32,
+}
+
+/// The `Book` file header struct.
+///
+/// This is used to communicate that this file is in `Book`
+/// format, and what type of data it contains.
+#[derive(Debug, Clone, Versioned, Serialize, Deserialize)]
+pub struct FileHeaderV2 {
+    bookwriter_magic: u32,
+    /// The user-defined magic number.
+    pub user_magic: u32,
+    /// Flags describing the file layout.
+    flags: u32,
+    /// The size of the TOC region following the header, if
+    /// `FLAG_FRONT_TOC` is set.
+    front_toc_size: u64,
+}
+
+impl FromVersion<FileHeaderV1> for FileHeaderV2 {
+    fn from_version(v1: FileHeaderV1) -> Self {
+        FileHeaderV2 {
+            bookwriter_magic: v1.bookwriter_magic,
+            user_magic: v1.user_magic,
+            flags: 0,
+            front_toc_size: 0,
+        }
+    }
+}
+
+/// The `Book` file header struct.
+///
+/// This is used to communicate that this file is in `Book`
+/// format, and what type of data it contains.
+#[derive(Debug, Clone, Versioned, Serialize, Deserialize)]
+pub struct FileHeaderV3 {
+    bookwriter_magic: u32,
+    /// The user-defined magic number.
+    pub user_magic: u32,
+    /// Flags describing the file layout.
+    flags: u32,
+    /// The size of the TOC region following the header, if
+    /// `FLAG_FRONT_TOC` is set.
+    front_toc_size: u64,
+    /// Arbitrary user-defined metadata.
+    pub user_meta: Vec<u8>,
+}
+
+impl FromVersion<FileHeaderV2> for FileHeaderV3 {
+    fn from_version(v2: FileHeaderV2) -> Self {
+        FileHeaderV3 {
+            bookwriter_magic: v2.bookwriter_magic,
+            user_magic: v2.user_magic,
+            flags: v2.flags,
+            front_toc_size: v2.front_toc_size,
+            user_meta: Vec::new(),
+        }
+    }
+}
+
+/// The `Book` file header struct.
+///
+/// This is used to communicate that this file is in `Book`
+/// format, and what type of data it contains.
+#[derive(Debug, Clone, Versioned, Serialize, Deserialize)]
+pub struct FileHeaderV4 {
+    bookwriter_magic: u32,
+    /// The user-defined magic number.
+    pub user_magic: u32,
+    /// Flags describing the file layout.
+    flags: u32,
+    /// The size of the TOC region following the header, if
+    /// `FLAG_FRONT_TOC` is set.
+    front_toc_size: u64,
+    /// Arbitrary user-defined metadata.
+    pub user_meta: Vec<u8>,
+    /// The size of the header block, including padding.
+    header_size: u64,
+}
+
+impl FromVersion<FileHeaderV3> for FileHeaderV4 {
+    fn from_version(v3: FileHeaderV3) -> Self {
+        FileHeaderV4 {
+            bookwriter_magic: v3.bookwriter_magic,
+            user_magic: v3.user_magic,
+            flags: v3.flags,
+            front_toc_size: v3.front_toc_size,
+            user_meta: v3.user_meta,
+            // Older headers were always padded to the default size.
+            header_size: HEADER_SIZE as u64,
+        }
+    }
+}
+
+/// The `Book` file header struct.
+///
+/// This is used to communicate that this file is in `Book`
+/// format, and what type of data it contains.
+#[derive(Debug, Clone, Versioned, UpgradeLatest, Serialize, Deserialize)]
+pub struct FileHeaderV5 {
+    bookwriter_magic: u32,
+    /// The user-defined magic number.
     pub user_magic: u32,
+    /// Flags describing the file layout.
+    flags: u32,
+    /// The size of the TOC region following the header, if
+    /// `FLAG_FRONT_TOC` is set.
+    front_toc_size: u64,
+    /// Arbitrary user-defined metadata.
+    pub user_meta: Vec<u8>,
+    /// The size of the header block, including padding.
+    header_size: u64,
+    /// When the file was created, in seconds since the Unix epoch.
+    created_unix_secs: Option<u64>,
+}
+
+impl FromVersion<FileHeaderV4> for FileHeaderV5 {
+    fn from_version(v4: FileHeaderV4) -> Self {
+        FileHeaderV5 {
+            bookwriter_magic: v4.bookwriter_magic,
+            user_magic: v4.user_magic,
+            flags: v4.flags,
+            front_toc_size: v4.front_toc_size,
+            user_meta: v4.user_meta,
+            header_size: v4.header_size,
+            created_unix_secs: None,
+        }
+    }
 }
 
 /// A type alias; this will always point to the latest version `FileHeader`.
-pub type FileHeader = FileHeaderV1;
+pub type FileHeader = FileHeaderV5;
+
+impl FileHeader {
+    /// Create a new `FileHeader` with default settings.
+    fn new(user_magic: u32) -> Self {
+        FileHeader {
+            bookwriter_magic: BOOK_V1_MAGIC,
+            user_magic,
+            flags: FLAG_TOC_CRC,
+            front_toc_size: 0,
+            user_meta: Vec::new(),
+            header_size: HEADER_SIZE as u64,
+            created_unix_secs: now_unix_secs(),
+        }
+    }
+
+    /// Return the size of the header block, including padding.
+    pub fn header_size(&self) -> u64 {
+        self.header_size
+    }
+
+    /// Return when the file was created, in seconds since the Unix epoch.
+    ///
+    /// This is `None` for files written before the timestamp was added,
+    /// or if the writer chose not to record one.
+    pub fn created_unix_secs(&self) -> Option<u64> {
+        self.created_unix_secs
+    }
+
+    /// Read only the file header from the start of a stream.
+    ///
+    /// The magic number and header size are checked, as in [`Book::new`],
+    /// but nothing past the header block is read, so the TOC needn't be
+    /// valid or even present. This is a cheap way to identify many files.
+    /// To read chapters later without re-reading the header, use
+    /// [`Book::new_lazy`] instead.
+    pub fn read_from<R>(reader: &mut R) -> Result<FileHeader>
+    where
+        R: Read + Seek,
+    {
+        let (header, _) = read_header(reader).context("reading header")?;
+        Ok(header)
+    }
+}
+
+/// Return the current time, in seconds since the Unix epoch.
+///
+/// If the system clock is set before the epoch, `None` is returned.
+fn now_unix_secs() -> Option<u64> {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .ok()
+        .map(|elapsed| elapsed.as_secs())
+}
+
+/// Check that a header block size is within the allowed range.
+fn check_header_size(header_size: u64) -> Result<()> {
+    if header_size < MIN_HEADER_SIZE as u64 || header_size > MAX_HEADER_SIZE as u64 {
+        return Err(BookError::InvalidHeaderSize { size: header_size });
+    }
+    Ok(())
+}
+
+/// Check that a front TOC region can hold the TOC length and a TOC.
+///
+/// This bounds the region size, so that offsets computed from it can't
+/// overflow.
+fn check_front_toc_size(header: &FileHeader) -> Result<()> {
+    match header
+        .front_toc_size
+        .checked_sub(front_toc_overhead(header))
+    {
+        Some(toc_capacity) if toc_capacity <= MAX_TOC_SIZE => Ok(()),
+        _ => Err(BookError::CorruptToc),
+    }
+}
+
+/// Return the number of bytes in the front TOC region that precede the TOC.
+///
+/// The region starts with the 8-byte TOC length. If the header has
+/// `FLAG_TOC_CRC` set, it's followed by a 4-byte CRC of the TOC.
+fn front_toc_overhead(header: &FileHeader) -> u64 {
+    if header.flags & FLAG_TOC_CRC != 0 {
+        12
+    } else {
+        8
+    }
+}
+
+/// Serialize a header into a block of exactly `header_size` bytes.
+///
+/// If the serialized header doesn't fit, [`BookError::HeaderTooLarge`]
+/// is returned.
+fn serialize_header(header: &FileHeader, header_size: usize) -> Result<Vec<u8>> {
+    // Serialize the header into a buffer.
+    let header_buf = Cursor::new(Vec::<u8>::new());
+    let mut header_writer = CborData::new(header_buf);
+    header_writer.write_message(header)?;
+
+    let mut header_buf = header_writer.into_inner().into_inner();
+    if header_buf.len() > header_size {
+        return Err(BookError::HeaderTooLarge {
+            size: header_buf.len(),
+            max: header_size,
+        });
+    }
+    // Pad the buffer with zeroes so that it's the expected
+    // size.
+    header_buf.resize(header_size, 0);
+    Ok(header_buf)
+}
 
 /// A `FileSpan` stores the byte offset and length of some range of a file.
 ///
@@ -38,7 +349,7 @@ pub type FileHeader = FileHeaderV1;
 /// can be confusing if code attempts to read a zero-sized span. Use
 /// `Option<FileSpan` to represent a zero-sized span.
 ///
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct FileSpanV1 {
     pub offset: u64,
     pub length: NonZeroU64,
@@ -48,9 +359,7 @@ impl FileSpanV1 {
     /// Create a `FileSpan` from offset and length.
     ///
     /// If `length` is 0, `None` will be returned.
-    pub fn from_offset_length(offset: usize, length: usize) -> Option<Self> {
-        let offset = offset as u64;
-        let length = length as u64;
+    pub fn from_offset_length(offset: u64, length: u64) -> Option<Self> {
         // Try to create a NonZeroU64 length; if that returns Some(l)
         // then return Some(FileSpan{..}) else None.
         NonZeroU64::new(length).map(|length| FileSpanV1 { offset, length })
@@ -80,8 +389,144 @@ pub struct TocEntryV2 {
     pub span: Option<FileSpanV1>,
 }
 
+/// A Table-of-contents entry.
+///
+/// This contains an identifying number, a file span that
+/// tells us what chunk of the file contains this chapter, and
+/// an optional CRC-32 checksum of the chapter contents.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct TocEntryV3 {
+    pub id: Box<[u8]>,
+    pub span: Option<FileSpanV1>,
+    pub crc32: Option<u32>,
+}
+
+/// A Table-of-contents entry.
+///
+/// This contains an identifying number, an optional human-readable
+/// name, a file span that tells us what chunk of the file contains
+/// this chapter, and an optional CRC-32 checksum of the chapter contents.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TocEntryV4 {
+    pub id: Box<[u8]>,
+    pub name: Option<String>,
+    pub span: Option<FileSpanV1>,
+    pub crc32: Option<u32>,
+}
+
+/// A Table-of-contents entry.
+///
+/// This contains an identifying number, an optional human-readable
+/// name, a file span that tells us what chunk of the file contains
+/// this chapter, and an optional checksum of the chapter contents.
+/// The checksum records which algorithm was used to compute it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TocEntryV5 {
+    pub id: Box<[u8]>,
+    pub name: Option<String>,
+    pub span: Option<FileSpanV1>,
+    pub checksum: Option<Checksum>,
+}
+
+/// The parameters needed to decrypt an encrypted chapter.
+///
+/// Chapters are encrypted with AES-256-GCM; the chapter id is used as
+/// associated data, so an encrypted chapter can't be moved to another id.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EncryptionV1 {
+    /// The AES-GCM nonce.
+    pub nonce: [u8; 12],
+    /// The AES-GCM authentication tag.
+    pub tag: [u8; 16],
+}
+
+/// A Table-of-contents entry.
+///
+/// This contains an identifying number, an optional human-readable
+/// name, a file span that tells us what chunk of the file contains
+/// this chapter, an optional checksum of the chapter contents, and
+/// the encryption parameters if the chapter is encrypted.
+///
+/// For an encrypted chapter, the span and checksum refer to the
+/// ciphertext stored in the file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TocEntryV6 {
+    pub id: Box<[u8]>,
+    pub name: Option<String>,
+    pub span: Option<FileSpanV1>,
+    pub checksum: Option<Checksum>,
+    pub encryption: Option<EncryptionV1>,
+}
+
+/// A Table-of-contents entry.
+///
+/// This contains an identifying number, an optional human-readable
+/// name, a file span that tells us what chunk of the file contains
+/// this chapter, an optional checksum of the chapter contents, the
+/// encryption parameters if the chapter is encrypted, and the codec
+/// used if the chapter is compressed.
+///
+/// For a compressed chapter, the span and checksum refer to the
+/// compressed bytes stored in the file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TocEntryV7 {
+    pub id: Box<[u8]>,
+    pub name: Option<String>,
+    pub span: Option<FileSpanV1>,
+    pub checksum: Option<Checksum>,
+    pub encryption: Option<EncryptionV1>,
+    pub codec: Option<Codec>,
+}
+
+/// A Table-of-contents entry.
+///
+/// This contains an identifying number, an optional human-readable
+/// name, a file span that tells us what chunk of the file contains
+/// this chapter, an optional checksum of the chapter contents, the
+/// encryption parameters if the chapter is encrypted, the codec used
+/// if the chapter is compressed, and an optional user-defined content
+/// type.
+///
+/// For a compressed chapter, the span and checksum refer to the
+/// compressed bytes stored in the file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TocEntryV8 {
+    pub id: Box<[u8]>,
+    pub name: Option<String>,
+    pub span: Option<FileSpanV1>,
+    pub checksum: Option<Checksum>,
+    pub encryption: Option<EncryptionV1>,
+    pub codec: Option<Codec>,
+    pub content_type: Option<u32>,
+}
+
+/// A Table-of-contents entry.
+///
+/// This contains an identifying number, an optional human-readable
+/// name, a file span that tells us what chunk of the file contains
+/// this chapter, an optional checksum of the chapter contents, the
+/// encryption parameters if the chapter is encrypted, the codec used
+/// if the chapter is compressed, an optional user-defined content
+/// type, and whether the chapter has been removed.
+///
+/// For a compressed chapter, the span and checksum refer to the
+/// compressed bytes stored in the file. A removed chapter keeps its
+/// id and its place in the TOC, but has no span, and lookups skip it;
+/// see [`BookEditor::remove_chapter`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TocEntryV9 {
+    pub id: Box<[u8]>,
+    pub name: Option<String>,
+    pub span: Option<FileSpanV1>,
+    pub checksum: Option<Checksum>,
+    pub encryption: Option<EncryptionV1>,
+    pub codec: Option<Codec>,
+    pub content_type: Option<u32>,
+    pub removed: bool,
+}
+
 // A type alias, to make code a little easier to read.
-type TocEntry = TocEntryV2;
+type TocEntry = TocEntryV9;
 
 /// A Table-of-contents.
 ///
@@ -92,9 +537,51 @@ struct TocV1(Vec<TocEntryV1>);
 /// A Table-of-contents.
 ///
 /// This contains multiple `TocEntry` values, one for each chapter.
-#[derive(Debug, Default, Serialize, Deserialize, Versioned, UpgradeLatest)]
+#[derive(Debug, Default, Serialize, Deserialize, Versioned)]
 pub struct TocV2(Vec<TocEntryV2>);
 
+/// A Table-of-contents.
+///
+/// This contains multiple `TocEntry` values, one for each chapter.
+#[derive(Debug, Default, Serialize, Deserialize, Versioned)]
+pub struct TocV3(Vec<TocEntryV3>);
+
+/// A Table-of-contents.
+///
+/// This contains multiple `TocEntry` values, one for each chapter.
+#[derive(Debug, Default, Serialize, Deserialize, Versioned)]
+pub struct TocV4(Vec<TocEntryV4>);
+
+/// A Table-of-contents.
+///
+/// This contains multiple `TocEntry` values, one for each chapter.
+#[derive(Debug, Default, Serialize, Deserialize, Versioned)]
+pub struct TocV5(Vec<TocEntryV5>);
+
+/// A Table-of-contents.
+///
+/// This contains multiple `TocEntry` values, one for each chapter.
+#[derive(Debug, Default, Serialize, Deserialize, Versioned)]
+pub struct TocV6(Vec<TocEntryV6>);
+
+/// A Table-of-contents.
+///
+/// This contains multiple `TocEntry` values, one for each chapter.
+#[derive(Debug, Default, Serialize, Deserialize, Versioned)]
+pub struct TocV7(Vec<TocEntryV7>);
+
+/// A Table-of-contents.
+///
+/// This contains multiple `TocEntry` values, one for each chapter.
+#[derive(Debug, Default, Serialize, Deserialize, Versioned)]
+pub struct TocV8(Vec<TocEntryV8>);
+
+/// A Table-of-contents.
+///
+/// This contains multiple `TocEntry` values, one for each chapter.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Versioned, UpgradeLatest)]
+pub struct TocV9(Vec<TocEntryV9>);
+
 impl FromVersion<TocV1> for TocV2 {
     fn from_version(v1: TocV1) -> Self {
         let entries =
@@ -108,90 +595,414 @@ impl FromVersion<TocV1> for TocV2 {
     }
 }
 
-// A type alias, used by the Versioned trait.
-type Toc = TocV2;
-
-impl Toc {
-    fn add(&mut self, entry: TocEntry) {
-        self.0.push(entry);
+impl FromVersion<TocV2> for TocV3 {
+    fn from_version(v2: TocV2) -> Self {
+        let entries =
+            v2.0.into_iter()
+                .map(|v2_entry| TocEntryV3 {
+                    id: v2_entry.id,
+                    span: v2_entry.span,
+                    crc32: None,
+                })
+                .collect();
+        TocV3(entries)
     }
+}
 
-    fn iter(&self) -> impl Iterator<Item = &TocEntry> {
-        self.0.iter()
+impl FromVersion<TocV3> for TocV4 {
+    fn from_version(v3: TocV3) -> Self {
+        let entries =
+            v3.0.into_iter()
+                .map(|v3_entry| TocEntryV4 {
+                    id: v3_entry.id,
+                    name: None,
+                    span: v3_entry.span,
+                    crc32: v3_entry.crc32,
+                })
+                .collect();
+        TocV4(entries)
     }
+}
 
-    fn get_chapter<Id>(&self, id: Id) -> Result<&TocEntry>
-    where
-        Id: Into<ChapterId>,
-    {
-        let id: ChapterId = id.into();
-        let entry = self.iter().find(|entry| entry.id == id.0);
-        entry.ok_or(BookError::NoChapter)
+impl FromVersion<TocV4> for TocV5 {
+    fn from_version(v4: TocV4) -> Self {
+        let entries =
+            v4.0.into_iter()
+                .map(|v4_entry| TocEntryV5 {
+                    id: v4_entry.id,
+                    name: v4_entry.name,
+                    span: v4_entry.span,
+                    checksum: v4_entry.crc32.map(Checksum::Crc32),
+                })
+                .collect();
+        TocV5(entries)
     }
 }
 
-assign_message_ids! {
-    FileHeader: 1,
-    Toc: 2,
+impl FromVersion<TocV5> for TocV6 {
+    fn from_version(v5: TocV5) -> Self {
+        let entries =
+            v5.0.into_iter()
+                .map(|v5_entry| TocEntryV6 {
+                    id: v5_entry.id,
+                    name: v5_entry.name,
+                    span: v5_entry.span,
+                    checksum: v5_entry.checksum,
+                    encryption: None,
+                })
+                .collect();
+        TocV6(entries)
+    }
 }
 
-/// A chapter identifier.
-///
-/// This is internally a byte array. Any type may be used as a chapter
-/// identifier, as long as it implements `Into<ChapterId>`.
-pub struct ChapterId(pub Box<[u8]>);
-
-impl From<&[u8]> for ChapterId {
-    fn from(slice: &[u8]) -> Self {
-        Self(slice.into())
+impl FromVersion<TocV6> for TocV7 {
+    fn from_version(v6: TocV6) -> Self {
+        let entries =
+            v6.0.into_iter()
+                .map(|v6_entry| TocEntryV7 {
+                    id: v6_entry.id,
+                    name: v6_entry.name,
+                    span: v6_entry.span,
+                    checksum: v6_entry.checksum,
+                    encryption: v6_entry.encryption,
+                    codec: None,
+                })
+                .collect();
+        TocV7(entries)
     }
 }
 
-impl From<Box<[u8]>> for ChapterId {
-    fn from(boxed: Box<[u8]>) -> Self {
-        Self(boxed)
+impl FromVersion<TocV7> for TocV8 {
+    fn from_version(v7: TocV7) -> Self {
+        let entries =
+            v7.0.into_iter()
+                .map(|v7_entry| TocEntryV8 {
+                    id: v7_entry.id,
+                    name: v7_entry.name,
+                    span: v7_entry.span,
+                    checksum: v7_entry.checksum,
+                    encryption: v7_entry.encryption,
+                    codec: v7_entry.codec,
+                    content_type: None,
+                })
+                .collect();
+        TocV8(entries)
     }
 }
 
-impl From<&str> for ChapterId {
-    fn from(s: &str) -> Self {
-        String::from(s).into()
+impl FromVersion<TocV8> for TocV9 {
+    fn from_version(v8: TocV8) -> Self {
+        let entries =
+            v8.0.into_iter()
+                .map(|v8_entry| TocEntryV9 {
+                    id: v8_entry.id,
+                    name: v8_entry.name,
+                    span: v8_entry.span,
+                    checksum: v8_entry.checksum,
+                    encryption: v8_entry.encryption,
+                    codec: v8_entry.codec,
+                    content_type: v8_entry.content_type,
+                    removed: false,
+                })
+                .collect();
+        TocV9(entries)
     }
 }
 
-impl From<String> for ChapterId {
-    fn from(s: String) -> Self {
-        Self(s.into_bytes().into_boxed_slice())
+/// A type alias; this will always point to the latest version `Toc`.
+pub type Toc = TocV9;
+
+impl Toc {
+    /// Serialize the table of contents.
+    ///
+    /// This produces the same bytes that are stored in a `Book` file
+    /// (not including the TOC length that follows them).
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        self.write_into(&mut buf)?;
+        Ok(buf)
     }
-}
 
-impl From<u64> for ChapterId {
-    fn from(n: u64) -> Self {
-        Self(Box::new(n.to_be_bytes()))
+    /// Serialize the table of contents, appending to `buf`.
+    fn write_into(&self, buf: &mut Vec<u8>) -> Result<()> {
+        let mut toc_writer = CborData::new(buf);
+        toc_writer.write_message(self)?;
+        Ok(())
     }
-}
 
-/// A tool for writing a `Chapter`.
-///
-/// A `ChapterWriter` creates a new chapter. Chapters will be written
-/// sequentially in the file, and only one can be active at a time.
-///
-/// To write the chapter data, use the [`Write`] interface.
-///
+    /// Serialize entries as TOC records, appending to `buf`.
+    ///
+    /// The record stream starts with a 4-byte header: a message id that
+    /// distinguishes it from a single-message TOC, and the TOC version
+    /// whose entry type the records hold. Each entry is then stored as a
+    /// 4-byte big-endian length followed by the CBOR-serialized entry.
+    ///
+    /// If `buf` is empty, the stream header is written first; otherwise
+    /// `buf` must already hold a record stream, and the entries starting
+    /// at index `skip` are appended to it.
+    fn write_records(&self, skip: usize, buf: &mut Vec<u8>) -> Result<()> {
+        if buf.is_empty() {
+            buf.write_u16::<BigEndian>(TOC_RECORDS_ID).unwrap();
+            buf.write_u16::<BigEndian>(Self::VER).unwrap();
+        }
+        for entry in self.0.iter().skip(skip) {
+            let len_offset = buf.len();
+            buf.write_u32::<BigEndian>(0).unwrap();
+            serde_cbor::to_writer(&mut *buf, entry)?;
+            let record_len: u32 = (buf.len() - len_offset - 4)
+                .try_into()
+                .map_err(|_| BookError::Serializer)?;
+            buf[len_offset..len_offset + 4].copy_from_slice(&record_len.to_be_bytes());
+        }
+        Ok(())
+    }
+
+    /// Deserialize TOC records, following the record stream header.
+    ///
+    /// Returns the TOC and the version of the entries it was read from.
+    fn read_records(mut buf: &[u8]) -> Result<(Self, u16)> {
+        let version = buf
+            .read_u16::<BigEndian>()
+            .map_err(|_| BookError::CorruptToc)?;
+        // Records were first written with `TocEntryV8`. Older record
+        // versions are parsed as their own entry type and upgraded.
+        let toc = match version {
+            TocV8::VER => Self::from_version(TocV8(Self::read_record_entries(buf)?)),
+            Self::VER => Self(Self::read_record_entries(buf)?),
+            _ => return Err(BookError::CorruptToc),
+        };
+        Ok((toc, version))
+    }
+
+    /// Deserialize the entries of a TOC record stream.
+    ///
+    /// `buf` holds the records that follow the stream header.
+    fn read_record_entries<T: DeserializeOwned>(mut buf: &[u8]) -> Result<Vec<T>> {
+        let mut entries = Vec::new();
+        while !buf.is_empty() {
+            let record_len = buf
+                .read_u32::<BigEndian>()
+                .map_err(|_| BookError::CorruptToc)?;
+            let record_len: usize = record_len.try_into().map_err(|_| BookError::CorruptToc)?;
+            if record_len > buf.len() {
+                return Err(BookError::CorruptToc);
+            }
+            if entries.len() == MAX_CHAPTERS {
+                return Err(BookError::TooManyChapters {
+                    count: MAX_CHAPTERS as u64 + 1,
+                });
+            }
+            let (record, rest) = buf.split_at(record_len);
+            let entry = serde_cbor::from_slice(record).map_err(|_| BookError::CorruptToc)?;
+            entries.push(entry);
+            buf = rest;
+        }
+        Ok(entries)
+    }
+
+    /// Deserialize a table of contents.
+    ///
+    /// This accepts the output of [`to_bytes`], or a TOC extracted
+    /// from a `Book` file, in either the single-message or the record
+    /// format (see [`BookBuilder::record_toc`]).
+    ///
+    /// [`to_bytes`]: Self::to_bytes
+    pub fn from_bytes(buf: &[u8]) -> Result<Self> {
+        let (toc, _) = Self::deserialize_versioned(buf)?;
+        Ok(toc)
+    }
+
+    /// Deserialize a table of contents, also returning its on-disk version.
+    ///
+    /// Both TOC formats start with a big-endian message id, which tells
+    /// them apart.
+    fn deserialize_versioned(mut buf: &[u8]) -> Result<(Self, u16)> {
+        if buf.starts_with(&TOC_RECORDS_ID.to_be_bytes()) {
+            return Self::read_records(&buf[2..]);
+        }
+        // Check the entry count before allocating anything for entries.
+        let count = read_toc_count(buf)?;
+        if count > MAX_CHAPTERS as u64 {
+            return Err(BookError::TooManyChapters { count });
+        }
+        let mut data_src = CborData::new(&mut buf);
+        expect_versioned(&mut data_src).map_err(|e| match BookError::from(e) {
+            BookError::Io(e) => BookError::Io(e),
+            _ => BookError::CorruptToc,
+        })
+    }
+
+    fn add(&mut self, entry: TocEntry) {
+        self.0.push(entry);
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &TocEntry> {
+        self.0.iter()
+    }
+
+    fn get_chapter<Id>(&self, id: Id) -> Result<&TocEntry>
+    where
+        Id: Into<ChapterId>,
+    {
+        let id: ChapterId = id.into();
+        let entry = self.live().find(|(_, entry)| entry.id == id.0);
+        entry.map(|(_, entry)| entry).ok_or(BookError::NoChapter)
+    }
+
+    /// Return the entry at `index`, even if the chapter was removed.
+    fn get_entry(&self, index: ChapterIndex) -> Result<&TocEntry> {
+        self.0.get(index.0).ok_or(BookError::IndexOutOfRange {
+            index: index.0,
+            len: self.0.len(),
+        })
+    }
+
+    pub(crate) fn get_index(&self, index: ChapterIndex) -> Result<&TocEntry> {
+        let entry = self.get_entry(index)?;
+        if entry.removed {
+            return Err(BookError::ChapterRemoved { index: index.0 });
+        }
+        Ok(entry)
+    }
+
+    /// Iterate over the chapters that haven't been removed, with their
+    /// indices.
+    fn live(&self) -> impl Iterator<Item = (usize, &TocEntry)> {
+        self.iter().enumerate().filter(|(_, entry)| !entry.removed)
+    }
+
+    /// Find the index of the first chapter with a particular name.
+    fn find_name(&self, name: &str) -> Option<ChapterIndex> {
+        self.live()
+            .find(|(_, entry)| entry.name.as_deref() == Some(name))
+            .map(|(index, _)| ChapterIndex(index))
+    }
+
+    /// Find the index of the first chapter matching `id`, assuming the
+    /// entries are sorted by id.
+    fn find_sorted(&self, id: &[u8]) -> Option<ChapterIndex> {
+        let start = self.0.partition_point(|entry| *entry.id < *id);
+        self.0[start..]
+            .iter()
+            .take_while(|entry| *entry.id == *id)
+            .position(|entry| !entry.removed)
+            .map(|offset| ChapterIndex(start + offset))
+    }
+
+    /// Find the index of every chapter matching `id`, in file order.
+    fn find_all<'a>(&'a self, id: &'a [u8]) -> impl Iterator<Item = ChapterIndex> + 'a {
+        self.live()
+            .filter(move |(_, entry)| *entry.id == *id)
+            .map(|(index, _)| ChapterIndex(index))
+    }
+}
+
+impl TocEntry {
+    /// Verify chapter contents against the stored checksum.
+    ///
+    /// If no checksum was stored, or the algorithm used to compute it
+    /// isn't enabled, this always succeeds.
+    fn verify_checksum(&self, buf: &[u8]) -> Result<()> {
+        let expected = match &self.checksum {
+            None => return Ok(()),
+            Some(expected) => expected,
+        };
+        match expected.recompute(buf) {
+            Some(actual) if actual != *expected => Err(BookError::ChecksumMismatch {
+                id: self.id.clone(),
+                expected: expected.value(),
+                actual: actual.value(),
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+assign_message_ids! {
+    FileHeader: 1,
+    Toc: 2,
+}
+
+/// The chapter id reserved for a book's manifest.
+///
+/// The manifest is an ordinary chapter holding application-level metadata,
+/// such as an index mapping application keys to chapter ids. Using a
+/// well-known id lets tools agree on where to find it. See
+/// [`BookWriter::set_manifest`] and [`Book::manifest`].
+pub const MANIFEST_CHAPTER_ID: u64 = u64::MAX;
+
+/// A chapter identifier.
+///
+/// This is internally a byte array. Any type may be used as a chapter
+/// identifier, as long as it implements `Into<ChapterId>`.
+pub struct ChapterId(pub Box<[u8]>);
+
+impl From<&[u8]> for ChapterId {
+    fn from(slice: &[u8]) -> Self {
+        Self(slice.into())
+    }
+}
+
+impl From<Box<[u8]>> for ChapterId {
+    fn from(boxed: Box<[u8]>) -> Self {
+        Self(boxed)
+    }
+}
+
+impl From<&str> for ChapterId {
+    fn from(s: &str) -> Self {
+        String::from(s).into()
+    }
+}
+
+impl From<String> for ChapterId {
+    fn from(s: String) -> Self {
+        Self(s.into_bytes().into_boxed_slice())
+    }
+}
+
+impl From<u64> for ChapterId {
+    fn from(n: u64) -> Self {
+        Self(Box::new(n.to_be_bytes()))
+    }
+}
+
+/// The position of a chapter within a `Book`'s table of contents.
+///
+/// Chapters are numbered in the order they were written, starting at 0.
+/// Unlike a [`ChapterId`], a `ChapterIndex` always refers to exactly one
+/// chapter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ChapterIndex(pub usize);
+
+/// A tool for writing a `Chapter`.
+///
+/// A `ChapterWriter` creates a new chapter. Chapters will be written
+/// sequentially in the file, and only one can be active at a time.
+///
+/// To write the chapter data, use the [`Write`] interface.
+///
 /// When the chapter is complete, call [`close()`] to flush any
 /// remaining bytes and update the `Book` table-of-contents.
 ///
 /// Attempting to drop a chapter without calling `close` will
-/// cause a panic.
+/// cause a panic, unless this has been disabled with
+/// [`BookWriter::set_strict_drop`].
 ///
 /// See [`BookWriter`] for more information.
 ///
 /// [`close()`]: Self::close
-pub struct ChapterWriter<W> {
+pub struct ChapterWriter<W: Write> {
     book: Option<BookWriter<W>>,
     id: Box<[u8]>,
-    offset: usize,
-    length: usize,
+    name: Option<String>,
+    offset: u64,
+    hasher: ChapterHasher,
+    encryption: Option<EncryptionV1>,
+    codec: Option<Codec>,
+    content_type: Option<u32>,
+    /// The space reserved by [`BookWriter::reserve_chapter`], if any.
+    reserved_len: Option<u64>,
 }
 
 impl<W> ChapterWriter<W>
@@ -199,18 +1010,74 @@ where
     W: Write,
 {
     /// Create a new `ChapterWriter`.
-    fn new<Id>(book: BookWriter<W>, id: Id) -> Self
+    fn new<Id>(book: BookWriter<W>, id: Id, name: Option<String>) -> Self
     where
         Id: Into<ChapterId>,
     {
         let id: ChapterId = id.into();
-        let offset = book.current_offset;
+        let offset = book.current_offset();
+        let hasher = ChapterHasher::new(book.checksum_kind);
         ChapterWriter {
             book: Some(book),
             id: id.0,
+            name,
             offset,
-            length: 0,
+            hasher,
+            encryption: None,
+            codec: None,
+            content_type: None,
+            reserved_len: None,
+        }
+    }
+
+    /// Fail if writing `len` more bytes would overflow the reserved space.
+    fn check_reserved(&self, len: usize) -> io::Result<()> {
+        if let Some(reserved_len) = self.reserved_len {
+            let fits = self
+                .current_len()
+                .checked_add(len as u64)
+                .filter(|&end| end <= reserved_len)
+                .is_some();
+            if !fits {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "write exceeds reserved chapter length",
+                ));
+            }
         }
+        Ok(())
+    }
+
+    /// Return the chapter id.
+    #[cfg(feature = "encryption")]
+    pub(crate) fn id(&self) -> &[u8] {
+        &self.id
+    }
+
+    /// Record that this chapter is compressed.
+    #[cfg(feature = "gzip")]
+    pub(crate) fn set_codec(&mut self, codec: Codec) {
+        self.codec = Some(codec);
+    }
+
+    /// Record that this chapter is encrypted.
+    #[cfg(feature = "encryption")]
+    pub(crate) fn set_encryption(&mut self, encryption: EncryptionV1) {
+        self.encryption = Some(encryption);
+    }
+
+    /// Return the file offset where this chapter starts.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Return the number of bytes written to this chapter so far.
+    pub fn current_len(&self) -> u64 {
+        // It should never be possible to panic here, because self.book
+        // is set to Some during construction, and it's not possible to
+        // reach the ChapterWriter after close().
+        let book = self.book.as_ref().unwrap();
+        book.current_offset() - self.offset
     }
 
     /// Complete the chapter.
@@ -220,12 +1087,38 @@ where
     /// `Chapter` instances should not be dropped; they must be consumed
     /// by calling `close`. This allows us to detect any final IO errors
     /// and update the TOC.
-    pub fn close(mut self) -> Result<BookWriter<W>> {
+    pub fn close(self) -> Result<BookWriter<W>> {
+        let (book, _) = self.close_with_span()?;
+        Ok(book)
+    }
+
+    /// Complete the chapter, returning the span it occupies.
+    ///
+    /// This is the same as [`close()`], but also returns the chapter's
+    /// `(offset, length)` in the file, e.g. for recording in an external
+    /// index. The length may be zero.
+    ///
+    /// [`close()`]: Self::close
+    pub fn close_with_span(mut self) -> Result<(BookWriter<W>, (u64, u64))> {
         self.flush()?;
 
+        let length = self.current_len();
+        if let Some(reserved_len) = self.reserved_len {
+            // Fill the rest of the reserved space. The padding isn't part
+            // of the chapter, so it isn't included in the span or checksum.
+            let book = self.book.as_mut().unwrap();
+            let padding = reserved_len - length;
+            io::copy(&mut io::repeat(0).take(padding), &mut book.writer)?;
+        }
         let toc_entry = TocEntry {
             id: self.id.clone(),
-            span: FileSpan::from_offset_length(self.offset, self.length),
+            name: self.name.take(),
+            span: FileSpan::from_offset_length(self.offset, length),
+            checksum: self.hasher.finish(),
+            encryption: self.encryption.take(),
+            codec: self.codec.take(),
+            content_type: self.content_type,
+            removed: false,
         };
 
         // It should never be possible to panic here, because self.book
@@ -233,24 +1126,31 @@ where
         // reach the ChapterWriter after close().
         let mut book = self.book.take().unwrap();
 
-        book.toc.add(toc_entry);
-        book.current_offset += self.length;
+        book.add_entry(toc_entry)?;
 
-        Ok(book)
+        Ok((book, (self.offset, length)))
     }
 }
 
-impl<W> Drop for ChapterWriter<W> {
+impl<W: Write> Drop for ChapterWriter<W> {
     fn drop(&mut self) {
         // A `Chapter` must not be dropped if it has contents,
         // because we want the owner to call [`close`] and handle
-        // any IO errors.
-        if self.book.is_some() {
+        // any IO errors, unless the BookWriter has been told that's OK.
+        let book = match self.book.take() {
+            Some(book) => book,
+            None => return,
+        };
+        if book.strict_drop {
             // We don't want to panic if the Chapter is being dropped
             // while unwinding.
             if !panicking() {
                 panic!("ChapterWriter was dropped without calling close()");
             }
+        } else {
+            // Finish the book without this chapter. There's nowhere to
+            // report an error.
+            let _ = book.close();
         }
     }
 }
@@ -260,12 +1160,35 @@ where
     W: Write,
 {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.check_reserved(buf.len())?;
         // It should never be possible to panic here, because self.book
         // is set to Some during construction, and it's not possible to
         // reach the ChapterWriter after close().
         let book = self.book.as_mut().unwrap();
         let bytes_written = book.writer.write(buf)?;
-        self.length += bytes_written;
+        self.hasher.update(&buf[..bytes_written]);
+        Ok(bytes_written)
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        self.check_reserved(bufs.iter().map(|buf| buf.len()).sum())?;
+        // It should never be possible to panic here, because self.book
+        // is set to Some during construction, and it's not possible to
+        // reach the ChapterWriter after close().
+        let book = self.book.as_mut().unwrap();
+        let bytes_written = book.writer.write_vectored(bufs)?;
+
+        // Only the first `bytes_written` bytes, which may end partway
+        // through a buffer, were written.
+        let mut remaining = bytes_written;
+        for buf in bufs {
+            let len = buf.len().min(remaining);
+            self.hasher.update(&buf[..len]);
+            remaining -= len;
+            if remaining == 0 {
+                break;
+            }
+        }
         Ok(bytes_written)
     }
 
@@ -288,15 +1211,65 @@ where
 /// When the book is complete, call [`close()`] to flush any
 /// remaining bytes and write out the table of contents.
 ///
+/// # Durability
+///
+/// `BookWriter` doesn't ask the OS to persist anything on its own.
+/// [`flush_chapter_boundary()`] and [`sync_all()`] can be used between
+/// chapters to push completed chapters towards stable storage, but the
+/// table of contents is only written by [`close()`]; until then, the
+/// file can't be opened as a `Book`. The book is only durable once
+/// `close()` has returned and the returned stream has been synced, e.g.
+/// with [`File::sync_all`].
+///
 /// [`close()`]: Self::close
 /// [`new_chapter()`]: Self::new_chapter
+/// [`flush_chapter_boundary()`]: Self::flush_chapter_boundary
+/// [`sync_all()`]: Self::sync_all
+/// [`File::sync_all`]: std::fs::File::sync_all
 ///
 #[derive(Debug)]
 pub struct BookWriter<W> {
-    writer: W,
-    current_offset: usize,
+    writer: CountingWriter<W>,
     header: FileHeader,
     toc: Toc,
+    strict_drop: bool,
+    checksum_kind: ChecksumKind,
+    /// The minimum length of the finished file.
+    ///
+    /// When appending, the stream can't be truncated, so the TOC is
+    /// padded to at least cover the original file contents.
+    min_length: u64,
+    /// A reusable buffer for serializing values and the TOC.
+    scratch: Vec<u8>,
+    /// Chapters start at a multiple of this many bytes.
+    chapter_alignment: u64,
+    /// The size of the buffer used when copying chapter data.
+    io_buffer_size: usize,
+    /// The manifest, to be written as the last chapter.
+    manifest: Option<Vec<u8>>,
+    /// TOC records already serialized, when the TOC is stored as records.
+    ///
+    /// These hold the first `toc_records_count` entries of `toc`, so
+    /// only later entries need to be serialized when the TOC is written.
+    toc_records: Vec<u8>,
+    toc_records_count: usize,
+    /// Spans of chapters already written, if deduplication is enabled.
+    dedup: Option<HashMap<DedupKey, FileSpan>>,
+}
+
+/// Identifies chapter contents for deduplication: the length, CRC-32,
+/// and SipHash of the bytes.
+///
+/// The written bytes can't be read back to confirm a match, so two
+/// independent hashes are used, making a false match (about one in
+/// 2^96 per pair of chapters of the same length) negligible.
+type DedupKey = (u64, u32, u64);
+
+/// Compute the deduplication key for a chapter's contents.
+fn dedup_key(data: &[u8]) -> DedupKey {
+    let mut hasher = DefaultHasher::new();
+    hasher.write(data);
+    (data.len() as u64, Crc32::checksum(data), hasher.finish())
 }
 
 impl<W: Write> BookWriter<W> {
@@ -307,40 +1280,126 @@ impl<W: Write> BookWriter<W> {
     /// disambiguate different kinds of files.
     ///
     pub fn new(writer: W, user_magic: u32) -> Result<Self> {
+        Self::with_header(writer, FileHeader::new(user_magic))
+    }
+
+    /// Create a new `BookWriter`, storing user metadata in the header.
+    ///
+    /// This is the same as [`new`](Self::new), but `meta` is also stored
+    /// in the file header, and can be retrieved with [`Book::user_meta`].
+    /// It might contain a schema version, a timestamp, or anything else
+    /// that describes the file's contents.
+    ///
+    /// The metadata must fit in the fixed-size header block; if it doesn't,
+    /// [`BookError::HeaderTooLarge`] is returned.
+    pub fn new_with_meta(writer: W, user_magic: u32, meta: &[u8]) -> Result<Self> {
+        let mut header = FileHeader::new(user_magic);
+        header.user_meta = meta.to_vec();
+        Self::with_header(writer, header)
+    }
+
+    /// Create a new `BookWriter` and write the file header.
+    fn with_header(writer: W, header: FileHeader) -> Result<Self> {
         let mut this = BookWriter {
-            writer,
-            current_offset: 0,
-            header: FileHeader {
-                bookwriter_magic: BOOK_V1_MAGIC,
-                user_magic,
-            },
+            writer: CountingWriter::new(writer),
+            header,
             toc: Toc::default(),
+            strict_drop: true,
+            checksum_kind: ChecksumKind::default(),
+            min_length: 0,
+            scratch: Vec::new(),
+            chapter_alignment: 1,
+            io_buffer_size: COPY_BUFFER_SIZE,
+            manifest: None,
+            toc_records: Vec::new(),
+            toc_records_count: 0,
+            dedup: None,
         };
-        this.write_header()?;
+        this.write_header().context("writing header")?;
         Ok(this)
     }
 
+    /// Control what happens when a `ChapterWriter` is dropped without
+    /// calling [`ChapterWriter::close`].
+    ///
+    /// By default (`strict == true`) this causes a panic, because it's
+    /// almost certainly a bug. If `strict` is `false`, the chapter is
+    /// abandoned instead.
+    ///
+    /// Because a `ChapterWriter` owns the `BookWriter`, the book can't be
+    /// used after a chapter is abandoned, so it's finished as if by
+    /// [`close()`]. The TOC has no entry for the abandoned chapter; any
+    /// bytes already written to it are left in the file, unreferenced.
+    /// Nothing is logged, and if closing the book fails, the error is
+    /// ignored. Call [`ChapterWriter::close`] to handle errors.
+    ///
+    /// [`close()`]: Self::close
+    pub fn set_strict_drop(&mut self, strict: bool) {
+        self.strict_drop = strict;
+    }
+
+    /// Return the user-defined magic number this book was created with.
+    pub fn user_magic(&self) -> u32 {
+        self.header.user_magic
+    }
+
+    /// Select the checksum algorithm for new chapters.
+    ///
+    /// The algorithm is chosen when each chapter is started, and is
+    /// recorded in the table of contents, so a single book may contain
+    /// chapters with different kinds of checksum. The default is
+    /// [`ChecksumKind::None`], so that books are written exactly as they
+    /// were before checksums existed.
+    pub fn set_checksum_kind(&mut self, kind: ChecksumKind) {
+        self.checksum_kind = kind;
+    }
+
     fn write_header(&mut self) -> Result<()> {
-        // Serialize the header into a buffer.
-        let header_buf = Cursor::new(Vec::<u8>::new());
-        let mut header_writer = CborData::new(header_buf);
-        header_writer.write_message(&self.header)?;
+        // The header size was checked when the header was created.
+        let header_size = self.header.header_size.try_into().unwrap();
+        let header_buf = serialize_header(&self.header, header_size)?;
 
-        let mut header_buf = header_writer.into_inner().into_inner();
-        if header_buf.len() > HEADER_SIZE {
-            panic!("serialized header exceeds maximum size");
+        self.writer.write_all(&header_buf)?;
+        if self.header.flags & FLAG_FRONT_TOC != 0 {
+            // Reserve space for the TOC; it will be filled in later.
+            let region_size = self.header.front_toc_size;
+            io::copy(&mut io::repeat(0).take(region_size), &mut self.writer)?;
         }
-        // Pad the buffer with zeroes so that it's the expected
-        // size.
-        header_buf.resize(HEADER_SIZE, 0);
+        Ok(())
+    }
 
-        // FIXME: wrap the writer in some struct that automatically counts
-        // the number of bytes written.
-        self.writer.write_all(&header_buf)?;
-        self.current_offset = HEADER_SIZE;
+    /// Flush all completed chapters to the underlying stream.
+    ///
+    /// This doesn't ask the OS to persist the data; see [`sync_all()`].
+    ///
+    /// [`sync_all()`]: Self::sync_all
+    pub fn flush_chapter_boundary(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    /// Flush all completed chapters, and ask the OS to persist them.
+    ///
+    /// For a `File`, this calls [`File::sync_all`]. The table of contents
+    /// isn't written until [`close()`], so this alone doesn't make the
+    /// book readable after a crash.
+    ///
+    /// [`File::sync_all`]: std::fs::File::sync_all
+    /// [`close()`]: Self::close
+    pub fn sync_all(&mut self) -> Result<()>
+    where
+        W: Durable,
+    {
+        self.writer.flush()?;
+        self.writer.get_mut().sync()?;
         Ok(())
     }
 
+    /// Return the offset where the next byte will be written.
+    fn current_offset(&self) -> u64 {
+        self.writer.count()
+    }
+
     /// Create a new `ChapterWriter`.
     ///
     /// The chapter `id` can be any value the user wants, and can be
@@ -351,337 +1410,5416 @@ impl<W: Write> BookWriter<W> {
     where
         Id: Into<ChapterId>,
     {
-        ChapterWriter::new(self, id)
+        ChapterWriter::new(self, id, None)
     }
 
-    /// Finish writing the `Book` file.
+    /// Create a new `ChapterWriter` for a named chapter.
     ///
-    /// On success, this returns the original writer stream.
-    /// It is normal to discard it, except in unit tests.
-    pub fn close(mut self) -> Result<W> {
-        // Serialize the TOC into a buffer.
-        let toc_buf = Cursor::new(Vec::<u8>::new());
-        let mut toc_writer = CborData::new(toc_buf);
-        toc_writer.write_message(&self.toc)?;
-        let mut toc_buf = toc_writer.into_inner().into_inner();
-
-        // Manually serialize the TOC length, so that it has a fixed size and
-        // a fixed offset (relative to the end of the file).
-        let toc_length = toc_buf.len() as u64;
-        toc_buf.write_u64::<BigEndian>(toc_length).unwrap();
-
-        // Write the TOC.
-        self.writer.write_all(&toc_buf)?;
-
-        // TODO: Add a checksum.
-
-        self.writer.flush()?;
-        Ok(self.writer)
+    /// This is the same as [`new_chapter()`], but also stores a
+    /// human-readable name for the chapter. Chapters may later be
+    /// located by name using [`Book::find_chapter_by_name`].
+    ///
+    /// [`new_chapter()`]: Self::new_chapter
+    pub fn new_chapter_named<Id>(self, id: Id, name: &str) -> ChapterWriter<W>
+    where
+        Id: Into<ChapterId>,
+    {
+        ChapterWriter::new(self, id, Some(name.to_owned()))
     }
-}
-
-/// An interface for reading a Bookfile.
-///
-/// The `Book` type represents a read-only Bookfile. Invividual chapters can
-/// be read in their entirety, or incrementally using the [`Read`] interface.
-/// [`Seek`] and [`read_at`]/[`read_exact_at`]
-/// are also provided, and work within the context of that chapter: the seek
-/// offset is the offset within the chapter, and a read at the end of the
-/// chapter will return EOF.
-///
-/// [`Read`]: std::io::Read
-/// [`Seek`]: std::io::Seek
-/// [`read_at`]: crate::BoundedReader::read_at
-/// [`read_exact_at`]: crate::BoundedReader::read_exact_at
-
-#[derive(Debug)]
-pub struct Book<R> {
-    reader: R,
-    header: FileHeader,
-    toc: Toc,
-}
 
-impl<R> Book<R> {
-    /// Return the file's magic number.
+    /// Create a new `ChapterWriter` with a content type.
     ///
-    /// Each BookWriter specifies a magic number, used to identify this file format.
-    /// This method returns the value found in the file.
-    pub fn magic(&self) -> u32 {
-        self.header.user_magic
+    /// This is the same as [`new_chapter()`], but also stores a
+    /// user-defined content type, which can be read back with
+    /// [`Book::chapter_content_type`]. It might identify the format of
+    /// the chapter's payload, so that a reader knows how to decode it.
+    ///
+    /// [`new_chapter()`]: Self::new_chapter
+    pub fn new_chapter_typed<Id>(self, id: Id, content_type: u32) -> ChapterWriter<W>
+    where
+        Id: Into<ChapterId>,
+    {
+        let mut chapter = ChapterWriter::new(self, id, None);
+        chapter.content_type = Some(content_type);
+        chapter
     }
-}
 
-#[cfg(target_family = "unix")]
-impl<R> Book<R>
-where
-    R: std::os::unix::fs::FileExt,
-{
-    /// Create a shared reader object.
-    ///
-    /// This returns a BoundedReader that does not implement `Seek` or `Read`, only
-    /// [`read_at`] and [`read_exact_at`]
-    /// This means it can be used via a shared reference.
+    /// Create a new `ChapterWriter` occupying exactly `max_len` bytes.
     ///
-    /// If the `Seek` and `Read` traits are required, use [`exclusive_chapter_reader`] instead.
+    /// This is the same as [`new_chapter()`], but a write that would take
+    /// the chapter past `max_len` bytes fails without writing anything.
+    /// When the chapter is closed, the unused part of the reservation is
+    /// filled with zero bytes, so the next chapter starts `max_len` bytes
+    /// after this one (plus any alignment padding). The span recorded in
+    /// the TOC covers only the bytes actually written.
     ///
-    /// [`read_at`]: crate::BoundedReader::read_at
-    /// [`read_exact_at`]: crate::BoundedReader::read_exact_at
-    /// [`exclusive_chapter_reader`]: Self::exclusive_chapter_reader
-    ///
-    pub fn chapter_reader<Id>(&self, index: Id) -> Result<BoundedReader<&R>>
+    /// [`new_chapter()`]: Self::new_chapter
+    pub fn reserve_chapter<Id>(self, id: Id, max_len: u64) -> ChapterWriter<W>
     where
         Id: Into<ChapterId>,
     {
-        let toc_entry = self.toc.get_chapter(index)?;
-        match &toc_entry.span {
-            None => {
-                // If the span is empty, no IO is necessary; just return
-                // an empty Vec.
-                Ok(BoundedReader::empty(&self.reader))
-            }
-            Some(span) => Ok(BoundedReader::new(
-                &self.reader,
-                span.offset,
-                span.length.into(),
-            )),
-        }
+        let mut chapter = ChapterWriter::new(self, id, None);
+        chapter.reserved_len = Some(max_len);
+        chapter
     }
 
-    /// Read all bytes in a chapter.
+    /// Create a new `CompressedChapterWriter`.
     ///
-    /// This is the same thing as calling [`chapter_reader`] followed by
-    /// [`read_exact_at`].
+    /// Bytes written to the chapter are compressed before being stored.
+    /// The chapter can be read back using [`Book::decoded_chapter_reader`].
+    /// The span and checksum stored in the TOC refer to the compressed
+    /// bytes.
+    #[cfg(feature = "gzip")]
+    pub fn new_chapter_compressed<Id>(
+        self,
+        id: Id,
+        compression: Compression,
+    ) -> CompressedChapterWriter<W>
+    where
+        Id: Into<ChapterId>,
+    {
+        CompressedChapterWriter::new(ChapterWriter::new(self, id, None), compression)
+    }
+
+    /// Create a new `EncryptedChapterWriter`.
     ///
-    /// [`chapter_reader`]: Self::chapter_reader
-    /// [`read_exact_at`]: crate::BoundedReader::read_exact_at
-    pub fn read_chapter<Id>(&self, index: Id) -> Result<Box<[u8]>>
+    /// The chapter contents will be encrypted with AES-256-GCM using `key`.
+    /// The chapter can be read back using [`Book::chapter_reader_decrypted`].
+    /// The span and checksum stored in the TOC refer to the ciphertext.
+    #[cfg(feature = "encryption")]
+    pub fn new_chapter_encrypted<Id>(self, id: Id, key: &[u8; 32]) -> EncryptedChapterWriter<W>
     where
         Id: Into<ChapterId>,
     {
-        let reader = self.chapter_reader(index)?;
-        let chapter_len: usize = reader.len().try_into().unwrap();
-        let mut buf = vec![0u8; chapter_len];
-        reader.read_exact_at(&mut buf, 0)?;
-        Ok(buf.into_boxed_slice())
+        EncryptedChapterWriter::new(ChapterWriter::new(self, id, None), key)
     }
-}
 
-impl<R> Book<R>
-where
-    R: Read + Seek,
-{
-    /// Create a new Book from a stream.
+    /// Write a complete chapter.
     ///
-    /// This call will attempt to read the file header and table of contents.
-    /// It may fail due to IO errors while reading, or invalid file data.
+    /// This is the same thing as calling [`new_chapter()`], writing all of
+    /// `data`, and then calling [`ChapterWriter::close()`], but is more
+    /// convenient when the chapter contents are already in memory.
     ///
-    /// The stream must impl the `Read` and `Seek` traits (e.g. a `File`).
+    /// If deduplication is enabled (see [`BookBuilder::dedup`]) and `data`
+    /// is identical to an earlier chapter written by this method, the
+    /// bytes aren't written again; the new chapter shares the earlier
+    /// chapter's span.
     ///
-    pub fn new(mut reader: R) -> Result<Self> {
-        // Read the header from the beginning of the file.
-        let mut header_buf = [0u8; HEADER_SIZE];
-        reader.seek(SeekFrom::Start(0))?;
-        reader.read_exact(&mut header_buf)?;
-        let buf_reader = &header_buf[..];
-
-        let mut data_src = CborData::new(buf_reader);
-        let header: FileHeader = data_src.expect_message()?;
-
-        // Verify magic numbers
-        if header.bookwriter_magic != BOOK_V1_MAGIC {
-            return Err(BookError::Serializer);
-        }
+    /// [`new_chapter()`]: Self::new_chapter
+    /// [`ChapterWriter::close()`]: ChapterWriter::close
+    pub fn write_chapter<Id>(&mut self, id: Id, data: &[u8]) -> Result<()>
+    where
+        Id: Into<ChapterId>,
+    {
+        let id: ChapterId = id.into();
+        let mut hasher = ChapterHasher::new(self.checksum_kind);
+        hasher.update(data);
+        let key = match &self.dedup {
+            Some(spans) if !data.is_empty() => {
+                let key = dedup_key(data);
+                if let Some(span) = spans.get(&key) {
+                    let entry = TocEntry {
+                        id: id.0,
+                        name: None,
+                        span: Some(span.clone()),
+                        checksum: hasher.finish(),
+                        encryption: None,
+                        codec: None,
+                        content_type: None,
+                        removed: false,
+                    };
+                    return self.add_entry(entry);
+                }
+                Some(key)
+            }
+            _ => None,
+        };
 
-        // Read the TOC length. For v1 it is the last 8 bytes of the file.
-        let toc_end = reader.seek(SeekFrom::End(-8))?;
-        let toc_len = reader.read_u64::<BigEndian>()?;
-        if toc_len > MAX_TOC_SIZE {
-            return Err(BookError::Serializer);
+        let offset = self.current_offset();
+        self.writer.write_all(data)?;
+        self.writer.flush()?;
+        let length = self.add_chapter(id.0, offset, hasher.finish())?;
+        if let (Some(spans), Some(key)) = (&mut self.dedup, key) {
+            if let Some(span) = FileSpan::from_offset_length(offset, length) {
+                spans.insert(key, span);
+            }
         }
+        Ok(())
+    }
 
-        // Deserialize the TOC.
-        let toc_offset = toc_end - toc_len;
-        let toc_reader = BoundedReader::new(&mut reader, toc_offset, toc_len);
-        let mut data_src = CborData::new(toc_reader);
-        let toc: Toc = data_src.expect_message()?;
-
-        Ok(Book {
-            reader,
-            header,
-            toc,
-        })
+    /// Write a complete chapter from part of a memory-mapped file.
+    ///
+    /// The bytes in `range` are written straight from the mapping, as by
+    /// [`write_chapter()`], without being copied into an intermediate
+    /// buffer. If `range` extends past the end of the mapping,
+    /// [`BookError::OutOfBounds`] is returned and nothing is written.
+    ///
+    /// [`write_chapter()`]: Self::write_chapter
+    #[cfg(feature = "mmap")]
+    pub fn write_chapter_mmap<Id>(&mut self, id: Id, map: &Mmap, range: Range<usize>) -> Result<()>
+    where
+        Id: Into<ChapterId>,
+    {
+        let data = map.get(range.clone()).ok_or(BookError::OutOfBounds {
+            start: range.start as u64,
+            len: range.len() as u64,
+            chapter_len: map.len() as u64,
+        })?;
+        self.write_chapter(id, data)
     }
 
-    /// Check whether a chapter exists.
+    /// Write a complete chapter, copying its contents from a `Read` source.
     ///
-    /// For now, we assume chapter ids are unique. That's dumb,
-    /// and will be fixed in a future version.
-    pub fn has_chapter<Id>(&self, id: Id) -> bool
+    /// The source will be read until EOF, using a fixed-size buffer, so the
+    /// chapter contents never need to be held in memory all at once.
+    /// On success, the number of bytes in the new chapter is returned.
+    ///
+    /// If reading from `src` fails, [`BookError::Source`] will be returned.
+    /// The partial chapter will not be added to the table of contents,
+    /// though any bytes already copied will still occupy space in the file.
+    pub fn write_chapter_from<Id, Rd>(&mut self, id: Id, src: &mut Rd) -> Result<u64>
     where
         Id: Into<ChapterId>,
+        Rd: Read,
     {
-        self.toc.get_chapter(id).is_ok()
+        self.write_chapter_from_with_progress(id, src, &mut |_| {})
     }
 
-    /// Read a chapter, with seeking.
+    /// Write a complete chapter from a `Read` source, reporting progress.
     ///
-    /// This returns a `BoundedReader` that can read and seek within the chapter
-    /// (assuming the underlying Book implements `Read + Seek`).
-    /// The `Seek` and `Read` traits require exclusive access. For a shared reader,
-    /// use [`chapter_reader`] instead.
+    /// This behaves like [`write_chapter_from`], but calls `progress` with
+    /// the cumulative number of bytes copied after each buffer is written.
+    /// The last call reports the chapter's total length. `progress` is
+    /// never called for an empty read, so an empty chapter produces no
+    /// calls at all.
     ///
-    /// [`chapter_reader`]: Self::chapter_reader
-    pub fn exclusive_chapter_reader<Id>(&mut self, id: Id) -> Result<BoundedReader<&mut R>>
+    /// [`write_chapter_from`]: Self::write_chapter_from
+    pub fn write_chapter_from_with_progress<Id, Rd>(
+        &mut self,
+        id: Id,
+        src: &mut Rd,
+        progress: &mut dyn FnMut(u64),
+    ) -> Result<u64>
     where
         Id: Into<ChapterId>,
+        Rd: Read,
     {
-        let toc_entry = self.toc.get_chapter(id)?;
-        match &toc_entry.span {
-            None => {
-                // If the span is empty, no IO is necessary; just return
-                // an empty Vec.
-                Ok(BoundedReader::empty(&mut self.reader))
-            }
-            Some(span) => {
-                self.reader.seek(SeekFrom::Start(span.offset))?;
-                Ok(BoundedReader::new(
-                    &mut self.reader,
-                    span.offset,
-                    span.length.into(),
-                ))
-            }
+        let id: ChapterId = id.into();
+        let offset = self.current_offset();
+        let mut buf = vec![0u8; self.io_buffer_size];
+        let mut hasher = ChapterHasher::new(self.checksum_kind);
+        let mut copied = 0u64;
+        loop {
+            let bytes_read = match src.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(BookError::Source(e)),
+            };
+            let data = &buf[..bytes_read];
+            self.writer.write_all(data)?;
+            hasher.update(data);
+            copied += bytes_read as u64;
+            progress(copied);
         }
+        self.writer.flush()?;
+        let length = self.add_chapter(id.0, offset, hasher.finish())?;
+        Ok(length)
     }
 
-    /// Read all bytes in a chapter.
-    ///
-    /// This is the same thing as calling [`exclusive_chapter_reader`]
-    /// followed by [`read_to_end`]. It can be used on anything that implements
-    /// `Read + Seek`, but as a result it requires exclusive access via a
-    /// mutable reference.
+    /// Write a chapter containing a single serialized value.
     ///
-    /// [`exclusive_chapter_reader`]: Self::exclusive_chapter_reader
-    ///[`read_to_end`]: std::io::Read::read_to_end
+    /// The value is serialized using CBOR, the same format used for the
+    /// file header and table of contents. It can be read back using
+    /// [`Book::read_chapter_value`].
     ///
-    pub fn exclusive_read_chapter<Id>(&mut self, index: Id) -> Result<Box<[u8]>>
+    /// The value is serialized into a buffer that is reused by later
+    /// calls, so writing many small values doesn't allocate each time.
+    pub fn write_chapter_value<Id, T>(&mut self, id: Id, value: &T) -> Result<()>
     where
         Id: Into<ChapterId>,
+        T: Serialize,
     {
-        let mut buf = vec![];
-        let mut reader = self.exclusive_chapter_reader(index)?;
-        reader.read_to_end(&mut buf)?;
-        Ok(buf.into_boxed_slice())
+        let mut buf = self.take_scratch();
+        let result = serde_cbor::to_writer(&mut buf, value)
+            .map_err(BookError::from)
+            .and_then(|()| self.write_chapter(id, &buf));
+        self.scratch = buf;
+        result
     }
-}
 
-#[cfg(test)]
-mod tests {
+    /// Take the scratch buffer, leaving an empty one in its place.
+    ///
+    /// The buffer is always empty, but keeps the capacity from earlier
+    /// uses. It should be returned by assigning it to `self.scratch`.
+    fn take_scratch(&mut self) -> Vec<u8> {
+        let mut buf = std::mem::take(&mut self.scratch);
+        buf.clear();
+        buf
+    }
 
-    use super::*;
-    use std::io::Cursor;
+    /// Copy a chapter from another book.
+    ///
+    /// The chapter's bytes are copied verbatim, without being buffered in
+    /// memory. Its id, name, checksum, and encryption parameters are
+    /// preserved. Checksums aren't verified; use [`Book::verify`] first if
+    /// that's needed. The source book is only read.
+    pub fn copy_chapter_from<R>(&mut self, src: &mut Book<R>, index: ChapterIndex) -> Result<()>
+    where
+        R: Read + Seek,
+    {
+        let src_entry = src.toc.get_index(index)?;
+        let offset = self.current_offset();
+        let mut reader = exclusive_entry_reader(&mut src.reader, src_entry)?;
+        let mut buf = vec![0u8; self.io_buffer_size];
+        let mut copied = 0;
+        loop {
+            let bytes_read = match reader.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            };
+            self.writer.write_all(&buf[..bytes_read])?;
+            copied += bytes_read as u64;
+        }
+        if copied < reader.len() {
+            return Err(BookError::Eof);
+        }
+        self.writer.flush()?;
+        self.add_entry(TocEntry {
+            id: src_entry.id.clone(),
+            name: src_entry.name.clone(),
+            span: FileSpan::from_offset_length(offset, copied),
+            checksum: src_entry.checksum,
+            encryption: src_entry.encryption.clone(),
+            codec: src_entry.codec,
+            content_type: src_entry.content_type,
+            removed: false,
+        })?;
+        Ok(())
+    }
 
-    #[test]
-    fn empty_book() {
-        let magic = 0x1234;
-        let mut cursor = Cursor::new(Vec::<u8>::new());
-        {
-            let book = BookWriter::new(&mut cursor, magic).unwrap();
-            book.close().unwrap();
+    /// Add a chapter that was just written to the TOC.
+    ///
+    /// The chapter extends from `offset` to the current offset.
+    /// Returns the length of the chapter.
+    fn add_chapter(
+        &mut self,
+        id: Box<[u8]>,
+        offset: u64,
+        checksum: Option<Checksum>,
+    ) -> Result<u64> {
+        let length = self.current_offset() - offset;
+        let toc_entry = TocEntry {
+            id,
+            name: None,
+            span: FileSpan::from_offset_length(offset, length),
+            checksum,
+            encryption: None,
+            codec: None,
+            content_type: None,
+            removed: false,
+        };
+        self.add_entry(toc_entry)?;
+        Ok(length)
+    }
+
+    /// Add an entry to the TOC.
+    ///
+    /// The stream is then padded so the next chapter is aligned.
+    fn add_entry(&mut self, entry: TocEntry) -> Result<()> {
+        if self.toc.0.len() >= MAX_CHAPTERS {
+            return Err(BookError::TooManyChapters {
+                count: MAX_CHAPTERS as u64 + 1,
+            });
         }
+        if self.header.flags & FLAG_SORTED_IDS != 0 {
+            let last_id = self.toc.iter().last().map(|last| &last.id);
+            debug_assert!(
+                last_id.filter(|&last_id| *last_id > entry.id).is_none(),
+                "chapter ids must be written in ascending order"
+            );
+        }
+        self.toc.add(entry);
+        self.pad_to_alignment()
+    }
 
-        // This file contains only a header, an empty TOC, and a TOC-length.
-        assert_eq!(cursor.get_ref().len(), 4096 + 9 + 8);
+    /// Write zero bytes until the current offset is a multiple of the
+    /// chapter alignment.
+    fn pad_to_alignment(&mut self) -> Result<()> {
+        let remainder = self.current_offset() % self.chapter_alignment;
+        if remainder != 0 {
+            let padding = self.chapter_alignment - remainder;
+            io::copy(&mut io::repeat(0).take(padding), &mut self.writer)?;
+        }
+        Ok(())
+    }
 
-        // If this succeeds then the header and TOC were parsed correctly.
-        let _ = Book::new(cursor).unwrap();
+    /// Finish writing the `Book` file.
+    ///
+    /// On success, this returns the original writer stream.
+    /// It is normal to discard it, except in unit tests, or to sync it
+    /// to stable storage (see [Durability](Self#durability)).
+    ///
+    /// As a last line of defense against writing a corrupt book, the
+    /// chapter spans are checked first; if any of them overlap,
+    /// [`BookError::SpanOverlap`] is returned and no TOC is written.
+    pub fn close(self) -> Result<W> {
+        let (writer, _) = self.close_with_size()?;
+        Ok(writer)
     }
 
-    #[test]
-    fn truncated_book() {
-        let magic = 0x1234;
-        let mut cursor = Cursor::new(Vec::<u8>::new());
-        {
-            let _book = BookWriter::new(&mut cursor, magic).unwrap();
-            // We drop the BookWriter without calling close().
+    /// Finish writing the `Book` file, returning its total size.
+    ///
+    /// This is the same as [`close()`], but also returns the length of
+    /// the finished book in bytes, e.g. for recording in a catalog.
+    ///
+    /// [`close()`]: Self::close
+    pub fn close_with_size(mut self) -> Result<(W, u64)> {
+        self.write_manifest()?;
+        self.write_toc().context("writing TOC")?;
+        let size = self.current_offset();
+        Ok((self.writer.into_inner(), size))
+    }
+
+    /// Set the book's manifest.
+    ///
+    /// The manifest is stored as an ordinary chapter with id
+    /// [`MANIFEST_CHAPTER_ID`], but it isn't written until the book is
+    /// closed, so it's always the last chapter. Calling this again
+    /// replaces the pending manifest. It can be read back with
+    /// [`Book::manifest`].
+    pub fn set_manifest(&mut self, bytes: &[u8]) {
+        self.manifest = Some(bytes.to_vec());
+    }
+
+    /// Write the pending manifest chapter, if there is one.
+    fn write_manifest(&mut self) -> Result<()> {
+        if let Some(manifest) = self.manifest.take() {
+            self.write_chapter(MANIFEST_CHAPTER_ID, &manifest)?;
         }
+        Ok(())
+    }
 
-        // This file contains only a header (yes, this is invalid).
-        assert_eq!(cursor.get_ref().len(), 4096);
+    /// Write the TOC, TOC checksum, and TOC length at the end of the file.
+    fn write_toc(&mut self) -> Result<()> {
+        // Chapters are always appended, so this should never fail. If it
+        // does, refuse to write a TOC that would describe a corrupt book.
+        check_spans(&self.toc, data_start(&self.header), self.current_offset())?;
+
+        // Serialize the TOC into a buffer.
+        let mut toc_buf = self.take_scratch();
+        if self.header.flags & FLAG_TOC_RECORDS != 0 {
+            // Only entries added since the records were last written need
+            // to be serialized.
+            self.toc
+                .write_records(self.toc_records_count, &mut self.toc_records)?;
+            self.toc_records_count = self.toc.0.len();
+            toc_buf.extend_from_slice(&self.toc_records);
+        } else {
+            self.toc.write_into(&mut toc_buf)?;
+        }
+        if self.header.flags & FLAG_TOC_GZIP != 0 {
+            toc_buf = compression::compress_toc(&toc_buf)?;
+        }
+
+        // If we're overwriting an old TOC, pad the space before the new
+        // one, so that no stale bytes remain at the end of the file.
+        let trailer_len = toc_trailer_len(&self.header);
+        let end_offset = self.current_offset() + toc_buf.len() as u64 + trailer_len;
+        if end_offset < self.min_length {
+            let padding = self.min_length - end_offset;
+            io::copy(&mut io::repeat(0).take(padding), &mut self.writer)?;
+        }
+
+        // Manually serialize the TOC checksum and length, so that they have
+        // a fixed size and a fixed offset (relative to the end of the file).
+        let toc_length = toc_buf.len() as u64;
+        if self.header.flags & FLAG_TOC_CRC != 0 {
+            let crc = Crc32::checksum(&toc_buf);
+            toc_buf.write_u32::<BigEndian>(crc).unwrap();
+        }
+        toc_buf.write_u64::<BigEndian>(toc_length).unwrap();
+
+        // Write the TOC.
+        self.writer.write_all(&toc_buf)?;
+        self.scratch = toc_buf;
 
-        // This should fail, because we are unable to parse the chapter index.
-        Book::new(cursor).unwrap_err();
+        self.writer.flush()?;
+        Ok(())
     }
+}
 
-    #[test]
-    fn simple_book() {
-        let magic = 0x1234;
-        let buffer = {
-            let buffer = Cursor::new(Vec::<u8>::new());
-            let book = BookWriter::new(buffer, magic).unwrap();
-            let chapter = book.new_chapter(11);
-            let book = chapter.close().unwrap();
-            let mut chapter = book.new_chapter(22);
-            chapter.write_all(b"This is chapter 22").unwrap();
-            let book = chapter.close().unwrap();
-            let mut chapter = book.new_chapter("🦀");
-            chapter.write_all(b"This is chapter 33").unwrap();
-            let book = chapter.close().unwrap();
-            book.close().unwrap()
-        };
-        let mut book = Book::new(buffer).unwrap();
-        let ch1 = book.exclusive_read_chapter(11).unwrap();
-        assert!(ch1.is_empty());
+impl<W: Write + Seek> BookWriter<W> {
+    /// Create a new `BookWriter` that stores the TOC at the front of the file.
+    ///
+    /// Normally the table of contents is stored at the end of the file,
+    /// which means readers need to seek there before reading any chapters.
+    /// This constructor reserves a region immediately after the file header,
+    /// which will be filled in with the TOC by [`close_front_toc()`].
+    /// A reader can then parse the header and TOC in one forward read, e.g.
+    /// using a [`ForwardReader`].
+    ///
+    /// `toc_capacity` is the maximum size of the serialized TOC, in bytes.
+    /// The reserved space is wasted if the TOC is smaller.
+    ///
+    /// The TOC is still stored at the end of the file as well, so if the
+    /// book is completed with [`close()`], or the TOC doesn't fit, readers
+    /// will fall back to reading the TOC at the end of the file.
+    ///
+    /// The writer must be positioned at the start of the book.
+    ///
+    /// [`close()`]: Self::close
+    /// [`close_front_toc()`]: Self::close_front_toc
+    /// [`ForwardReader`]: crate::ForwardReader
+    pub fn with_reserved_toc(writer: W, user_magic: u32, toc_capacity: usize) -> Result<Self> {
+        BookBuilder::new()
+            .user_magic(user_magic)
+            .reserved_toc(toc_capacity)
+            .build(writer)
+    }
 
-        assert!(!book.has_chapter(1));
+    /// Finish writing the `Book` file, including the front TOC.
+    ///
+    /// This does everything that [`close()`] does, and then seeks back
+    /// to fill in the TOC region reserved by [`with_reserved_toc()`].
+    /// If the TOC doesn't fit in the reserved region,
+    /// [`BookError::TocTooLarge`] is returned, though the book will
+    /// still be readable using the TOC at the end of the file.
+    ///
+    /// If the `BookWriter` wasn't created with a reserved TOC region, this
+    /// is the same as `close()`.
+    ///
+    /// [`close()`]: Self::close
+    /// [`with_reserved_toc()`]: Self::with_reserved_toc
+    pub fn close_front_toc(mut self) -> Result<W> {
+        self.write_manifest()?;
+        self.write_toc().context("writing TOC")?;
+        if self.header.flags & FLAG_FRONT_TOC == 0 {
+            return Ok(self.writer.into_inner());
+        }
 
-        let ch2 = book.exclusive_read_chapter(22).unwrap();
-        assert_eq!(ch2.as_ref(), b"This is chapter 22");
+        let toc_buf = self.toc.to_bytes()?;
+        let overhead = front_toc_overhead(&self.header);
+        let max = (self.header.front_toc_size - overhead)
+            .try_into()
+            .unwrap_or(usize::MAX);
+        if toc_buf.len() > max {
+            return Err(BookError::TocTooLarge {
+                size: toc_buf.len(),
+                max,
+            });
+        }
 
-        let ch2 = book.exclusive_read_chapter("🦀").unwrap();
-        assert_eq!(ch2.as_ref(), b"This is chapter 33");
+        // Seek back to the start of the reserved region. We don't assume
+        // the book starts at offset 0 of the underlying stream.
+        let end_offset = self.current_offset();
+        let mut writer = self.writer.into_inner();
+        let rewind = (end_offset - self.header.header_size) as i64;
+        writer.seek(SeekFrom::Current(-rewind))?;
+        writer.write_u64::<BigEndian>(toc_buf.len() as u64)?;
+        if self.header.flags & FLAG_TOC_CRC != 0 {
+            writer.write_u32::<BigEndian>(Crc32::checksum(&toc_buf))?;
+        }
+        writer.write_all(&toc_buf)?;
+        writer.seek(SeekFrom::Current(
+            rewind - overhead as i64 - toc_buf.len() as i64,
+        ))?;
+        writer.flush()?;
+        Ok(writer)
     }
+}
 
-    #[test]
-    fn book_file_shared() {
-        let temp = tempfile::tempfile().unwrap();
+impl<W: Read + Write + Seek> BookWriter<W> {
+    /// Open an existing `Book` in order to add more chapters.
+    ///
+    /// The header and table of contents are read and validated before
+    /// anything is written, so if this fails the stream is unchanged.
+    /// New chapters are written over the old TOC, and [`close()`] writes
+    /// a TOC containing both the old and new chapters.
+    ///
+    /// The book must start at offset 0 of the stream. The stream is never
+    /// truncated; if the new contents would be shorter than the old file,
+    /// padding is inserted before the new TOC.
+    ///
+    /// If the book has a reserved front TOC, it is cleared, so that
+    /// readers will use the TOC at the end of the file. It can be filled
+    /// in again using [`close_front_toc()`].
+    ///
+    /// If the book's TOC is stored as records (see
+    /// [`BookBuilder::record_toc`]), the existing records are kept as they
+    /// are, and only entries for new chapters are serialized.
+    ///
+    /// [`close()`]: Self::close
+    /// [`close_front_toc()`]: Self::close_front_toc
+    pub fn open_append(mut stream: W) -> Result<Self> {
+        let (header, _) = read_header(&mut stream).context("reading header")?;
+        let (toc_buf, toc_offset) =
+            read_end_toc_bytes(&mut stream, &header).context("reading TOC")?;
+        let (toc, toc_version) = Toc::deserialize_versioned(toc_buf.as_slice())?;
+        let file_length = stream.seek(SeekFrom::End(0))?;
 
-        let magic = 0x1234;
-        let file = {
-            let book = BookWriter::new(temp, magic).unwrap();
-            let chapter = book.new_chapter(11);
-            let book = chapter.close().unwrap();
-            let mut chapter = book.new_chapter(22);
-            chapter.write_all(b"This is chapter 22").unwrap();
-            let book = chapter.close().unwrap();
-            let mut chapter = book.new_chapter("🦀");
-            chapter.write_all(b"This is chapter 33").unwrap();
-            let book = chapter.close().unwrap();
-            book.close().unwrap()
+        // Existing chapters must lie between the header and the TOC, or
+        // new chapters would overwrite them.
+        check_spans(&toc, data_start(&header), toc_offset)?;
+
+        // Everything is valid; from here on we may modify the stream.
+        if header.flags & FLAG_FRONT_TOC != 0 {
+            stream.seek(SeekFrom::Start(header.header_size))?;
+            stream.write_u64::<BigEndian>(0)?;
+        }
+        stream.seek(SeekFrom::Start(toc_offset))?;
+
+        // Existing records can be reused if they're in the current format.
+        let reuse_records = header.flags & FLAG_TOC_RECORDS != 0
+            && toc_buf.starts_with(&TOC_RECORDS_ID.to_be_bytes())
+            && toc_version == Toc::VER;
+        let (toc_records, toc_records_count) = if reuse_records {
+            (toc_buf, toc.0.len())
+        } else {
+            (Vec::new(), 0)
         };
-        let book = Book::new(file).unwrap();
-        let ch1 = book.read_chapter(11).unwrap();
-        assert!(ch1.is_empty());
 
-        assert!(!book.has_chapter(1));
+        Ok(BookWriter {
+            writer: CountingWriter::with_count(stream, toc_offset),
+            header,
+            toc,
+            strict_drop: true,
+            checksum_kind: ChecksumKind::default(),
+            min_length: file_length,
+            scratch: Vec::new(),
+            chapter_alignment: 1,
+            io_buffer_size: COPY_BUFFER_SIZE,
+            manifest: None,
+            toc_records,
+            toc_records_count,
+            dedup: None,
+        })
+    }
 
-        let ch2 = book.read_chapter(22).unwrap();
-        assert_eq!(ch2.as_ref(), b"This is chapter 22");
+    /// Discard any TOC records that would be reused when the TOC is
+    /// written, because existing entries have changed.
+    fn discard_toc_records(&mut self) {
+        self.toc_records.clear();
+        self.toc_records_count = 0;
+    }
+}
 
-        let ch2 = book.read_chapter("🦀").unwrap();
-        assert_eq!(ch2.as_ref(), b"This is chapter 33");
+/// A tool for modifying chapters of an existing `Book` in place.
+///
+/// Chapters can be overwritten without rewriting the rest of the file,
+/// as long as the new contents are the same size. When editing is
+/// complete, call [`close()`] to write the updated table of contents;
+/// until then, readers will see checksum mismatches for edited chapters.
+///
+/// This is opened in the same way as [`BookWriter::open_append`], and has
+/// the same requirements for the stream.
+///
+/// [`close()`]: Self::close
+#[derive(Debug)]
+pub struct BookEditor<W> {
+    book: BookWriter<W>,
+}
+
+impl<W: Read + Write + Seek> BookEditor<W> {
+    /// Open an existing `Book` for editing.
+    ///
+    /// See [`BookWriter::open_append`].
+    pub fn open(stream: W) -> Result<Self> {
+        let book = BookWriter::open_append(stream)?;
+        Ok(BookEditor { book })
     }
 
-    #[test]
-    fn toc_compat() {
-        let mut toc = Vec::new();
-        toc.push(TocEntryV1 {
-            id: 1234,
-            span: Some(FileSpanV1 {
-                length: 33.try_into().unwrap(),
-                offset: 44,
-            }),
-        });
-        let toc = TocV1(toc);
+    /// Replace the contents of a chapter.
+    ///
+    /// `data` must be the same length as the existing chapter, or
+    /// [`BookError::SizeMismatch`] is returned and nothing is written.
+    /// The chapter's checksum is updated, using the same algorithm as
+    /// before; a chapter without a checksum doesn't gain one.
+    ///
+    /// The bytes are stored verbatim, so to replace a compressed or
+    /// encrypted chapter, `data` must already be compressed or encrypted.
+    ///
+    /// If other chapters share this chapter's span (see
+    /// [`BookBuilder::dedup`]), their contents change too, and their
+    /// checksums are updated in the same way.
+    pub fn overwrite_chapter(&mut self, index: ChapterIndex, data: &[u8]) -> Result<()> {
+        let book = &mut self.book;
+        let entry = book.toc.get_index(index)?;
+        let length = entry.span.as_ref().map_or(0, |span| span.length.get());
+        if data.len() as u64 != length {
+            return Err(BookError::SizeMismatch {
+                expected: length,
+                actual: data.len() as u64,
+            });
+        }
+        let span = entry.span.clone();
+
+        if let Some(span) = &span {
+            let current_offset = book.current_offset();
+            let stream = book.writer.get_mut();
+            stream.seek(SeekFrom::Start(span.offset))?;
+            stream.write_all(data)?;
+            stream.seek(SeekFrom::Start(current_offset))?;
+        }
+        let checksum_kind = book.checksum_kind;
+        for (entry_index, entry) in book.toc.0.iter_mut().enumerate() {
+            if entry_index != index.0 && (span.is_none() || entry.span != span) {
+                continue;
+            }
+            entry.checksum = entry.checksum.and_then(|old| {
+                old.recompute(data).or_else(|| {
+                    // The original algorithm isn't enabled.
+                    let mut hasher = ChapterHasher::new(checksum_kind);
+                    hasher.update(data);
+                    hasher.finish()
+                })
+            });
+        }
+        book.discard_toc_records();
+        Ok(())
+    }
+
+    /// Remove a chapter, leaving its bytes in place.
+    ///
+    /// The chapter's entry is kept as a tombstone, marked as removed, so
+    /// the indices of other chapters don't change. Its span, checksum,
+    /// codec and encryption are cleared. Lookups by id (such as
+    /// [`Book::find_chapter`] and [`Book::find_latest_chapter`]) skip it,
+    /// and reading it by index returns [`BookError::ChapterRemoved`].
+    ///
+    /// This is cheap, as no chapter data is moved, but the file doesn't
+    /// shrink: the removed bytes stay in the file, unreferenced, and can
+    /// still be found (and recovered) via [`Book::layout_gaps`]. Use
+    /// [`remove_chapter_compact`] to discard them.
+    ///
+    /// [`remove_chapter_compact`]: Self::remove_chapter_compact
+    pub fn remove_chapter(&mut self, index: ChapterIndex) -> Result<()> {
+        self.book.toc.get_index(index)?;
+        let entry = &mut self.book.toc.0[index.0];
+        entry.removed = true;
+        entry.span = None;
+        entry.checksum = None;
+        entry.codec = None;
+        entry.encryption = None;
+        self.book.discard_toc_records();
+        Ok(())
+    }
+
+    /// Remove a chapter, moving later chapters down over its bytes.
+    ///
+    /// Unlike [`remove_chapter`], the chapter's entry is dropped from the
+    /// table of contents, so later chapters' indices shift down by one.
+    /// Every chapter stored after the removed one is copied down to close
+    /// the gap, and its span is updated. This can be expensive for a large
+    /// book. Chapters that were aligned (see
+    /// [`BookBuilder::chapter_alignment`]) may no longer be. This can also
+    /// be used to drop the entry of a chapter that was already removed
+    /// with `remove_chapter`.
+    ///
+    /// A stream can't be truncated through `Write + Seek`, so the freed
+    /// space moves to the end of the chapter data, and [`close()`] fills
+    /// it with zeros before the new TOC. To shrink the file, truncate it
+    /// afterwards and rewrite the TOC with [`BookWriter::open_append`].
+    ///
+    /// If another chapter shares the removed chapter's span (see
+    /// [`BookBuilder::dedup`]), the bytes are still in use, so nothing is
+    /// moved, as with `remove_chapter`.
+    ///
+    /// [`remove_chapter`]: Self::remove_chapter
+    /// [`close()`]: Self::close
+    pub fn remove_chapter_compact(&mut self, index: ChapterIndex) -> Result<()> {
+        let book = &mut self.book;
+        let span = book.toc.get_entry(index)?.span.clone();
+        book.toc.0.remove(index.0);
+        book.discard_toc_records();
+        let (offset, length) = match &span {
+            Some(span)
+                if book
+                    .toc
+                    .iter()
+                    .all(|entry| entry.span.as_ref() != Some(span)) =>
+            {
+                (span.offset, span.length.get())
+            }
+            _ => return Ok(()),
+        };
+
+        // Copy everything after the chapter down. Chapters don't overlap
+        // (open_append checked), so everything being moved lies after it.
+        let end = offset + length;
+        let current_offset = book.current_offset();
+        let mut buf = vec![0u8; book.io_buffer_size];
+        let stream = book.writer.get_mut();
+        let mut src = end;
+        while src < current_offset {
+            let chunk_len: usize = (current_offset - src)
+                .try_into()
+                .unwrap_or(usize::MAX)
+                .min(buf.len());
+            let chunk = &mut buf[..chunk_len];
+            stream.seek(SeekFrom::Start(src))?;
+            stream.read_exact(chunk)?;
+            stream.seek(SeekFrom::Start(src - length))?;
+            stream.write_all(chunk)?;
+            src += chunk_len as u64;
+        }
+        let new_offset = current_offset - length;
+        stream.seek(SeekFrom::Start(new_offset))?;
+        book.writer.set_count(new_offset);
+
+        for entry in book.toc.0.iter_mut() {
+            if let Some(span) = &mut entry.span {
+                if span.offset >= end {
+                    span.offset -= length;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Finish editing, writing the updated table of contents.
+    ///
+    /// On success, this returns the original stream.
+    pub fn close(self) -> Result<W> {
+        self.book.close()
+    }
+}
+
+impl BookWriter<Cursor<Vec<u8>>> {
+    /// Create a new `BookWriter` that writes to a memory buffer.
+    pub fn new_in_memory(user_magic: u32) -> Result<Self> {
+        Self::new(Cursor::new(Vec::new()), user_magic)
+    }
+
+    /// Finish writing the `Book`, and open the result for reading.
+    ///
+    /// This is the same as calling [`close()`] followed by [`Book::new`]
+    /// on the returned buffer.
+    ///
+    /// [`close()`]: Self::close
+    pub fn close_to_book(self) -> Result<Book<Cursor<Vec<u8>>>> {
+        let buffer = self.close()?;
+        Book::new(buffer)
+    }
+}
+
+/// A builder for configuring a [`BookWriter`].
+///
+/// This collects writer options up front, and then creates the
+/// `BookWriter` with [`build()`]. [`BookWriter::new`] is similar to a
+/// builder with only the user magic number set, except that the builder
+/// stores CRC-32 chapter checksums by default.
+///
+/// [`build()`]: Self::build
+#[derive(Debug, Clone)]
+pub struct BookBuilder {
+    user_magic: u32,
+    user_meta: Vec<u8>,
+    checksum_kind: ChecksumKind,
+    strict_drop: bool,
+    toc_capacity: Option<usize>,
+    sorted_ids: bool,
+    header_size: usize,
+    chapter_alignment: u64,
+    io_buffer_size: usize,
+    /// The creation timestamp, or `None` to use the current time.
+    created_at: Option<Option<u64>>,
+    compress_toc: bool,
+    record_toc: bool,
+    dedup: bool,
+}
+
+impl Default for BookBuilder {
+    /// Create a `BookBuilder` with default settings.
+    ///
+    /// New chapters get CRC-32 checksums. This default is unconditional;
+    /// it doesn't depend on any crate feature.
+    fn default() -> Self {
+        BookBuilder {
+            user_magic: 0,
+            user_meta: Vec::new(),
+            checksum_kind: ChecksumKind::Crc32,
+            strict_drop: true,
+            toc_capacity: None,
+            sorted_ids: false,
+            header_size: HEADER_SIZE,
+            chapter_alignment: 1,
+            io_buffer_size: COPY_BUFFER_SIZE,
+            created_at: None,
+            compress_toc: false,
+            record_toc: false,
+            dedup: false,
+        }
+    }
+}
+
+impl BookBuilder {
+    /// Create a new `BookBuilder` with default settings.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the user-defined magic number. The default is 0.
+    ///
+    /// See [`BookWriter::new`].
+    pub fn user_magic(mut self, user_magic: u32) -> Self {
+        self.user_magic = user_magic;
+        self
+    }
+
+    /// Set the user metadata stored in the file header.
+    ///
+    /// See [`BookWriter::new_with_meta`].
+    pub fn user_meta(mut self, meta: &[u8]) -> Self {
+        self.user_meta = meta.to_vec();
+        self
+    }
+
+    /// Set the checksum algorithm for new chapters. The default is
+    /// [`ChecksumKind::Crc32`].
+    ///
+    /// See [`BookWriter::set_checksum_kind`].
+    pub fn checksum(mut self, kind: ChecksumKind) -> Self {
+        self.checksum_kind = kind;
+        self
+    }
+
+    /// Control whether dropping an unclosed `ChapterWriter` panics.
+    ///
+    /// See [`BookWriter::set_strict_drop`].
+    pub fn strict_drop(mut self, strict: bool) -> Self {
+        self.strict_drop = strict;
+        self
+    }
+
+    /// Reserve space for a TOC at the front of the file.
+    ///
+    /// See [`BookWriter::with_reserved_toc`]. The book must be finished
+    /// with [`BookWriter::close_front_toc`] to fill in the reserved space.
+    pub fn reserved_toc(mut self, toc_capacity: usize) -> Self {
+        self.toc_capacity = Some(toc_capacity);
+        self
+    }
+
+    /// Promise that chapters will be written in ascending id order.
+    ///
+    /// Ids are compared as byte strings, which for integer ids is the
+    /// same as numeric order. Chapters with equal ids are allowed.
+    /// This is recorded in the file header, and allows
+    /// [`Book::find_chapter`] to use a binary search rather than a
+    /// linear scan. Debug builds panic if the promise is broken.
+    pub fn sorted_ids(mut self, sorted: bool) -> Self {
+        self.sorted_ids = sorted;
+        self
+    }
+
+    /// Store the TOC at the end of the file gzip-compressed.
+    ///
+    /// This is worthwhile for books with very many chapters, where the
+    /// TOC itself is large. It's recorded in the file header, so readers
+    /// decompress the TOC automatically, but they need the `gzip` feature
+    /// to do so. A reserved front TOC (see [`reserved_toc()`]) is always
+    /// stored uncompressed.
+    ///
+    /// [`reserved_toc()`]: Self::reserved_toc
+    #[cfg(feature = "gzip")]
+    pub fn compress_toc(mut self, compress: bool) -> Self {
+        self.compress_toc = compress;
+        self
+    }
+
+    /// Store the TOC at the end of the file as a stream of entry records.
+    ///
+    /// Normally the TOC is serialized as a single message, so appending
+    /// chapters with [`BookWriter::open_append`] re-serializes every
+    /// existing entry. With this option, each entry is a separate
+    /// length-prefixed record, and appending only serializes the new
+    /// entries; the existing records are copied as they are. This is
+    /// worthwhile for books with very many chapters that are appended to
+    /// often. The records take a few more bytes per chapter.
+    ///
+    /// The format is recorded in the file header, so readers parse either
+    /// format automatically, and `open_append` keeps whichever format the
+    /// book was written with. To convert an existing book, copy its
+    /// chapters into a new one (see [`BookWriter::copy_chapter_from`]).
+    /// A reserved front TOC (see [`reserved_toc()`]) is always stored as
+    /// a single message.
+    ///
+    /// [`reserved_toc()`]: Self::reserved_toc
+    pub fn record_toc(mut self, record_toc: bool) -> Self {
+        self.record_toc = record_toc;
+        self
+    }
+
+    /// Store identical chapters only once.
+    ///
+    /// When enabled, [`BookWriter::write_chapter`] hashes each chapter's
+    /// contents, and if they match a chapter written earlier, the new TOC
+    /// entry points at the existing span instead of storing the bytes
+    /// again. Reading either chapter returns the same bytes. Chapters
+    /// written in other ways (e.g. with a [`ChapterWriter`], or compressed
+    /// or encrypted) are never deduplicated, since their bytes are
+    /// already stored by the time they could be compared.
+    ///
+    /// Shared spans affect editing: [`BookEditor::overwrite_chapter`]
+    /// changes every chapter that shares the span, and
+    /// [`BookEditor::remove_chapter_compact`] leaves a shared span in place.
+    pub fn dedup(mut self, dedup: bool) -> Self {
+        self.dedup = dedup;
+        self
+    }
+
+    /// Set the size of the header block. The default is 4096 bytes.
+    ///
+    /// The size is recorded in the header, so readers don't need to know
+    /// it in advance. It must be between 512 bytes and 1MB, or
+    /// [`build()`] will return [`BookError::InvalidHeaderSize`]. If the
+    /// header (including the user metadata) doesn't fit,
+    /// [`BookError::HeaderTooLarge`] is returned.
+    ///
+    /// [`build()`]: Self::build
+    pub fn header_size(mut self, header_size: usize) -> Self {
+        self.header_size = header_size;
+        self
+    }
+
+    /// Start each chapter at a multiple of `alignment` bytes.
+    ///
+    /// This is useful when chapters will be memory-mapped or read with
+    /// direct I/O. The gaps before chapters are filled with zero bytes.
+    /// The default is 1, meaning no padding; 0 is treated the same way.
+    ///
+    /// Offsets are measured from the start of the stream. Alignment isn't
+    /// recorded in the file, so chapters added with
+    /// [`BookWriter::open_append`] aren't padded.
+    pub fn chapter_alignment(mut self, alignment: u64) -> Self {
+        self.chapter_alignment = alignment.max(1);
+        self
+    }
+
+    /// Set the size of the buffer used to copy chapter data.
+    ///
+    /// This is used by [`BookWriter::write_chapter_from`] and
+    /// [`BookWriter::copy_chapter_from`]. A larger buffer means fewer
+    /// read and write calls for large chapters. The default is 64KB;
+    /// 0 is treated as 1.
+    pub fn io_buffer_size(mut self, size: usize) -> Self {
+        self.io_buffer_size = size.max(1);
+        self
+    }
+
+    /// Set the creation timestamp, in seconds since the Unix epoch.
+    ///
+    /// By default, the current time is recorded when the `BookWriter` is
+    /// built. A fixed value makes the output reproducible: the same
+    /// settings and chapters produce the same bytes. `None` stores no
+    /// timestamp at all. See [`Book::created_at`].
+    pub fn created_at(mut self, created_unix_secs: Option<u64>) -> Self {
+        self.created_at = Some(created_unix_secs);
+        self
+    }
+
+    /// Create the `BookWriter` and write the file header.
+    pub fn build<W: Write>(self, writer: W) -> Result<BookWriter<W>> {
+        let mut header = FileHeader::new(self.user_magic);
+        header.user_meta = self.user_meta;
+        header.header_size = self.header_size as u64;
+        if let Some(created_unix_secs) = self.created_at {
+            header.created_unix_secs = created_unix_secs;
+        }
+        check_header_size(header.header_size)?;
+        if let Some(toc_capacity) = self.toc_capacity {
+            header.flags |= FLAG_FRONT_TOC;
+            // The region also holds the TOC length and CRC.
+            header.front_toc_size =
+                (toc_capacity as u64).saturating_add(front_toc_overhead(&header));
+        }
+        if self.sorted_ids {
+            header.flags |= FLAG_SORTED_IDS;
+        }
+        if self.compress_toc {
+            header.flags |= FLAG_TOC_GZIP;
+        }
+        if self.record_toc {
+            header.flags |= FLAG_TOC_RECORDS;
+        }
+        let mut book = BookWriter::with_header(writer, header)?;
+        book.set_checksum_kind(self.checksum_kind);
+        book.set_strict_drop(self.strict_drop);
+        book.chapter_alignment = self.chapter_alignment;
+        book.io_buffer_size = self.io_buffer_size;
+        if self.dedup {
+            book.dedup = Some(HashMap::new());
+        }
+        book.pad_to_alignment().context("writing header")?;
+        Ok(book)
+    }
+}
+
+/// Estimate the size of a book, without writing it.
+///
+/// `chapters` holds the `(id, length)` of each chapter, in the order
+/// they will be written. The estimate assumes
+/// a book written by [`BookWriter::new`] with default settings: a
+/// 4096-byte header, unnamed chapters, and no chapter checksums.
+///
+/// The TOC is sized by serializing a real TOC, so the estimate is exact
+/// for such a book.
+pub fn estimate_book_size(chapters: &[(u64, u64)]) -> u64 {
+    let mut toc = Toc::default();
+    let mut offset = HEADER_SIZE as u64;
+    for &(id, length) in chapters {
+        toc.add(TocEntry {
+            id: ChapterId::from(id).0,
+            name: None,
+            span: FileSpan::from_offset_length(offset, length),
+            checksum: None,
+            encryption: None,
+            codec: None,
+            content_type: None,
+            removed: false,
+        });
+        offset += length;
+    }
+    // Serializing into memory can't fail.
+    let toc_len = toc.to_bytes().unwrap().len() as u64;
+    offset + toc_len + toc_trailer_len(&FileHeader::new(0))
+}
+
+/// A summary of a book's format, from [`probe`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BookProbe {
+    /// The Bookfile format magic number.
+    pub bookwriter_magic: u32,
+    /// The user-defined magic number.
+    pub user_magic: u32,
+    /// The on-disk version of the file header.
+    pub header_version: u16,
+    /// The length of the serialized TOC at the end of the file, in bytes.
+    pub toc_len: u64,
+    /// The number of chapters.
+    pub chapter_count: u64,
+}
+
+/// Identify a book, without loading it.
+///
+/// This reads the file header, the TOC length, and just enough of the TOC
+/// to count the chapters; the chapter entries themselves aren't parsed
+/// or checked. It's intended for quickly inspecting files, e.g. in a
+/// command-line tool. To read the book, use [`Book::new`].
+///
+/// The stream position afterwards is unspecified. The stream isn't
+/// otherwise affected, so it can be seeked back to read it again.
+pub fn probe<R>(reader: &mut R) -> Result<BookProbe>
+where
+    R: Read + Seek,
+{
+    let (header, header_version) = read_header(reader).context("reading header")?;
+    let (toc_offset, toc_len) = locate_end_toc(reader, &header).context("reading TOC")?;
+    reader.seek(SeekFrom::Start(toc_offset))?;
+    let toc_reader = BoundedReader::new(reader, toc_offset, toc_len);
+    let codec = if header.flags & FLAG_TOC_GZIP != 0 {
+        Some(Codec::Gzip)
+    } else {
+        None
+    };
+    let toc_reader = compression::decoder(codec, toc_reader)?;
+    let chapter_count = read_toc_count(toc_reader).context("reading TOC")?;
+    Ok(BookProbe {
+        bookwriter_magic: header.bookwriter_magic,
+        user_magic: header.user_magic,
+        header_version,
+        toc_len,
+        chapter_count,
+    })
+}
+
+/// Verify a book's chapter checksums in a single forward pass.
+///
+/// The stream is read from the start of the book up to the end of the
+/// last chapter, without seeking, so this works on pipes and tapes. Each
+/// chapter's checksum is computed as the stream passes over it. Because
+/// the TOC is at the end of the file, it must be supplied separately, e.g.
+/// from a sidecar file parsed with [`Toc::from_bytes`], or from
+/// [`Book::toc`] when checking a copy of a book.
+///
+/// Chapters without checksums, or whose checksum algorithm isn't enabled,
+/// are skipped over. If the stream ends early, [`BookError::Eof`] is
+/// returned.
+pub fn verify_sequential<R>(mut reader: R, toc: &Toc) -> Result<()>
+where
+    R: Read,
+{
+    // Overlapping spans can't be checked in one pass.
+    check_spans(toc, 0, u64::MAX)?;
+    let mut entries: Vec<_> = toc
+        .iter()
+        .filter_map(|entry| entry.span.as_ref().map(|span| (span, entry)))
+        .collect();
+    entries.sort_by_key(|(span, _)| span.offset);
+
+    let mut position = 0;
+    let mut buf = vec![0u8; COPY_BUFFER_SIZE];
+    // Chapters that share a span (see `BookBuilder::dedup`) are adjacent
+    // after sorting, and are all checked in one pass over the span.
+    for group in entries.chunk_by(|(a, _), (b, _)| a == b) {
+        let span = group[0].0;
+
+        // Skip to the start of the chapter.
+        let gap = span.offset - position;
+        let skipped = io::copy(&mut (&mut reader).take(gap), &mut io::sink())?;
+        if skipped < gap {
+            return Err(BookError::Eof);
+        }
+
+        let mut hashers: Vec<_> = group
+            .iter()
+            .map(|(_, entry)| {
+                let kind = entry.checksum.and_then(|checksum| checksum.kind());
+                ChapterHasher::new(kind.unwrap_or(ChecksumKind::None))
+            })
+            .collect();
+        let mut chapter = (&mut reader).take(span.length.get());
+        let mut remaining = span.length.get();
+        while remaining > 0 {
+            let bytes_read = match chapter.read(&mut buf) {
+                Ok(0) => return Err(BookError::Eof),
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            };
+            for hasher in &mut hashers {
+                hasher.update(&buf[..bytes_read]);
+            }
+            remaining -= bytes_read as u64;
+        }
+        position = span.offset + span.length.get();
+
+        for ((_, entry), hasher) in group.iter().zip(&hashers) {
+            if let (Some(expected), Some(actual)) = (entry.checksum, hasher.finish()) {
+                if actual != expected {
+                    return Err(BookError::ChecksumMismatch {
+                        id: entry.id.clone(),
+                        expected: expected.value(),
+                        actual: actual.value(),
+                    });
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Read the number of entries from the start of a serialized TOC.
+///
+/// Every TOC version is serialized as a CBOR array of entries, so the
+/// count can be read from the array header without parsing the entries.
+/// A TOC stored as records has no count, so the records are skipped over
+/// one at a time.
+fn read_toc_count(mut reader: impl Read) -> Result<u64> {
+    let msg_id = reader.read_u16::<BigEndian>()?;
+    if msg_id == TOC_RECORDS_ID {
+        return count_toc_records(reader);
+    }
+    let reader = io::Cursor::new(msg_id.to_be_bytes()).chain(reader);
+    let mut data_src = CborData::new(reader);
+    let header = data_src.read_header()?;
+    if header.msg_id() != Toc::MSG_ID {
+        return Err(BookError::CorruptToc);
+    }
+    let mut reader = data_src.into_inner();
+
+    // The CBOR major type is in the top 3 bits; 4 is an array. The low
+    // 5 bits hold a small length, or say how many bytes of length follow.
+    let initial = reader.read_u8()?;
+    if initial >> 5 != 4 {
+        return Err(BookError::CorruptToc);
+    }
+    let count = match initial & 0x1F {
+        len @ 0..=23 => u64::from(len),
+        24 => u64::from(reader.read_u8()?),
+        25 => u64::from(reader.read_u16::<BigEndian>()?),
+        26 => u64::from(reader.read_u32::<BigEndian>()?),
+        27 => reader.read_u64::<BigEndian>()?,
+        _ => return Err(BookError::CorruptToc),
+    };
+    Ok(count)
+}
+
+/// Count the records in a TOC stored as records.
+///
+/// `reader` is positioned just after the record stream's message id.
+fn count_toc_records(mut reader: impl Read) -> Result<u64> {
+    reader.read_u16::<BigEndian>()?;
+    let mut count = 0;
+    loop {
+        // A clean EOF is only allowed between records.
+        let mut len_buf = [0u8; 4];
+        let first = loop {
+            match reader.read(&mut len_buf[..1]) {
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                result => break result?,
+            }
+        };
+        if first == 0 {
+            return Ok(count);
+        }
+        reader.read_exact(&mut len_buf[1..])?;
+        let record_len = u64::from(u32::from_be_bytes(len_buf));
+        let skipped = io::copy(&mut (&mut reader).take(record_len), &mut io::sink())?;
+        if skipped < record_len {
+            return Err(BookError::CorruptToc);
+        }
+        count += 1;
+    }
+}
+
+/// An interface for reading a Bookfile.
+///
+/// The `Book` type represents a read-only Bookfile. Invividual chapters can
+/// be read in their entirety, or incrementally using the [`Read`] interface.
+/// [`Seek`] and [`read_at`]/[`read_exact_at`]
+/// are also provided, and work within the context of that chapter: the seek
+/// offset is the offset within the chapter, and a read at the end of the
+/// chapter will return EOF.
+///
+/// [`Read`]: std::io::Read
+/// [`Seek`]: std::io::Seek
+/// [`read_at`]: crate::BoundedReader::read_at
+/// [`read_exact_at`]: crate::BoundedReader::read_exact_at
+
+#[derive(Debug)]
+pub struct Book<R> {
+    reader: R,
+    header: FileHeader,
+    toc: Toc,
+    /// The offset of the TOC at the end of the file, if it was read.
+    toc_offset: Option<u64>,
+    verify_checksums: bool,
+    cache: Option<Mutex<ChapterCache>>,
+    versions: FormatVersions,
+}
+
+/// The on-disk format versions of a [`Book`]'s header and TOC.
+///
+/// See [`Book::format_versions`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FormatVersions {
+    /// The version of the file header.
+    pub header: u16,
+    /// The version of the table of contents.
+    pub toc: u16,
+}
+
+impl Default for FormatVersions {
+    /// The versions written by this version of the library.
+    fn default() -> Self {
+        FormatVersions {
+            header: FileHeader::VER,
+            toc: Toc::VER,
+        }
+    }
+}
+
+/// Summary statistics about a [`Book`].
+///
+/// See [`Book::stats`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BookStats {
+    /// The number of chapters.
+    pub chapter_count: usize,
+    /// The total length of all chapters, in bytes.
+    ///
+    /// Chapters that share a span (see [`BookBuilder::dedup`]) are each
+    /// counted.
+    pub total_payload_bytes: u64,
+    /// The offset of the TOC at the end of the file.
+    ///
+    /// This is `None` if the TOC wasn't read from the end of the file
+    /// (e.g. it was read from the front of the file, or the `Book` was
+    /// created with [`Book::from_parts`]).
+    pub toc_offset: Option<u64>,
+}
+
+impl<R> Book<R> {
+    /// Return the file's magic number.
+    ///
+    /// Each BookWriter specifies a magic number, used to identify this file format.
+    /// This method returns the value found in the file.
+    pub fn magic(&self) -> u32 {
+        self.header.user_magic
+    }
+
+    /// Return the file's user-defined magic number.
+    ///
+    /// This is the same as [`magic`](Self::magic).
+    pub fn user_magic(&self) -> u32 {
+        self.header.user_magic
+    }
+
+    /// Return the user metadata stored in the file header.
+    ///
+    /// This is empty if no metadata was written.
+    pub fn user_meta(&self) -> &[u8] {
+        &self.header.user_meta
+    }
+
+    /// Return when the file was created, in seconds since the Unix epoch.
+    ///
+    /// This is `None` if the file was written by an older version of this
+    /// crate, or with [`BookBuilder::created_at`] set to `None`.
+    pub fn created_at(&self) -> Option<u64> {
+        self.header.created_unix_secs
+    }
+
+    /// Create a Book from a stream, a header, and a table of contents.
+    ///
+    /// This doesn't do any IO. It's useful when the header and TOC
+    /// were obtained separately (e.g. from a sidecar file, via
+    /// `Toc::from_bytes`), and the stream can't seek to the end of the
+    /// file to read them.
+    ///
+    /// Reading chapters still requires `Read + Seek`. If the stream can't
+    /// seek, wrap it in a [`ForwardReader`]; chapters may then be read in
+    /// ascending offset order (the order they were written). Attempting
+    /// to read a chapter that starts before the current stream position
+    /// will return an error, so out-of-order access still requires a
+    /// stream that can seek.
+    ///
+    /// [`ForwardReader`]: crate::ForwardReader
+    pub fn from_parts(reader: R, header: FileHeader, toc: Toc) -> Self {
+        Book {
+            reader,
+            header,
+            toc,
+            toc_offset: None,
+            verify_checksums: false,
+            cache: None,
+            versions: FormatVersions::default(),
+        }
+    }
+
+    /// Return the file header.
+    pub fn header(&self) -> &FileHeader {
+        &self.header
+    }
+
+    /// Return a reference to the underlying stream.
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    /// Return a mutable reference to the underlying stream.
+    ///
+    /// Chapter readers always seek before reading, so moving the stream
+    /// position won't affect later reads. Writing to the stream might.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    /// Consume the `Book`, returning the underlying stream.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Return the format versions the header and TOC were stored with.
+    ///
+    /// Older versions are upgraded to the latest one when they're read,
+    /// so this is the only way to tell which versions a file used, e.g.
+    /// to decide whether it should be rewritten. For a `Book` created
+    /// with [`from_parts`](Self::from_parts), the latest versions are
+    /// reported.
+    pub fn format_versions(&self) -> FormatVersions {
+        self.versions
+    }
+
+    /// Keep recently read chapters in memory.
+    ///
+    /// Chapters read with [`exclusive_read_chapter`] (or
+    /// [`read_chapter`]) are stored in a least-recently-used cache
+    /// holding up to `capacity_bytes` of chapter data, and later reads
+    /// of the same chapter are served from memory. Chapters larger than
+    /// the cache are never stored. Use [`exclusive_read_chapter_uncached`]
+    /// (or [`read_chapter_uncached`]) to bypass the cache.
+    ///
+    /// [`exclusive_read_chapter`]: Self::exclusive_read_chapter
+    /// [`read_chapter`]: Self::read_chapter
+    /// [`exclusive_read_chapter_uncached`]: Self::exclusive_read_chapter_uncached
+    /// [`read_chapter_uncached`]: Self::read_chapter_uncached
+    pub fn with_cache(mut self, capacity_bytes: usize) -> Self {
+        self.cache = Some(Mutex::new(ChapterCache::new(capacity_bytes)));
+        self
+    }
+
+    /// Look up a chapter in the cache, if there is one.
+    fn cache_get(&self, index: ChapterIndex) -> Option<Box<[u8]>> {
+        lock_cache(self.cache.as_ref()?).get(index)
+    }
+
+    /// Add a chapter to the cache, if there is one.
+    fn cache_insert(&self, index: ChapterIndex, data: &[u8]) {
+        if let Some(cache) = &self.cache {
+            lock_cache(cache).insert(index, data);
+        }
+    }
+
+    /// Return the table of contents.
+    pub fn toc(&self) -> &Toc {
+        &self.toc
+    }
+
+    /// Return the number of chapters in the book.
+    ///
+    /// Chapters removed with [`BookEditor::remove_chapter`] are still
+    /// counted, as they keep their place in the TOC.
+    pub fn len(&self) -> usize {
+        self.toc.0.len()
+    }
+
+    /// Return `true` if the book contains no chapters.
+    pub fn is_empty(&self) -> bool {
+        self.toc.0.is_empty()
+    }
+
+    /// Return a human-readable description of the book.
+    ///
+    /// This lists the magic numbers and format versions, followed by a
+    /// table of chapters with their index, id, offset, and length. Ids
+    /// made from a `u64` are shown as numbers, UTF-8 ids as strings, and
+    /// anything else in hex. The format is meant for debugging, and may
+    /// change.
+    pub fn describe(&self) -> String {
+        use std::fmt::Write as _;
+
+        // Writing to a String can't fail.
+        let mut out = String::new();
+        writeln!(
+            out,
+            "Book: magic {:#x}, user magic {:#x}, header v{}, TOC v{}",
+            self.header.bookwriter_magic,
+            self.header.user_magic,
+            self.versions.header,
+            self.versions.toc,
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "{:>5} | {:<20} | {:>12} | {:>12}",
+            "index", "id", "offset", "length"
+        )
+        .unwrap();
+        for (index, entry) in self.toc.iter().enumerate() {
+            let (offset, length) = match &entry.span {
+                Some(span) => (span.offset.to_string(), span.length.get()),
+                None if entry.removed => ("removed".to_string(), 0),
+                None => ("-".to_string(), 0),
+            };
+            let id = describe_id(&entry.id);
+            writeln!(
+                out,
+                "{:>5} | {:<20} | {:>12} | {:>12}",
+                index, id, offset, length
+            )
+            .unwrap();
+        }
+        out
+    }
+
+    /// Return summary statistics about the book.
+    ///
+    /// This doesn't do any IO.
+    pub fn stats(&self) -> BookStats {
+        let total_payload_bytes = self
+            .toc
+            .iter()
+            .filter_map(|entry| entry.span.as_ref())
+            .map(|span| span.length.get())
+            .sum();
+        BookStats {
+            chapter_count: self.len(),
+            total_payload_bytes,
+            toc_offset: self.toc_offset,
+        }
+    }
+
+    /// Return the byte ranges not used by any chapter.
+    ///
+    /// Each gap is an `(offset, length)` pair within the chapter region,
+    /// which runs from the end of the header (and any reserved front TOC
+    /// region) to the start of the TOC. Gaps come from alignment padding,
+    /// abandoned chapters, or a TOC replaced by [`BookWriter::open_append`].
+    /// If the TOC offset isn't known (see [`BookStats::toc_offset`]), space
+    /// after the last chapter isn't reported.
+    ///
+    /// This doesn't do any IO.
+    pub fn layout_gaps(&self) -> Vec<(u64, u64)> {
+        let mut spans: Vec<_> = self
+            .toc
+            .iter()
+            .filter_map(|entry| entry.span.as_ref())
+            .map(|span| (span.offset, span.length.get()))
+            .collect();
+        spans.sort_unstable();
+
+        let mut gaps = Vec::new();
+        let mut pos = data_start(&self.header);
+        for (offset, length) in spans {
+            if offset > pos {
+                gaps.push((pos, offset - pos));
+            }
+            pos = pos.max(offset.saturating_add(length));
+        }
+        if let Some(toc_offset) = self.toc_offset {
+            if toc_offset > pos {
+                gaps.push((pos, toc_offset - pos));
+            }
+        }
+        gaps
+    }
+
+    /// Iterate over all chapters in the book.
+    ///
+    /// This yields each chapter's id and index, in file order. Removed
+    /// chapters are skipped.
+    pub fn chapters(&self) -> impl Iterator<Item = (&[u8], ChapterIndex)> {
+        self.toc
+            .live()
+            .map(|(index, entry)| (entry.id.as_ref(), ChapterIndex(index)))
+    }
+
+    /// Return the distinct integer chapter ids, in ascending order.
+    ///
+    /// Only ids created from a `u64` (i.e. exactly 8 bytes long) are
+    /// included; other ids are skipped. Note that an 8-byte string id is
+    /// indistinguishable from an integer id.
+    pub fn chapter_ids(&self) -> Vec<u64> {
+        let mut ids: Vec<u64> = self
+            .toc
+            .live()
+            .filter_map(|(_, entry)| entry.id.as_ref().try_into().ok())
+            .map(u64::from_be_bytes)
+            .collect();
+        ids.sort_unstable();
+        ids.dedup();
+        ids
+    }
+
+    /// Return the length of a chapter, in bytes.
+    ///
+    /// This doesn't require any IO.
+    pub fn chapter_len(&self, index: ChapterIndex) -> Result<u64> {
+        let toc_entry = self.toc.get_index(index)?;
+        Ok(toc_entry.span.as_ref().map_or(0, |span| span.length.get()))
+    }
+
+    /// Return the content type of a chapter.
+    ///
+    /// This returns `None` if the chapter was written without a content
+    /// type (see [`BookWriter::new_chapter_typed`]), or if `index` is
+    /// invalid.
+    pub fn chapter_content_type(&self, index: ChapterIndex) -> Option<u32> {
+        self.toc.get_index(index).ok()?.content_type
+    }
+
+    /// Return the location of a chapter within the file.
+    ///
+    /// This returns the chapter's `(offset, length)` in bytes. Empty
+    /// chapters don't occupy any space in the file, so `None` is
+    /// returned for them (and for an invalid `index`).
+    pub fn chapter_span(&self, index: ChapterIndex) -> Option<(u64, u64)> {
+        let toc_entry = self.toc.get_index(index).ok()?;
+        let span = toc_entry.span.as_ref()?;
+        Some((span.offset, span.length.get()))
+    }
+
+    /// Find a chapter by its id.
+    ///
+    /// If more than one chapter has this id, the index of the first one
+    /// is returned. Use [`find_all_chapters`] to find all of them.
+    ///
+    /// If the book was written with [`BookBuilder::sorted_ids`], this
+    /// uses a binary search; otherwise it scans the whole TOC.
+    ///
+    /// [`find_all_chapters`]: Self::find_all_chapters
+    pub fn find_chapter<Id>(&self, id: Id) -> Option<ChapterIndex>
+    where
+        Id: Into<ChapterId>,
+    {
+        let id: ChapterId = id.into();
+        if self.header.flags & FLAG_SORTED_IDS != 0 {
+            return self.toc.find_sorted(&id.0);
+        }
+        self.toc
+            .live()
+            .find(|(_, entry)| entry.id == id.0)
+            .map(|(index, _)| ChapterIndex(index))
+    }
+
+    /// Find a chapter by its name.
+    ///
+    /// If more than one chapter has this name, the index of the first
+    /// one is returned. Chapters written without a name will never match.
+    pub fn find_chapter_by_name(&self, name: &str) -> Option<ChapterIndex> {
+        self.toc.find_name(name)
+    }
+
+    /// Find the most recently written chapter with a given id.
+    ///
+    /// Chapters are listed in the TOC in the order they were written, so
+    /// this returns the last matching entry. If a book is used as an
+    /// append-only log, rewriting a chapter id gives last-write-wins
+    /// semantics.
+    pub fn find_latest_chapter<Id>(&self, id: Id) -> Option<ChapterIndex>
+    where
+        Id: Into<ChapterId>,
+    {
+        let id: ChapterId = id.into();
+        self.toc.find_all(&id.0).last()
+    }
+
+    /// Find all chapters with a given id.
+    ///
+    /// The chapter indices are returned in file order. If no chapter has
+    /// this id, the result will be empty.
+    pub fn find_all_chapters<Id>(&self, id: Id) -> Vec<ChapterIndex>
+    where
+        Id: Into<ChapterId>,
+    {
+        let id: ChapterId = id.into();
+        self.toc.find_all(&id.0).collect()
+    }
+
+    /// Check whether any chapter has a given id.
+    pub fn contains_chapter<Id>(&self, id: Id) -> bool
+    where
+        Id: Into<ChapterId>,
+    {
+        self.find_chapter(id).is_some()
+    }
+
+    /// Count the chapters with a given id.
+    pub fn count_chapters<Id>(&self, id: Id) -> usize
+    where
+        Id: Into<ChapterId>,
+    {
+        let id: ChapterId = id.into();
+        self.toc.find_all(&id.0).count()
+    }
+
+    /// Enable or disable chapter checksum verification.
+    ///
+    /// When enabled, reading an entire chapter will compare its contents
+    /// against the checksum stored in the table of contents, and return
+    /// [`BookError::ChecksumMismatch`] if they differ. Chapters written
+    /// without a checksum are never verified.
+    ///
+    /// Verification is disabled by default. It has no effect on
+    /// chapters read incrementally via a `BoundedReader`.
+    pub fn verify_checksums(&mut self, verify: bool) {
+        self.verify_checksums = verify;
+    }
+
+    /// Verify chapter contents, if checksum verification is enabled.
+    fn check_chapter(&self, toc_entry: &TocEntry, buf: &[u8]) -> Result<()> {
+        if self.verify_checksums {
+            toc_entry.verify_checksum(buf)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl<R> Book<R>
+where
+    R: TryClone,
+{
+    /// Create another `Book` reading from a duplicate of this stream.
+    ///
+    /// The header and TOC are copied from this `Book`, so nothing is
+    /// re-read from the stream. This allows chapters to be read from
+    /// several threads, each with its own `Book`, without locking.
+    ///
+    /// The checksum verification setting is also copied.
+    pub fn try_clone_reader(&self) -> Result<Book<R>> {
+        let reader = self.reader.try_clone()?;
+        Ok(Book {
+            reader,
+            header: self.header.clone(),
+            toc: self.toc.clone(),
+            toc_offset: self.toc_offset,
+            verify_checksums: self.verify_checksums,
+            cache: self.cache.as_ref().map(|cache| {
+                let capacity = lock_cache(cache).capacity();
+                Mutex::new(ChapterCache::new(capacity))
+            }),
+            versions: self.versions,
+        })
+    }
+}
+
+#[cfg(target_family = "unix")]
+impl<R> Book<R>
+where
+    R: std::os::unix::fs::FileExt,
+{
+    /// Create a shared reader object.
+    ///
+    /// This returns a BoundedReader that does not implement `Seek` or `Read`, only
+    /// [`read_at`] and [`read_exact_at`]
+    /// This means it can be used via a shared reference.
+    ///
+    /// If the `Seek` and `Read` traits are required, use [`exclusive_chapter_reader`] instead.
+    ///
+    /// Positioned reads don't use or modify the stream position, so readers
+    /// for different chapters can be used in any order.
+    ///
+    /// [`read_at`]: crate::BoundedReader::read_at
+    /// [`read_exact_at`]: crate::BoundedReader::read_exact_at
+    /// [`exclusive_chapter_reader`]: Self::exclusive_chapter_reader
+    ///
+    pub fn chapter_reader<Id>(&self, index: Id) -> Result<BoundedReader<&R>>
+    where
+        Id: Into<ChapterId>,
+    {
+        let toc_entry = self.toc.get_chapter(index)?;
+        Ok(self.entry_reader(toc_entry))
+    }
+
+    /// Create a shared reader for a particular TOC entry.
+    fn entry_reader(&self, toc_entry: &TocEntry) -> BoundedReader<&R> {
+        match &toc_entry.span {
+            None => {
+                // If the span is empty, no IO is necessary; just return
+                // an empty Vec.
+                BoundedReader::empty(&self.reader)
+            }
+            Some(span) => BoundedReader::new(&self.reader, span.offset, span.length.into()),
+        }
+    }
+
+    /// Read all bytes in a chapter.
+    ///
+    /// This is the same thing as calling [`chapter_reader`] followed by
+    /// [`read_exact_at`].
+    ///
+    /// [`chapter_reader`]: Self::chapter_reader
+    /// [`read_exact_at`]: crate::BoundedReader::read_exact_at
+    ///
+    /// If the book has a cache (see [`with_cache`]), it's used.
+    ///
+    /// [`with_cache`]: Self::with_cache
+    pub fn read_chapter<Id>(&self, id: Id) -> Result<Box<[u8]>>
+    where
+        Id: Into<ChapterId>,
+    {
+        let index = self.find_chapter(id).ok_or(BookError::NoChapter)?;
+        if let Some(data) = self.cache_get(index) {
+            return Ok(data);
+        }
+        let data = self.read_chapter_at(index)?;
+        self.cache_insert(index, &data);
+        Ok(data)
+    }
+
+    /// Read all bytes in a chapter, bypassing the cache.
+    ///
+    /// This is the same as [`read_chapter`], but the cache (if any) is
+    /// neither consulted nor updated.
+    ///
+    /// [`read_chapter`]: Self::read_chapter
+    pub fn read_chapter_uncached<Id>(&self, id: Id) -> Result<Box<[u8]>>
+    where
+        Id: Into<ChapterId>,
+    {
+        let index = self.find_chapter(id).ok_or(BookError::NoChapter)?;
+        self.read_chapter_at(index)
+    }
+
+    /// Read all bytes in the chapter at `index`, using positioned reads.
+    fn read_chapter_at(&self, index: ChapterIndex) -> Result<Box<[u8]>> {
+        let toc_entry = self.toc.get_index(index)?;
+        let reader = self.entry_reader(toc_entry);
+        let chapter_len: usize = reader.len().try_into().unwrap();
+        let mut buf = vec![0u8; chapter_len];
+        reader.read_exact_at(&mut buf, 0)?;
+        self.check_chapter(toc_entry, &buf)?;
+        Ok(buf.into_boxed_slice())
+    }
+}
+
+impl<R> Book<R>
+where
+    R: Read + Seek,
+{
+    /// Create a new Book from a stream.
+    ///
+    /// This call will attempt to read the file header and table of contents.
+    /// It may fail due to IO errors while reading, or invalid file data.
+    /// If a chapter's span lies outside the region between the header and
+    /// the TOC, [`BookError::SpanOutOfBounds`] is returned.
+    ///
+    /// The stream must impl the `Read` and `Seek` traits (e.g. a `File`).
+    ///
+    pub fn new(mut reader: R) -> Result<Self> {
+        let (header, header_version) = read_header(&mut reader).context("reading header")?;
+        let mut book = Book::from_parts(reader, header, Toc::default());
+        book.versions.header = header_version;
+        book.load_toc()?;
+        Ok(book)
+    }
+
+    /// Create a new Book from a stream, without reading the TOC.
+    ///
+    /// Only the file header is read. The TOC will be read the first time
+    /// it's needed; see [`LazyBook`].
+    pub fn new_lazy(mut reader: R) -> Result<LazyBook<R>> {
+        let (header, header_version) = read_header(&mut reader).context("reading header")?;
+        let mut book = Book::from_parts(reader, header, Toc::default());
+        book.versions.header = header_version;
+        Ok(LazyBook {
+            book,
+            toc_loaded: false,
+        })
+    }
+
+    /// Read the TOC, replacing the current one.
+    ///
+    /// Chapter spans are checked against the region where chapters may
+    /// be stored, so that a corrupt TOC can't direct a chapter reader to
+    /// the header or TOC bytes.
+    fn load_toc(&mut self) -> Result<()> {
+        let start = data_start(&self.header);
+
+        // If the TOC was stored at the front of the file, read it from there.
+        // We don't know where the chapters end, as a forward-only stream
+        // can't find the end of the file.
+        if let Some((toc, toc_version)) =
+            read_front_toc(&mut self.reader, &self.header).context("reading TOC")?
+        {
+            check_span_bounds(&toc, start, u64::MAX)?;
+            self.toc = toc;
+            self.versions.toc = toc_version;
+            return Ok(());
+        }
+
+        let (toc, toc_version, toc_offset) =
+            read_end_toc(&mut self.reader, &self.header).context("reading TOC")?;
+        check_span_bounds(&toc, start, toc_offset)?;
+        self.toc = toc;
+        self.versions.toc = toc_version;
+        self.toc_offset = Some(toc_offset);
+        Ok(())
+    }
+
+    /// Create a new Book from a stream, checking the user magic number.
+    ///
+    /// This is the same as [`new`](Self::new), but returns
+    /// [`BookError::WrongUserMagic`] if the file's magic number isn't
+    /// `user_magic`.
+    pub fn new_expecting(reader: R, user_magic: u32) -> Result<Self> {
+        let book = Self::new(reader)?;
+        if book.magic() != user_magic {
+            return Err(BookError::WrongUserMagic {
+                expected: user_magic,
+                found: book.magic(),
+            });
+        }
+        Ok(book)
+    }
+
+    /// Check whether a chapter exists.
+    pub fn has_chapter<Id>(&self, id: Id) -> bool
+    where
+        Id: Into<ChapterId>,
+    {
+        self.toc.get_chapter(id).is_ok()
+    }
+
+    /// Read a chapter, with seeking.
+    ///
+    /// This returns a `BoundedReader` that can read and seek within the chapter
+    /// (assuming the underlying Book implements `Read + Seek`).
+    /// The `Seek` and `Read` traits require exclusive access. For a shared reader,
+    /// use [`chapter_reader`] instead.
+    ///
+    /// The reader doesn't depend on the stream position left behind by
+    /// earlier reads. On return, the stream is positioned at the start of
+    /// the chapter; if the chapter is empty, the stream is not moved.
+    ///
+    /// [`chapter_reader`]: Self::chapter_reader
+    pub fn exclusive_chapter_reader<Id>(&mut self, id: Id) -> Result<BoundedReader<&mut R>>
+    where
+        Id: Into<ChapterId>,
+    {
+        let toc_entry = self.toc.get_chapter(id)?;
+        exclusive_entry_reader(&mut self.reader, toc_entry)
+    }
+
+    /// Read each chapter in turn, with seeking.
+    ///
+    /// This returns a [`ChapterReaders`], which yields each chapter's id
+    /// and a reader for its contents, in TOC order. See its documentation
+    /// for why it isn't an `Iterator`.
+    pub fn chapter_readers(&mut self) -> ChapterReaders<'_, R> {
+        ChapterReaders {
+            book: self,
+            next: 0,
+        }
+    }
+
+    /// Read all bytes in a chapter.
+    ///
+    /// This is the same thing as calling [`exclusive_chapter_reader`]
+    /// followed by [`read_to_end`]. It can be used on anything that implements
+    /// `Read + Seek`, but as a result it requires exclusive access via a
+    /// mutable reference.
+    ///
+    /// [`exclusive_chapter_reader`]: Self::exclusive_chapter_reader
+    ///[`read_to_end`]: std::io::Read::read_to_end
+    ///
+    /// If the book has a cache (see [`with_cache`]), it's used.
+    ///
+    /// [`with_cache`]: Self::with_cache
+    pub fn exclusive_read_chapter<Id>(&mut self, index: Id) -> Result<Box<[u8]>>
+    where
+        Id: Into<ChapterId>,
+    {
+        let index = self.find_chapter(index).ok_or(BookError::NoChapter)?;
+        self.read_index_cached(index)
+    }
+
+    /// Read all bytes in every chapter with a given id.
+    ///
+    /// The chapters are returned in file order; empty chapters are
+    /// included as empty buffers. If no chapter has this id, the result
+    /// is empty. Like [`exclusive_read_chapter`], this uses the cache.
+    ///
+    /// [`exclusive_read_chapter`]: Self::exclusive_read_chapter
+    pub fn read_all_chapters<Id>(&mut self, id: Id) -> Result<Vec<Box<[u8]>>>
+    where
+        Id: Into<ChapterId>,
+    {
+        self.find_all_chapters(id)
+            .into_iter()
+            .map(|index| self.read_index_cached(index))
+            .collect()
+    }
+
+    /// Read every chapter in turn, passing its id and contents to `f`.
+    ///
+    /// Chapters are visited in TOC order. This stops at the first error,
+    /// whether it came from reading a chapter or from `f`, and returns it.
+    /// Checksums are verified as usual, but the cache isn't used.
+    pub fn try_for_each_chapter<F>(&mut self, mut f: F) -> Result<()>
+    where
+        F: FnMut(&[u8], &[u8]) -> Result<()>,
+    {
+        let mut buf = vec![];
+        for index in 0..self.len() {
+            let index = ChapterIndex(index);
+            if self.toc.0[index.0].removed {
+                continue;
+            }
+            self.read_chapter_into(index, &mut buf)?;
+            let id = &self.toc.get_index(index)?.id;
+            f(id, &buf)?;
+        }
+        Ok(())
+    }
+
+    /// Read the book's manifest.
+    ///
+    /// This reads the chapter with id [`MANIFEST_CHAPTER_ID`], written by
+    /// [`BookWriter::set_manifest`]. If there are several (e.g. after
+    /// appending), the most recent is returned. If the book has no
+    /// manifest, `None` is returned.
+    pub fn manifest(&mut self) -> Result<Option<Box<[u8]>>> {
+        match self.find_latest_chapter(MANIFEST_CHAPTER_ID) {
+            Some(index) => self.read_index_cached(index).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Read all bytes in a chapter, bypassing the cache.
+    ///
+    /// This is the same as [`exclusive_read_chapter`], but the cache (if
+    /// any) is neither consulted nor updated.
+    ///
+    /// [`exclusive_read_chapter`]: Self::exclusive_read_chapter
+    pub fn exclusive_read_chapter_uncached<Id>(&mut self, index: Id) -> Result<Box<[u8]>>
+    where
+        Id: Into<ChapterId>,
+    {
+        let index = self.find_chapter(index).ok_or(BookError::NoChapter)?;
+        self.read_index(index)
+    }
+
+    /// Read all bytes in a chapter into an existing buffer.
+    ///
+    /// `buf` is cleared first, and then grown to the chapter length if
+    /// needed, so a buffer reused across many chapters only allocates when
+    /// a chapter is larger than any before it. Returns the number of bytes
+    /// read. Checksums are verified as by [`exclusive_read_chapter`], but
+    /// the cache (if any) isn't used.
+    ///
+    /// [`exclusive_read_chapter`]: Self::exclusive_read_chapter
+    pub fn read_chapter_into(&mut self, index: ChapterIndex, buf: &mut Vec<u8>) -> Result<usize> {
+        let toc_entry = self.toc.get_index(index)?;
+        buf.clear();
+        let mut reader = exclusive_entry_reader(&mut self.reader, toc_entry)?;
+        // The length is only a hint; if it doesn't fit, read_to_end will
+        // fail when the buffer can't grow.
+        buf.reserve(reader.len().try_into().unwrap_or(0));
+        let length = reader.read_to_end(buf)?;
+        self.check_chapter(toc_entry, buf)?;
+        Ok(length)
+    }
+
+    /// Read a chapter containing a single serialized value.
+    ///
+    /// The chapter must have been written by [`BookWriter::write_chapter_value`].
+    pub fn read_chapter_value<T>(&mut self, index: ChapterIndex) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        let buf = self.read_index(index)?;
+        let value = serde_cbor::from_slice(&buf)?;
+        Ok(value)
+    }
+
+    /// Read the serialized TOC from the end of the file.
+    ///
+    /// This returns the TOC bytes exactly as stored, not including the
+    /// checksum and TOC length that follow them. This can be used to
+    /// fingerprint or cache the TOC; the bytes can be parsed with
+    /// [`Toc::from_bytes`]. If the book has a TOC checksum, it's verified.
+    /// A compressed TOC is decompressed first.
+    ///
+    /// Books written with a front TOC also store a copy at the end of the
+    /// file; that copy is the one returned. The stream position afterwards
+    /// is unspecified.
+    pub fn raw_toc(&mut self) -> Result<Box<[u8]>> {
+        let (buf, _) = read_end_toc_bytes(&mut self.reader, &self.header)?;
+        Ok(buf.into_boxed_slice())
+    }
+
+    /// Read part of a chapter.
+    ///
+    /// This reads `len` bytes, starting at offset `start` within the chapter,
+    /// without reading the rest of the chapter. If the range extends past
+    /// the end of the chapter, [`BookError::OutOfBounds`] is returned.
+    ///
+    /// Checksums can't be verified when reading part of a chapter.
+    pub fn read_chapter_range(
+        &mut self,
+        index: ChapterIndex,
+        start: u64,
+        len: u64,
+    ) -> Result<Box<[u8]>> {
+        let toc_entry = self.toc.get_index(index)?;
+        let mut reader = exclusive_entry_reader(&mut self.reader, toc_entry)?;
+        let chapter_len = reader.len();
+        let in_bounds = start
+            .checked_add(len)
+            .filter(|&end| end <= chapter_len)
+            .is_some();
+        if !in_bounds {
+            return Err(BookError::OutOfBounds {
+                start,
+                len,
+                chapter_len,
+            });
+        }
+        let buf_len: usize = len.try_into().map_err(|_| BookError::OutOfBounds {
+            start,
+            len,
+            chapter_len,
+        })?;
+        let mut buf = vec![0u8; buf_len];
+        reader.seek(SeekFrom::Start(start))?;
+        reader.read_exact(&mut buf)?;
+        Ok(buf.into_boxed_slice())
+    }
+
+    /// Read a chapter, decompressing it if necessary.
+    ///
+    /// If the chapter was written by [`BookWriter::new_chapter_compressed`],
+    /// the returned reader decompresses the chapter as it's read.
+    /// Otherwise, this is the same as [`exclusive_chapter_reader`].
+    ///
+    /// If the chapter was compressed with a codec that isn't enabled,
+    /// [`BookError::UnsupportedCodec`] is returned.
+    ///
+    /// [`exclusive_chapter_reader`]: Self::exclusive_chapter_reader
+    pub fn decoded_chapter_reader(&mut self, index: ChapterIndex) -> Result<Box<dyn Read + '_>> {
+        let toc_entry = self.toc.get_index(index)?;
+        let reader = exclusive_entry_reader(&mut self.reader, toc_entry)?;
+        compression::decoder(toc_entry.codec, reader)
+    }
+
+    /// Read a chapter's stored bytes, without decompressing them.
+    ///
+    /// This is the counterpart to [`decoded_chapter_reader`]: for a
+    /// compressed chapter, the reader returns the compressed bytes exactly
+    /// as stored. That's useful for tools that move compressed chapters
+    /// around without recompressing them; see also
+    /// [`BookWriter::copy_chapter_from`], which preserves the codec.
+    ///
+    /// [`decoded_chapter_reader`]: Self::decoded_chapter_reader
+    pub fn chapter_reader_raw(&mut self, index: ChapterIndex) -> Result<BoundedReader<&mut R>> {
+        let toc_entry = self.toc.get_index(index)?;
+        exclusive_entry_reader(&mut self.reader, toc_entry)
+    }
+
+    /// Read several chapters as one continuous stream.
+    ///
+    /// The returned reader yields the stored bytes of each chapter in
+    /// `indices`, in order, moving to the next chapter when one ends, so
+    /// `read_to_end` returns their concatenation. Empty chapters
+    /// contribute nothing. Every index is checked before anything is
+    /// read; if one is out of range, [`BookError::IndexOutOfRange`] is
+    /// returned.
+    ///
+    /// As with [`chapter_reader_raw`], compressed chapters aren't
+    /// decompressed, and checksums aren't verified.
+    ///
+    /// [`chapter_reader_raw`]: Self::chapter_reader_raw
+    pub fn concat_reader(&mut self, indices: &[ChapterIndex]) -> Result<impl Read + '_> {
+        let mut spans = Vec::with_capacity(indices.len());
+        for &index in indices {
+            let toc_entry = self.toc.get_index(index)?;
+            if let Some(span) = &toc_entry.span {
+                spans.push((span.offset, span.length.get()));
+            }
+        }
+        Ok(ConcatReader {
+            reader: &mut self.reader,
+            spans: spans.into_iter(),
+            remaining: 0,
+        })
+    }
+
+    /// Read a chapter's stored bytes, verifying its checksum as they're read.
+    ///
+    /// Unlike [`exclusive_read_chapter`], the chapter is never held in
+    /// memory all at once: the checksum is computed incrementally, and a
+    /// mismatch is reported by the `read` call that reaches the end of the
+    /// chapter. See [`CheckedChapterReader`] for details. As with
+    /// [`chapter_reader_raw`], compressed chapters aren't decompressed.
+    ///
+    /// [`exclusive_read_chapter`]: Self::exclusive_read_chapter
+    /// [`chapter_reader_raw`]: Self::chapter_reader_raw
+    pub fn chapter_reader_checked(
+        &mut self,
+        index: ChapterIndex,
+    ) -> Result<CheckedChapterReader<&mut R>> {
+        let toc_entry = self.toc.get_index(index)?;
+        let reader = exclusive_entry_reader(&mut self.reader, toc_entry)?;
+        Ok(CheckedChapterReader::new(
+            reader,
+            toc_entry.checksum,
+            toc_entry.id.clone(),
+        ))
+    }
+
+    /// Copy a chapter's contents to a writer, decompressing if necessary.
+    ///
+    /// The chapter is read through [`decoded_chapter_reader`] and copied
+    /// using a fixed-size buffer, so it never needs to be held in memory
+    /// all at once. On success, the number of bytes written to `out` is
+    /// returned. To copy the stored bytes without decompressing them, use
+    /// [`chapter_reader_raw`] with `std::io::copy`.
+    ///
+    /// Checksums aren't verified, since the chapter is never complete in
+    /// memory; see [`verify`] instead.
+    ///
+    /// [`decoded_chapter_reader`]: Self::decoded_chapter_reader
+    /// [`chapter_reader_raw`]: Self::chapter_reader_raw
+    /// [`verify`]: Self::verify
+    pub fn extract_chapter<Wr>(&mut self, index: ChapterIndex, out: &mut Wr) -> Result<u64>
+    where
+        Wr: Write,
+    {
+        let mut reader = self.decoded_chapter_reader(index)?;
+        let mut buf = vec![0u8; COPY_BUFFER_SIZE];
+        let mut copied = 0u64;
+        loop {
+            let bytes_read = match reader.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e).context("reading chapter"),
+            };
+            out.write_all(&buf[..bytes_read])?;
+            copied += bytes_read as u64;
+        }
+        Ok(copied)
+    }
+
+    /// Read and decrypt an encrypted chapter.
+    ///
+    /// The chapter must have been written by
+    /// [`BookWriter::new_chapter_encrypted`]. The entire chapter is read
+    /// and authenticated before any plaintext is returned. If the key is
+    /// wrong or the chapter has been modified, [`BookError::DecryptionFailed`]
+    /// is returned. If the chapter isn't encrypted,
+    /// [`BookError::NotEncrypted`] is returned.
+    #[cfg(feature = "encryption")]
+    pub fn chapter_reader_decrypted(
+        &mut self,
+        index: ChapterIndex,
+        key: &[u8; 32],
+    ) -> Result<Cursor<Box<[u8]>>> {
+        let toc_entry = self.toc.get_index(index)?;
+        let encryption = toc_entry
+            .encryption
+            .as_ref()
+            .ok_or(BookError::NotEncrypted)?;
+        let mut buf = vec![];
+        let mut reader = exclusive_entry_reader(&mut self.reader, toc_entry)?;
+        reader.read_to_end(&mut buf)?;
+        self.check_chapter(toc_entry, &buf)?;
+        encryption::decrypt(key, &toc_entry.id, encryption, &mut buf)?;
+        Ok(Cursor::new(buf.into_boxed_slice()))
+    }
+
+    /// Check the structure and contents of the whole book.
+    ///
+    /// This verifies the header magic number and the TOC at the end of
+    /// the file, and checks that every chapter lies between the header
+    /// and the TOC without overlapping any other chapter. Then every
+    /// chapter with a checksum is read and verified, regardless of the
+    /// [`verify_checksums`] setting.
+    ///
+    /// The first problem found is returned.
+    ///
+    /// [`verify_checksums`]: Self::verify_checksums
+    pub fn verify(&mut self) -> Result<()> {
+        check_magic(self.header.bookwriter_magic)?;
+        let (_, _, toc_offset) = read_end_toc(&mut self.reader, &self.header)?;
+        check_spans(&self.toc, data_start(&self.header), toc_offset)?;
+
+        for toc_entry in self.toc.iter() {
+            if toc_entry.checksum.is_none() {
+                continue;
+            }
+            let mut buf = vec![];
+            let mut reader = exclusive_entry_reader(&mut self.reader, toc_entry)?;
+            reader.read_to_end(&mut buf)?;
+            toc_entry.verify_checksum(&buf)?;
+        }
+        Ok(())
+    }
+
+    /// Read all bytes in the chapter at `index`, using the cache if any.
+    fn read_index_cached(&mut self, index: ChapterIndex) -> Result<Box<[u8]>> {
+        if let Some(data) = self.cache_get(index) {
+            return Ok(data);
+        }
+        let data = self.read_index(index)?;
+        self.cache_insert(index, &data);
+        Ok(data)
+    }
+
+    /// Read all bytes in the chapter at `index`.
+    fn read_index(&mut self, index: ChapterIndex) -> Result<Box<[u8]>> {
+        let mut buf = vec![];
+        self.read_chapter_into(index, &mut buf)?;
+        Ok(buf.into_boxed_slice())
+    }
+}
+
+impl Book<Cursor<Vec<u8>>> {
+    /// Open a book stored in memory, taking ownership of its bytes.
+    ///
+    /// This is the same as `Book::new(Cursor::new(bytes.into()))`.
+    pub fn from_bytes(bytes: impl Into<Vec<u8>>) -> Result<Self> {
+        Book::new(Cursor::new(bytes.into()))
+    }
+}
+
+impl<'a> Book<Cursor<&'a [u8]>> {
+    /// Open a book stored in memory, borrowing its bytes.
+    ///
+    /// This is the same as `Book::new(Cursor::new(bytes))`.
+    pub fn from_slice(bytes: &'a [u8]) -> Result<Self> {
+        Book::new(Cursor::new(bytes))
+    }
+
+    /// Check that a complete book in memory can be parsed.
+    ///
+    /// This parses the header and the TOC at the end of the file (and the
+    /// front TOC, if there is one), and checks that every chapter lies
+    /// between the header and the TOC without overlapping another. Chapter
+    /// contents and checksums aren't examined; see [`verify`] for that.
+    ///
+    /// This is meant as an entry point for fuzzing: it returns an error
+    /// for any malformed input, and never panics.
+    ///
+    /// [`verify`]: Self::verify
+    pub fn parse(bytes: &[u8]) -> Result<()> {
+        let mut reader = Cursor::new(bytes);
+        let (header, _) = read_header(&mut reader).context("reading header")?;
+        let (toc, _, toc_offset) = read_end_toc(&mut reader, &header).context("reading TOC")?;
+        let start = data_start(&header);
+        check_spans(&toc, start, toc_offset)?;
+        if let Some((front_toc, _)) = read_front_toc(&mut reader, &header).context("reading TOC")? {
+            check_spans(&front_toc, start, toc_offset)?;
+        }
+        Ok(())
+    }
+}
+
+/// A sequence of readers, one for each chapter in a [`Book`].
+///
+/// This is created by [`Book::chapter_readers`]. Each reader borrows the
+/// book's stream mutably, so only one can exist at a time. An `Iterator`
+/// can't express that, because it would allow the caller to hold several
+/// items at once; instead, [`next()`] borrows the `ChapterReaders`, and
+/// the previous reader must be dropped before it's called again. Use it
+/// with `while let Some(result) = readers.next()`.
+///
+/// [`next()`]: Self::next
+#[derive(Debug)]
+pub struct ChapterReaders<'a, R> {
+    book: &'a mut Book<R>,
+    next: usize,
+}
+
+impl<R> ChapterReaders<'_, R>
+where
+    R: Read + Seek,
+{
+    /// Return the next chapter's id and a reader for its contents.
+    ///
+    /// Returns `None` after the last chapter. If an error is returned,
+    /// later chapters can still be read.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Option<Result<(&[u8], BoundedReader<&mut R>)>> {
+        let book = &mut *self.book;
+        let toc_entry = book.toc.0.get(self.next)?;
+        self.next += 1;
+        let reader = exclusive_entry_reader(&mut book.reader, toc_entry);
+        Some(reader.map(|reader| (toc_entry.id.as_ref(), reader)))
+    }
+}
+
+/// A `Book` whose TOC hasn't been read yet.
+///
+/// This is created by [`Book::new_lazy`]. The file header is available
+/// immediately; the TOC is read the first time a chapter is looked up,
+/// and is kept for later use. This avoids parsing a large TOC if it's
+/// never needed.
+#[derive(Debug)]
+pub struct LazyBook<R> {
+    book: Book<R>,
+    toc_loaded: bool,
+}
+
+impl<R> LazyBook<R> {
+    /// Return the file's magic number.
+    pub fn magic(&self) -> u32 {
+        self.book.magic()
+    }
+
+    /// Return the user metadata stored in the file header.
+    pub fn user_meta(&self) -> &[u8] {
+        self.book.user_meta()
+    }
+
+    /// Return the file header.
+    pub fn header(&self) -> &FileHeader {
+        self.book.header()
+    }
+}
+
+impl<R> LazyBook<R>
+where
+    R: Read + Seek,
+{
+    /// Return the `Book`, reading the TOC if necessary.
+    ///
+    /// The `Book` can be used to access any chapter.
+    pub fn book(&mut self) -> Result<&mut Book<R>> {
+        if !self.toc_loaded {
+            self.book.load_toc()?;
+            self.toc_loaded = true;
+        }
+        Ok(&mut self.book)
+    }
+
+    /// Convert to a `Book`, reading the TOC if necessary.
+    pub fn into_book(mut self) -> Result<Book<R>> {
+        self.book()?;
+        Ok(self.book)
+    }
+
+    /// Find a chapter by its id, reading the TOC if necessary.
+    ///
+    /// See [`Book::find_chapter`].
+    pub fn find_chapter<Id>(&mut self, id: Id) -> Result<Option<ChapterIndex>>
+    where
+        Id: Into<ChapterId>,
+    {
+        Ok(self.book()?.find_chapter(id))
+    }
+
+    /// Read a chapter, with seeking, reading the TOC if necessary.
+    ///
+    /// See [`Book::exclusive_chapter_reader`].
+    pub fn exclusive_chapter_reader<Id>(&mut self, id: Id) -> Result<BoundedReader<&mut R>>
+    where
+        Id: Into<ChapterId>,
+    {
+        self.book()?.exclusive_chapter_reader(id)
+    }
+
+    /// Read all bytes in a chapter, reading the TOC if necessary.
+    ///
+    /// See [`Book::exclusive_read_chapter`].
+    pub fn exclusive_read_chapter<Id>(&mut self, id: Id) -> Result<Box<[u8]>>
+    where
+        Id: Into<ChapterId>,
+    {
+        self.book()?.exclusive_read_chapter(id)
+    }
+}
+
+/// Format a chapter id for [`Book::describe`].
+fn describe_id(id: &[u8]) -> String {
+    if let Ok(bytes) = id.try_into() {
+        u64::from_be_bytes(bytes).to_string()
+    } else if let Ok(text) = std::str::from_utf8(id) {
+        format!("{:?}", text)
+    } else {
+        id.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+}
+
+/// Lock a chapter cache.
+///
+/// The cache is never left in an inconsistent state, so a poisoned lock
+/// can be used as-is.
+fn lock_cache(cache: &Mutex<ChapterCache>) -> MutexGuard<'_, ChapterCache> {
+    cache.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Read and verify the file header.
+fn read_header<R>(reader: &mut R) -> Result<(FileHeader, u16)>
+where
+    R: Read + Seek,
+{
+    // Read the smallest possible header block from the beginning of the
+    // file. We don't seek to the end to find the file length, because a
+    // forward-only stream can't do that.
+    let mut header_buf = Vec::with_capacity(MIN_HEADER_SIZE);
+    reader.seek(SeekFrom::Start(0))?;
+    (&mut *reader)
+        .take(MIN_HEADER_SIZE as u64)
+        .read_to_end(&mut header_buf)?;
+    if header_buf.len() < MIN_HEADER_SIZE {
+        return Err(BookError::TooSmall {
+            len: header_buf.len() as u64,
+        });
+    }
+
+    // A large header may extend past the first block.
+    let max_rest = (MAX_HEADER_SIZE - MIN_HEADER_SIZE) as u64;
+    let mut rest = (&mut *reader).take(max_rest);
+
+    // If the header can't be parsed, this probably isn't a bookfile.
+    let mut data_src = CborData::new(header_buf.as_slice().chain(&mut rest));
+    let (header, header_version): (FileHeader, u16) = match expect_versioned(&mut data_src) {
+        Ok(result) => result,
+        Err(CborDataError::Serializer) => {
+            return Err(BookError::BadMagic {
+                expected: BOOK_V1_MAGIC,
+                found: 0,
+            })
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let read_len = MIN_HEADER_SIZE as u64 + (max_rest - rest.limit());
+
+    // Verify magic numbers
+    check_magic(header.bookwriter_magic)?;
+
+    // Headers written before the size was recorded were upgraded with
+    // the default size, which is what those writers always used.
+    check_header_size(header.header_size)?;
+    if header.flags & FLAG_FRONT_TOC != 0 {
+        check_front_toc_size(&header)?;
+    }
+    if read_len > header.header_size {
+        return Err(BookError::HeaderTooLarge {
+            size: read_len.try_into().unwrap(),
+            max: header.header_size.try_into().unwrap(),
+        });
+    }
+
+    // Skip the padding at the end of the header block.
+    let padding = header.header_size - read_len;
+    let skipped = io::copy(&mut reader.take(padding), &mut io::sink())?;
+    if skipped < padding {
+        return Err(BookError::TooSmall {
+            len: read_len + skipped,
+        });
+    }
+    Ok((header, header_version))
+}
+
+/// Check that a format magic number is one this library can read.
+///
+/// Magic numbers from a later format version are reported as
+/// [`BookError::UnsupportedVersion`]; anything else isn't a bookfile.
+fn check_magic(magic: u32) -> Result<()> {
+    match magic {
+        BOOK_V1_MAGIC => Ok(()),
+        // The v2 layout isn't defined yet.
+        BOOK_V2_MAGIC => Err(BookError::UnsupportedVersion { magic }),
+        _ if magic & 0xFFFF_0000 == BOOK_MAGIC_FAMILY => {
+            Err(BookError::UnsupportedVersion { magic })
+        }
+        _ => Err(BookError::BadMagic {
+            expected: BOOK_V1_MAGIC,
+            found: magic,
+        }),
+    }
+}
+
+/// Read the TOC from the end of the file.
+///
+/// Returns the TOC, its on-disk version, and its offset.
+fn read_end_toc<R>(reader: &mut R, header: &FileHeader) -> Result<(Toc, u16, u64)>
+where
+    R: Read + Seek,
+{
+    let (toc_buf, toc_offset) = read_end_toc_bytes(reader, header)?;
+    let (toc, toc_version) = Toc::deserialize_versioned(toc_buf.as_slice())?;
+    Ok((toc, toc_version, toc_offset))
+}
+
+/// Read the serialized TOC from the end of the file.
+///
+/// If the header has `FLAG_TOC_CRC` set, the bytes are checked against
+/// the stored CRC-32. Returns the TOC bytes and their offset.
+fn read_end_toc_bytes<R>(reader: &mut R, header: &FileHeader) -> Result<(Vec<u8>, u64)>
+where
+    R: Read + Seek,
+{
+    let (toc_offset, toc_len) = locate_end_toc(reader, header)?;
+    let toc_len = toc_len.try_into().map_err(|_| BookError::CorruptToc)?;
+    let mut toc_buf = vec![0u8; toc_len];
+    reader.seek(SeekFrom::Start(toc_offset))?;
+    reader.read_exact(&mut toc_buf)?;
+    if header.flags & FLAG_TOC_CRC != 0 {
+        let crc = reader.read_u32::<BigEndian>()?;
+        if crc != Crc32::checksum(&toc_buf) {
+            return Err(BookError::CorruptToc);
+        }
+    }
+    if header.flags & FLAG_TOC_GZIP != 0 {
+        let toc_buf = decompress_toc(&toc_buf)?;
+        return Ok((toc_buf, toc_offset));
+    }
+    Ok((toc_buf, toc_offset))
+}
+
+/// Decompress a TOC stored with `FLAG_TOC_GZIP`.
+///
+/// The decompressed TOC is subject to the same size limit as an
+/// uncompressed one.
+fn decompress_toc(buf: &[u8]) -> Result<Vec<u8>> {
+    let decoder = compression::decoder(Some(Codec::Gzip), buf)?;
+    let mut toc_buf = Vec::new();
+    decoder
+        .take(MAX_TOC_SIZE + 1)
+        .read_to_end(&mut toc_buf)
+        .map_err(|_| BookError::CorruptToc)?;
+    if toc_buf.len() as u64 > MAX_TOC_SIZE {
+        return Err(BookError::CorruptToc);
+    }
+    Ok(toc_buf)
+}
+
+/// Find the TOC at the end of the file.
+///
+/// Returns the TOC offset and length.
+fn locate_end_toc<R>(reader: &mut R, header: &FileHeader) -> Result<(u64, u64)>
+where
+    R: Read + Seek,
+{
+    // Read the TOC length. For v1 it is the last 8 bytes of the file,
+    // optionally preceded by a 4-byte CRC.
+    let header_size = header.header_size;
+    let trailer_len = toc_trailer_len(header);
+    let file_len = reader.seek(SeekFrom::End(0))?;
+    if file_len < header_size + trailer_len {
+        return Err(BookError::TooSmall { len: file_len });
+    }
+    // Seek from the start, so the position can't be negative.
+    reader.seek(SeekFrom::Start(file_len - 8))?;
+    let toc_len = reader.read_u64::<BigEndian>().map_err(|e| {
+        // The file is long enough, so it must have been truncated
+        // while we were reading it.
+        if e.kind() == io::ErrorKind::UnexpectedEof {
+            BookError::CorruptToc
+        } else {
+            e.into()
+        }
+    })?;
+    if toc_len > MAX_TOC_SIZE {
+        return Err(BookError::CorruptToc);
+    }
+
+    // The TOC must lie between the header and the trailer.
+    let toc_offset = (file_len - trailer_len)
+        .checked_sub(toc_len)
+        .filter(|&offset| offset >= header_size)
+        .ok_or(BookError::CorruptToc)?;
+    Ok((toc_offset, toc_len))
+}
+
+/// Return the size of the trailer that follows the TOC at the end of the file.
+fn toc_trailer_len(header: &FileHeader) -> u64 {
+    if header.flags & FLAG_TOC_CRC != 0 {
+        12
+    } else {
+        8
+    }
+}
+
+/// Return the offset where chapter data begins.
+///
+/// If the offset would overflow, `u64::MAX` is returned, which leaves no
+/// room for any chapter.
+fn data_start(header: &FileHeader) -> u64 {
+    if header.flags & FLAG_FRONT_TOC != 0 {
+        header.header_size.saturating_add(header.front_toc_size)
+    } else {
+        header.header_size
+    }
+}
+
+/// Check that all chapter spans lie in `start..end`.
+fn check_span_bounds(toc: &Toc, start: u64, end: u64) -> Result<()> {
+    for entry in toc.iter() {
+        if let Some(span) = &entry.span {
+            let span_end = span.offset.checked_add(span.length.get());
+            let in_bounds = span_end.filter(|&span_end| span_end <= end).is_some();
+            if span.offset < start || !in_bounds {
+                return Err(BookError::SpanOutOfBounds {
+                    id: entry.id.clone(),
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Check that all chapter spans lie in `start..end` and don't overlap.
+///
+/// Chapters may share an identical span (see [`BookBuilder::dedup`]), but
+/// spans may not otherwise overlap.
+fn check_spans(toc: &Toc, start: u64, end: u64) -> Result<()> {
+    check_span_bounds(toc, start, end)?;
+
+    // All spans are in bounds, so their ends can't overflow.
+    let mut spans: Vec<_> = toc
+        .iter()
+        .filter_map(|entry| entry.span.as_ref().map(|span| (span, &entry.id)))
+        .collect();
+    spans.sort_by_key(|(span, _)| span.offset);
+    for pair in spans.windows(2) {
+        let (first, first_id) = pair[0];
+        let (second, second_id) = pair[1];
+        if first != second && first.offset + first.length.get() > second.offset {
+            return Err(BookError::SpanOverlap {
+                first: first_id.clone(),
+                second: second_id.clone(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Read the TOC from the region following the header, if there is one.
+///
+/// Returns the TOC and its on-disk version, or `None` if the header doesn't
+/// have `FLAG_FRONT_TOC` set, or if the writer never filled in the TOC region.
+///
+/// If the header has `FLAG_TOC_CRC` set, the TOC is checked against the
+/// CRC-32 stored after its length.
+fn read_front_toc<R>(reader: &mut R, header: &FileHeader) -> Result<Option<(Toc, u16)>>
+where
+    R: Read + Seek,
+{
+    if header.flags & FLAG_FRONT_TOC == 0 {
+        return Ok(None);
+    }
+    let region_offset = header.header_size;
+    reader.seek(SeekFrom::Start(region_offset))?;
+    let toc_len = reader.read_u64::<BigEndian>()?;
+    if toc_len == 0 {
+        return Ok(None);
+    }
+    if toc_len
+        > header
+            .front_toc_size
+            .saturating_sub(front_toc_overhead(header))
+    {
+        return Err(BookError::CorruptToc);
+    }
+    let crc = if header.flags & FLAG_TOC_CRC != 0 {
+        Some(reader.read_u32::<BigEndian>()?)
+    } else {
+        None
+    };
+    // The region size was checked when the header was read.
+    let mut toc_buf = vec![0u8; toc_len.try_into().map_err(|_| BookError::CorruptToc)?];
+    reader.read_exact(&mut toc_buf)?;
+    if let Some(crc) = crc {
+        if crc != Crc32::checksum(&toc_buf) {
+            return Err(BookError::CorruptToc);
+        }
+    }
+    let toc = Toc::deserialize_versioned(&toc_buf)?;
+    Ok(Some(toc))
+}
+
+/// Read a message, upgrading it to the latest version.
+///
+/// This is the same as `DataSourceExt::expect_message`, but also returns
+/// the version that was read, which is otherwise lost in the upgrade.
+fn expect_versioned<T, Src>(src: &mut Src) -> std::result::Result<(T, u16), Src::Error>
+where
+    Src: DataSource,
+    T: MessageId + UpgradeLatest,
+{
+    let header = src.read_header()?;
+    if header.msg_id() != T::MSG_ID {
+        return Err(src.unexpected_message::<T>(header.msg_id()));
+    }
+    let version = header.msg_ver();
+    let msg = T::upgrade_latest(src, header)?;
+    Ok((msg, version))
+}
+
+/// A reader over several spans in sequence.
+///
+/// See [`Book::concat_reader`].
+struct ConcatReader<'a, R> {
+    reader: &'a mut R,
+    /// The `(offset, length)` of each span not yet started.
+    spans: std::vec::IntoIter<(u64, u64)>,
+    /// The number of bytes left in the current span.
+    remaining: u64,
+}
+
+impl<R> Read for ConcatReader<'_, R>
+where
+    R: Read + Seek,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        while self.remaining == 0 {
+            match self.spans.next() {
+                None => return Ok(0),
+                Some((offset, length)) => {
+                    self.reader.seek(SeekFrom::Start(offset))?;
+                    self.remaining = length;
+                }
+            }
+        }
+        let bytes_read = (&mut *self.reader).take(self.remaining).read(buf)?;
+        if bytes_read == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "stream ended before the end of a chapter",
+            ));
+        }
+        self.remaining -= bytes_read as u64;
+        Ok(bytes_read)
+    }
+}
+
+/// Create an exclusive reader for a particular TOC entry.
+fn exclusive_entry_reader<'a, R>(
+    reader: &'a mut R,
+    toc_entry: &TocEntry,
+) -> Result<BoundedReader<&'a mut R>>
+where
+    R: Read + Seek,
+{
+    match &toc_entry.span {
+        None => {
+            // If the span is empty, no IO is necessary. The stream is
+            // left where it is, so that a forward-only stream isn't
+            // asked to seek backwards.
+            Ok(BoundedReader::empty(reader))
+        }
+        Some(span) => {
+            // Always seek, rather than trusting the stream position left
+            // by a previous reader.
+            reader
+                .seek(SeekFrom::Start(span.offset))
+                .context("reading chapter")?;
+            Ok(BoundedReader::new(reader, span.offset, span.length.into()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::ForwardReader;
+    use std::io::Cursor;
+
+    /// Append a serialized TOC and its trailer to a book.
+    ///
+    /// The book's header must have `FLAG_TOC_CRC` set.
+    fn append_toc(buf: &mut Vec<u8>, toc_buf: &[u8]) {
+        buf.extend_from_slice(toc_buf);
+        buf.write_u32::<BigEndian>(Crc32::checksum(toc_buf))
+            .unwrap();
+        buf.write_u64::<BigEndian>(toc_buf.len() as u64).unwrap();
+    }
+
+    #[test]
+    fn empty_book() {
+        let magic = 0x1234;
+        let mut cursor = Cursor::new(Vec::<u8>::new());
+        {
+            let book = BookWriter::new(&mut cursor, magic).unwrap();
+            book.close().unwrap();
+        }
+
+        // This file contains only a header, an empty TOC, a TOC checksum,
+        // and a TOC-length.
+        assert_eq!(cursor.get_ref().len(), 4096 + 9 + 4 + 8);
+
+        // If this succeeds then the header and TOC were parsed correctly.
+        let _ = Book::new(cursor).unwrap();
+    }
+
+    #[test]
+    fn close_with_size() {
+        let book = BookWriter::new_in_memory(0x1234).unwrap();
+        let (cursor, size) = book.close_with_size().unwrap();
+        assert_eq!(size, cursor.get_ref().len() as u64);
+    }
+
+    #[test]
+    fn oversized_header() {
+        let header = FileHeader::new(0x1234);
+        let header_buf = serialize_header(&header, HEADER_SIZE).unwrap();
+        assert_eq!(header_buf.len(), HEADER_SIZE);
+
+        let err = serialize_header(&header, 8).unwrap_err();
+        match err {
+            BookError::HeaderTooLarge { size, max } => {
+                assert!(size > 8);
+                assert_eq!(max, 8);
+            }
+            _ => panic!("unexpected error {:?}", err),
+        }
+    }
+
+    #[test]
+    fn user_meta() {
+        let magic = 0x1234;
+        let meta = b"schema=3;tool=bookfile-test";
+        let buffer = {
+            let buffer = Cursor::new(Vec::<u8>::new());
+            let mut book = BookWriter::new_with_meta(buffer, magic, meta).unwrap();
+            book.write_chapter(22, b"This is chapter 22").unwrap();
+            book.close().unwrap()
+        };
+        let mut book = Book::new(Cursor::new(buffer.into_inner())).unwrap();
+        assert_eq!(book.magic(), magic);
+        assert_eq!(book.user_meta(), meta);
+        let ch2 = book.exclusive_read_chapter(22).unwrap();
+        assert_eq!(ch2.as_ref(), b"This is chapter 22");
+
+        // Replace the header with a V1 header; it should have no metadata.
+        let toc = book.toc().clone();
+        let mut buf = Vec::<u8>::new();
+        let v1_header = FileHeaderV1 {
+            bookwriter_magic: BOOK_V1_MAGIC,
+            user_magic: magic,
+        };
+        let mut header_writer = CborData::new(&mut buf);
+        header_writer.write_message(&v1_header).unwrap();
+        buf.resize(HEADER_SIZE, 0);
+        buf.extend_from_slice(b"This is chapter 22");
+        let toc_buf = toc.to_bytes().unwrap();
+        buf.extend_from_slice(&toc_buf);
+        buf.write_u64::<BigEndian>(toc_buf.len() as u64).unwrap();
+        let mut book = Book::new(Cursor::new(buf)).unwrap();
+        assert_eq!(book.magic(), magic);
+        assert!(book.user_meta().is_empty());
+        assert_eq!(book.created_at(), None);
+        let ch2 = book.exclusive_read_chapter(22).unwrap();
+        assert_eq!(ch2.as_ref(), b"This is chapter 22");
+
+        // Metadata that doesn't fit in the header is an error.
+        let meta = vec![0xAA; HEADER_SIZE];
+        let err = BookWriter::new_with_meta(Vec::<u8>::new(), magic, &meta).unwrap_err();
+        assert!(matches!(err, BookError::HeaderTooLarge { .. }), "{:?}", err);
+    }
+
+    #[test]
+    fn created_at() {
+        let book = BookWriter::new_in_memory(0x1234).unwrap();
+        let book = book.close_to_book().unwrap();
+        assert!(book.created_at().unwrap() > 0);
+
+        // A fixed timestamp produces identical files.
+        let write = || {
+            let mut book = BookBuilder::new()
+                .created_at(Some(1_600_000_000))
+                .build(Cursor::new(Vec::<u8>::new()))
+                .unwrap();
+            book.write_chapter(1, b"This is chapter 1").unwrap();
+            book.close().unwrap().into_inner()
+        };
+        let buf = write();
+        assert_eq!(buf, write());
+        let book = Book::new(Cursor::new(buf)).unwrap();
+        assert_eq!(book.created_at(), Some(1_600_000_000));
+
+        let mut book = BookBuilder::new()
+            .created_at(None)
+            .build(Cursor::new(Vec::<u8>::new()))
+            .unwrap();
+        book.write_chapter(1, b"This is chapter 1").unwrap();
+        let book = book.close_to_book().unwrap();
+        assert_eq!(book.created_at(), None);
+    }
+
+    #[test]
+    fn read_into() {
+        let mut book = BookWriter::new_in_memory(0x1234).unwrap();
+        book.write_chapter(1, &[0xAA; 1000]).unwrap();
+        book.write_chapter(2, b"This is chapter 2").unwrap();
+        let mut book = book.close_to_book().unwrap();
+
+        let mut buf = Vec::new();
+        let n = book.read_chapter_into(ChapterIndex(0), &mut buf).unwrap();
+        assert_eq!(n, 1000);
+        assert_eq!(buf, [0xAA; 1000]);
+        let capacity = buf.capacity();
+
+        // A shorter chapter replaces the contents, reusing the allocation.
+        let n = book.read_chapter_into(ChapterIndex(1), &mut buf).unwrap();
+        assert_eq!(n, 17);
+        assert_eq!(buf, b"This is chapter 2");
+        assert_eq!(buf.capacity(), capacity);
+
+        let err = book
+            .read_chapter_into(ChapterIndex(2), &mut buf)
+            .unwrap_err();
+        assert!(
+            matches!(err, BookError::IndexOutOfRange { index: 2, len: 2 }),
+            "{:?}",
+            err
+        );
+    }
+
+    #[test]
+    fn layout_gaps() {
+        let mut book = BookWriter::new_in_memory(0x1234).unwrap();
+        book.write_chapter(1, b"This is chapter 1").unwrap();
+        book.write_chapter(2, b"").unwrap();
+        book.write_chapter(3, b"This is chapter 3").unwrap();
+        let book = book.close_to_book().unwrap();
+        assert!(book.layout_gaps().is_empty());
+
+        let mut book = BookBuilder::new()
+            .chapter_alignment(64)
+            .build(Cursor::new(Vec::<u8>::new()))
+            .unwrap();
+        book.write_chapter(1, &[0xAA; 10]).unwrap();
+        book.write_chapter(2, &[0xBB; 64]).unwrap();
+        book.write_chapter(3, &[0xCC; 5]).unwrap();
+        let book = book.close_to_book().unwrap();
+        let header_end = HEADER_SIZE as u64;
+        assert_eq!(
+            book.layout_gaps(),
+            [(header_end + 10, 54), (header_end + 128 + 5, 59)]
+        );
+    }
+
+    #[test]
+    fn reserved_chapter() {
+        let book = BookWriter::new_in_memory(0x1234).unwrap();
+        let mut chapter = book.reserve_chapter(1, 100);
+        chapter.write_all(b"This is chapter 1").unwrap();
+        let (book, span) = chapter.close_with_span().unwrap();
+        assert_eq!(span, (HEADER_SIZE as u64, 17));
+
+        // A chapter may fill its reservation exactly, but not overflow it.
+        let mut chapter = book.reserve_chapter(2, 20);
+        chapter.write_all(&[0xAA; 20]).unwrap();
+        let err = chapter.write(b"x").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        let (book, span) = chapter.close_with_span().unwrap();
+        assert_eq!(span, (HEADER_SIZE as u64 + 100, 20));
+
+        // An overflowing write doesn't write anything.
+        let mut chapter = book.reserve_chapter(3, 10);
+        chapter.write_all(b"12345").unwrap();
+        assert!(chapter.write_all(b"This is too long").is_err());
+        assert_eq!(chapter.current_len(), 5);
+        let (book, span) = chapter.close_with_span().unwrap();
+        assert_eq!(span, (HEADER_SIZE as u64 + 120, 5));
+
+        let mut book = book.close_to_book().unwrap();
+        book.verify().unwrap();
+        let ch1 = book.exclusive_read_chapter(1).unwrap();
+        assert_eq!(ch1.as_ref(), b"This is chapter 1");
+        let ch3 = book.exclusive_read_chapter(3).unwrap();
+        assert_eq!(ch3.as_ref(), b"12345");
+        assert_eq!(
+            book.layout_gaps(),
+            [(HEADER_SIZE as u64 + 17, 83), (HEADER_SIZE as u64 + 125, 5)]
+        );
+    }
+
+    #[test]
+    fn chapter_alignment() {
+        let buffer = {
+            let mut book = BookBuilder::new()
+                .header_size(512)
+                .reserved_toc(400)
+                .chapter_alignment(256)
+                .build(Cursor::new(Vec::<u8>::new()))
+                .unwrap();
+            book.write_chapter(1, b"This is chapter 1").unwrap();
+            let mut chapter = book.new_chapter(2);
+            chapter.write_all(&[0xFF; 300]).unwrap();
+            let mut book = chapter.close().unwrap();
+            book.write_chapter_from(3, &mut &b"This is chapter 3"[..])
+                .unwrap();
+            book.close_front_toc().unwrap().into_inner()
+        };
+        let mut book = Book::new(Cursor::new(buffer.as_slice())).unwrap();
+        let spans: Vec<_> = (0..3)
+            .map(|i| book.chapter_span(ChapterIndex(i)).unwrap())
+            .collect();
+        assert_eq!(spans, [(1024, 17), (1280, 300), (1792, 17)]);
+
+        // The gaps between chapters are zero-filled.
+        let mut gap_start = 920;
+        for &(offset, length) in &spans {
+            let gap = &buffer[gap_start..offset.try_into().unwrap()];
+            assert!(gap.iter().all(|&b| b == 0));
+            gap_start = (offset + length).try_into().unwrap();
+        }
+        book.verify().unwrap();
+        let ch3 = book.exclusive_read_chapter(3).unwrap();
+        assert_eq!(ch3.as_ref(), b"This is chapter 3");
+    }
+
+    #[test]
+    fn header_size() {
+        // A small header block.
+        let mut book = BookBuilder::new()
+            .user_magic(0x1234)
+            .header_size(512)
+            .build(Cursor::new(Vec::<u8>::new()))
+            .unwrap();
+        book.write_chapter(22, b"This is chapter 22").unwrap();
+        let mut book = book.close_to_book().unwrap();
+        assert_eq!(book.header().header_size(), 512);
+        assert_eq!(book.chapter_span(ChapterIndex(0)), Some((512, 18)));
+        let ch2 = book.exclusive_read_chapter(22).unwrap();
+        assert_eq!(ch2.as_ref(), b"This is chapter 22");
+        book.verify().unwrap();
+
+        // A large header block, with a front TOC.
+        let meta = vec![0xAA; 10000];
+        let buffer = {
+            let mut book = BookBuilder::new()
+                .user_meta(&meta)
+                .header_size(0x8000)
+                .reserved_toc(200)
+                .build(Cursor::new(Vec::<u8>::new()))
+                .unwrap();
+            book.write_chapter(22, b"This is chapter 22").unwrap();
+            book.close_front_toc().unwrap()
+        };
+        let stream = ForwardReader::new(buffer.get_ref().as_slice());
+        let mut book = Book::new(stream).unwrap();
+        assert_eq!(book.user_meta(), meta.as_slice());
+        let ch2 = book.exclusive_read_chapter(22).unwrap();
+        assert_eq!(ch2.as_ref(), b"This is chapter 22");
+
+        // A file shorter than its header block is too small.
+        let mut buf = buffer.into_inner();
+        buf.truncate(0x7000);
+        let err = Book::new(Cursor::new(buf)).unwrap_err();
+        assert!(
+            matches!(err, BookError::TooSmall { len: 0x7000 }),
+            "{:?}",
+            err
+        );
+
+        for size in [0, 511, 0x10_0001] {
+            let err = BookBuilder::new()
+                .header_size(size)
+                .build(Vec::<u8>::new())
+                .unwrap_err();
+            assert!(
+                matches!(err, BookError::InvalidHeaderSize { .. }),
+                "{:?}",
+                err
+            );
+        }
+        let err = BookBuilder::new()
+            .user_meta(&[0; 600])
+            .header_size(512)
+            .build(Vec::<u8>::new())
+            .unwrap_err();
+        assert!(matches!(err, BookError::HeaderTooLarge { .. }), "{:?}", err);
+
+        // A header that claims to be smaller than its own encoding.
+        let mut header = FileHeader::new(0x1234);
+        header.user_meta = vec![0; 1000];
+        header.header_size = 512;
+        let mut buf = serialize_header(&header, 2048).unwrap();
+        buf.resize(8192, 0);
+        let err = Book::new(Cursor::new(buf)).unwrap_err();
+        assert!(matches!(err, BookError::HeaderTooLarge { .. }), "{:?}", err);
+    }
+
+    #[test]
+    fn truncated_book() {
+        let magic = 0x1234;
+        let mut cursor = Cursor::new(Vec::<u8>::new());
+        {
+            let _book = BookWriter::new(&mut cursor, magic).unwrap();
+            // We drop the BookWriter without calling close().
+        }
+
+        // This file contains only a header (yes, this is invalid).
+        assert_eq!(cursor.get_ref().len(), 4096);
+
+        // This should fail, because there is no room for the chapter index.
+        let err = Book::new(cursor).unwrap_err();
+        assert!(
+            matches!(err, BookError::TooSmall { len: 4096 }),
+            "{:?}",
+            err
+        );
+    }
+
+    #[test]
+    fn too_small() {
+        let err = Book::new(Cursor::new(Vec::<u8>::new())).unwrap_err();
+        assert!(matches!(err, BookError::TooSmall { len: 0 }), "{:?}", err);
+
+        let err = Book::new(Cursor::new(vec![0u8; 100])).unwrap_err();
+        assert!(matches!(err, BookError::TooSmall { len: 100 }), "{:?}", err);
+
+        // A forward-only stream is detected too.
+        let stream = ForwardReader::new(&[0u8; 100][..]);
+        let err = Book::new(stream).map(|_| ()).unwrap_err();
+        assert!(matches!(err, BookError::TooSmall { len: 100 }), "{:?}", err);
+
+        // A valid header with no room for the TOC length.
+        let mut buf = vec![];
+        BookWriter::new(&mut buf, 0x1234).unwrap();
+        buf.extend_from_slice(&[0; 7]);
+        let err = Book::new(Cursor::new(buf)).unwrap_err();
+        assert!(
+            matches!(err, BookError::TooSmall { len: 4103 }),
+            "{:?}",
+            err
+        );
+    }
+
+    #[test]
+    fn bad_magic() {
+        // Random bytes aren't a bookfile.
+        let mut state = 0x2545_F491_4F6C_DD1Du64;
+        let random: Vec<u8> = (0..HEADER_SIZE * 2)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state.to_be_bytes()[0]
+            })
+            .collect();
+        let err = Book::new(Cursor::new(random)).unwrap_err();
+        assert!(
+            matches!(
+                err,
+                BookError::BadMagic {
+                    expected: BOOK_V1_MAGIC,
+                    found: 0
+                }
+            ),
+            "{:?}",
+            err
+        );
+
+        // A well-formed header with the wrong format magic.
+        let mut buf = Vec::<u8>::new();
+        let header = FileHeaderV1 {
+            bookwriter_magic: 0xBAD,
+            user_magic: 0x1234,
+        };
+        CborData::new(&mut buf).write_message(&header).unwrap();
+        buf.resize(HEADER_SIZE * 2, 0);
+        let err = Book::new(Cursor::new(buf)).unwrap_err();
+        assert!(
+            matches!(err, BookError::BadMagic { found: 0xBAD, .. }),
+            "{:?}",
+            err
+        );
+
+        // A bookfile from a later format version.
+        for magic in [BOOK_V2_MAGIC, 0xFF33_0007] {
+            let mut buf = Vec::<u8>::new();
+            let header = FileHeaderV1 {
+                bookwriter_magic: magic,
+                user_magic: 0x1234,
+            };
+            CborData::new(&mut buf).write_message(&header).unwrap();
+            buf.resize(HEADER_SIZE * 2, 0);
+            let err = Book::new(Cursor::new(buf)).unwrap_err();
+            assert!(
+                matches!(err, BookError::UnsupportedVersion { magic: m } if m == magic),
+                "{:?}",
+                err
+            );
+        }
+    }
+
+    /// A reader that fails when reading past a given offset.
+    struct FailingReader {
+        inner: Cursor<Vec<u8>>,
+        limit: u64,
+    }
+
+    impl Read for FailingReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.inner.position() >= self.limit {
+                return Err(io::Error::other("disk on fire"));
+            }
+            self.inner.read(buf)
+        }
+    }
+
+    impl Seek for FailingReader {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.inner.seek(pos)
+        }
+    }
+
+    #[test]
+    fn io_error_context() {
+        use std::error::Error;
+
+        let mut buf = vec![];
+        let mut book = BookWriter::new(&mut buf, 0x1234).unwrap();
+        book.write_chapter(22, b"This is chapter 22").unwrap();
+        book.close().unwrap();
+
+        // The header can be read, but the TOC can't.
+        let reader = FailingReader {
+            inner: Cursor::new(buf),
+            limit: HEADER_SIZE as u64,
+        };
+        let err = Book::new(reader).map(|_| ()).unwrap_err();
+        assert!(
+            matches!(
+                err,
+                BookError::IoContext {
+                    op: "reading TOC",
+                    ..
+                }
+            ),
+            "{:?}",
+            err
+        );
+        assert_eq!(err.to_string(), "IO error while reading TOC");
+        let source = err.source().unwrap().downcast_ref::<io::Error>().unwrap();
+        assert_eq!(source.to_string(), "disk on fire");
+    }
+
+    #[test]
+    fn wrong_user_magic() {
+        let buffer = {
+            let book = BookWriter::new(Cursor::new(Vec::<u8>::new()), 0x1234).unwrap();
+            book.close().unwrap()
+        };
+        Book::new_expecting(buffer.clone(), 0x1234).unwrap();
+        let err = Book::new_expecting(buffer, 0x5678).unwrap_err();
+        assert!(
+            matches!(
+                err,
+                BookError::WrongUserMagic {
+                    expected: 0x5678,
+                    found: 0x1234
+                }
+            ),
+            "{:?}",
+            err
+        );
+    }
+
+    #[test]
+    fn corrupt_toc() {
+        let magic = 0x1234;
+        let buffer = {
+            let buffer = Cursor::new(Vec::<u8>::new());
+            let mut book = BookWriter::new(buffer, magic).unwrap();
+            book.write_chapter(22, b"This is chapter 22").unwrap();
+            book.close().unwrap()
+        };
+        let mut buf = buffer.into_inner();
+
+        // Chop the TOC in half, and rewrite the trailer to match.
+        let trailer_offset = buf.len() - 12;
+        let toc_len: usize = (&buf[trailer_offset + 4..])
+            .read_u64::<BigEndian>()
+            .unwrap()
+            .try_into()
+            .unwrap();
+        let toc_offset = trailer_offset - toc_len;
+        let short_toc = buf[toc_offset..toc_offset + toc_len / 2].to_vec();
+        buf.truncate(toc_offset);
+        append_toc(&mut buf, &short_toc);
+
+        let err = Book::new(Cursor::new(buf)).unwrap_err();
+        assert!(matches!(err, BookError::CorruptToc), "{:?}", err);
+    }
+
+    #[test]
+    fn toc_checksum() {
+        let mut book = BookWriter::new_in_memory(0x1234).unwrap();
+        book.write_chapter(22, b"This is chapter 22").unwrap();
+        let buf = book.close().unwrap().into_inner();
+        let toc_len = Book::new(Cursor::new(buf.as_slice()))
+            .unwrap()
+            .raw_toc()
+            .unwrap()
+            .len();
+        let trailer_offset = buf.len() - 12;
+
+        // Flip a byte in the TOC, the CRC, and the TOC length.
+        for offset in [trailer_offset - toc_len / 2, trailer_offset, buf.len() - 1] {
+            let mut corrupt = buf.clone();
+            corrupt[offset] ^= 0x01;
+            let err = Book::new(Cursor::new(corrupt)).unwrap_err();
+            assert!(matches!(err, BookError::CorruptToc), "{:?}", err);
+        }
+
+        // The front TOC is checked too.
+        let mut book = BookWriter::with_reserved_toc(Cursor::new(Vec::new()), 0x1234, 300).unwrap();
+        book.write_chapter(22, b"This is chapter 22").unwrap();
+        let front = book.close_front_toc().unwrap().into_inner();
+        let region = HEADER_SIZE..HEADER_SIZE + 12 + toc_len;
+        // Flip a byte in the front TOC's CRC, and in the TOC itself.
+        for offset in [region.start + 8, region.end - toc_len / 2] {
+            let mut corrupt = front.clone();
+            corrupt[offset] ^= 0x01;
+            let err = Book::new(Cursor::new(corrupt)).unwrap_err();
+            assert!(matches!(err, BookError::CorruptToc), "{:?}", err);
+        }
+
+        // Books written without the flag have no CRC.
+        let header = FileHeader {
+            flags: 0,
+            ..FileHeader::new(0x1234)
+        };
+        let mut book = BookWriter::with_header(Cursor::new(Vec::new()), header).unwrap();
+        book.write_chapter(22, b"This is chapter 22").unwrap();
+        let old = book.close().unwrap().into_inner();
+        assert_eq!(old.len(), buf.len() - 4);
+        let mut book = Book::new(Cursor::new(old)).unwrap();
+        let ch2 = book.exclusive_read_chapter(22).unwrap();
+        assert_eq!(ch2.as_ref(), b"This is chapter 22");
+    }
+
+    #[test]
+    fn parse_mutations() {
+        let buf = {
+            let mut book = BookBuilder::new()
+                .header_size(512)
+                .reserved_toc(400)
+                .build(Cursor::new(Vec::<u8>::new()))
+                .unwrap();
+            book.write_chapter(11, b"This is chapter 11").unwrap();
+            book.write_chapter(22, b"").unwrap();
+            book.write_chapter(b"third".as_ref(), b"This is the third chapter")
+                .unwrap();
+            book.close_front_toc().unwrap().into_inner()
+        };
+        Book::parse(&buf).unwrap();
+
+        // Corrupt and truncate the book in many ways; parsing may fail,
+        // but must never panic.
+        let mut state = 0x2545_F491_4F6C_DD1Du64;
+        let mut next = || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        for _ in 0..2000 {
+            let mut corrupt = buf.clone();
+            for _ in 0..(next() % 4 + 1) {
+                let offset: usize = (next() % buf.len() as u64).try_into().unwrap();
+                corrupt[offset] = next().to_be_bytes()[0];
+            }
+            if next() % 4 == 0 {
+                corrupt.truncate((next() % buf.len() as u64).try_into().unwrap());
+            }
+            let _ = Book::parse(&corrupt);
+        }
+        for len in 0..buf.len() {
+            assert!(Book::parse(&buf[..len]).is_err());
+        }
+    }
+
+    #[test]
+    fn header_only() {
+        let mut book = BookWriter::new_with_meta(Cursor::new(Vec::new()), 0x1234, b"meta").unwrap();
+        book.write_chapter(22, b"This is chapter 22").unwrap();
+        let mut buf = book.close().unwrap().into_inner();
+
+        // Destroy the TOC; the header can still be read.
+        let header_len = HEADER_SIZE + 18;
+        buf.truncate(header_len);
+        assert!(Book::new(Cursor::new(buf.as_slice())).is_err());
+        let header = FileHeader::read_from(&mut Cursor::new(buf.as_slice())).unwrap();
+        assert_eq!(header.user_magic, 0x1234);
+        assert_eq!(header.user_meta, b"meta");
+        assert_eq!(header.header_size(), HEADER_SIZE as u64);
+
+        let err = FileHeader::read_from(&mut Cursor::new(&buf[..100])).unwrap_err();
+        assert!(matches!(err, BookError::TooSmall { .. }), "{:?}", err);
+    }
+
+    #[test]
+    fn garbage_trailer() {
+        let err = Book::new(Cursor::new(vec![1u8, 2, 3])).unwrap_err();
+        assert!(matches!(err, BookError::TooSmall { len: 3 }), "{:?}", err);
+
+        let mut book = BookWriter::new_in_memory(0x1234).unwrap();
+        book.write_chapter(22, b"This is chapter 22").unwrap();
+        let buf = book.close().unwrap().into_inner();
+        let trailer_offset = buf.len() - 8;
+
+        // An implausibly large length, one that's plausible but wrong,
+        // and one that's too small to hold a TOC.
+        for garbage in [[0xFF; 8], 20u64.to_be_bytes(), [0; 8]] {
+            let mut corrupt = buf.clone();
+            corrupt[trailer_offset..].copy_from_slice(&garbage);
+            let err = Book::new(Cursor::new(corrupt)).unwrap_err();
+            assert!(matches!(err, BookError::CorruptToc), "{:?}", err);
+        }
+    }
+
+    #[test]
+    fn forged_front_toc_size() {
+        // Front TOC regions that can't hold a TOC length, or whose end
+        // would overflow.
+        for front_toc_size in [0, u64::MAX - 1, u64::MAX] {
+            let header = FileHeader {
+                flags: FLAG_FRONT_TOC | FLAG_TOC_CRC,
+                front_toc_size,
+                ..FileHeader::new(0x1234)
+            };
+            let mut buf = serialize_header(&header, HEADER_SIZE).unwrap();
+            buf.extend_from_slice(b"This is chapter 22");
+            let mut toc = Toc::default();
+            toc.add(TocEntry {
+                id: ChapterId::from(22).0,
+                name: None,
+                span: FileSpan::from_offset_length(HEADER_SIZE as u64, 18),
+                checksum: None,
+                encryption: None,
+                codec: None,
+                content_type: None,
+                removed: false,
+            });
+            append_toc(&mut buf, &toc.to_bytes().unwrap());
+            let err = Book::new(Cursor::new(buf)).unwrap_err();
+            assert!(matches!(err, BookError::CorruptToc), "{:?}", err);
+        }
+    }
+
+    #[test]
+    fn forged_toc_length() {
+        let magic = 0x1234;
+        let buffer = {
+            let buffer = Cursor::new(Vec::<u8>::new());
+            let mut book = BookWriter::new(buffer, magic).unwrap();
+            book.write_chapter(22, b"This is chapter 22").unwrap();
+            book.close().unwrap()
+        };
+        let mut buf = buffer.into_inner();
+        let trailer_offset = buf.len() - 8;
+        buf.truncate(trailer_offset);
+
+        // A TOC length that is below MAX_TOC_SIZE, but larger than the file.
+        let mut forged = buf.clone();
+        forged.write_u64::<BigEndian>(60 << 20).unwrap();
+        let err = Book::new(Cursor::new(forged)).unwrap_err();
+        assert!(matches!(err, BookError::CorruptToc), "{:?}", err);
+
+        // A TOC length that would overlap the header.
+        let mut forged = buf;
+        let overlap_len = trailer_offset - HEADER_SIZE + 1;
+        forged.write_u64::<BigEndian>(overlap_len as u64).unwrap();
+        let err = Book::new(Cursor::new(forged)).unwrap_err();
+        assert!(matches!(err, BookError::CorruptToc), "{:?}", err);
+    }
+
+    #[test]
+    fn verify_book() {
+        let magic = 0x1234;
+        let buffer = {
+            let buffer = Cursor::new(Vec::<u8>::new());
+            let mut book = BookWriter::new(buffer, magic).unwrap();
+            book.set_checksum_kind(ChecksumKind::Crc32);
+            book.write_chapter(11, b"").unwrap();
+            book.write_chapter(22, b"This is chapter 22").unwrap();
+            book.write_chapter(33, b"This is chapter 33").unwrap();
+            book.close().unwrap()
+        };
+        let original = buffer.into_inner();
+        let mut book = Book::new(Cursor::new(original.clone())).unwrap();
+        book.verify().unwrap();
+
+        // Rebuild the book with a hand-crafted TOC.
+        let forge = |entries: Vec<(u64, u64, u64)>| {
+            let mut buf = original[..HEADER_SIZE + 36].to_vec();
+            let mut toc = Toc::default();
+            for (id, offset, length) in entries {
+                toc.add(TocEntry {
+                    id: ChapterId::from(id).0,
+                    name: None,
+                    span: FileSpan::from_offset_length(offset, length),
+                    checksum: None,
+                    encryption: None,
+                    codec: None,
+                    content_type: None,
+                    removed: false,
+                });
+            }
+            let toc_buf = toc.to_bytes().unwrap();
+            append_toc(&mut buf, &toc_buf);
+            Book::new(Cursor::new(buf))
+        };
+        let start = HEADER_SIZE as u64;
+
+        let mut book = forge(vec![(22, start, 18), (33, start + 18, 18)]).unwrap();
+        book.verify().unwrap();
+
+        let mut book = forge(vec![(22, start, 18), (33, start + 10, 18)]).unwrap();
+        let err = book.verify().unwrap_err();
+        match err {
+            BookError::SpanOverlap { first, second } => {
+                assert_eq!(first.as_ref(), &22u64.to_be_bytes());
+                assert_eq!(second.as_ref(), &33u64.to_be_bytes());
+            }
+            _ => panic!("unexpected error {:?}", err),
+        }
+
+        // Spans that extend into the TOC, or into the header, are
+        // rejected when the book is opened.
+        let err = forge(vec![(22, start, 18), (33, start + 18, 19)]).unwrap_err();
+        match err {
+            BookError::SpanOutOfBounds { id } => {
+                assert_eq!(id.as_ref(), &33u64.to_be_bytes());
+            }
+            _ => panic!("unexpected error {:?}", err),
+        }
+
+        let err = forge(vec![(22, start - 1, 18)]).unwrap_err();
+        assert!(
+            matches!(err, BookError::SpanOutOfBounds { .. }),
+            "{:?}",
+            err
+        );
+
+        // A corrupt chapter fails verification, even if checksum
+        // verification is disabled.
+        let mut corrupt = original;
+        corrupt[HEADER_SIZE] ^= 0xFF;
+        let mut book = Book::new(Cursor::new(corrupt)).unwrap();
+        let err = book.verify().unwrap_err();
+        assert!(
+            matches!(err, BookError::ChecksumMismatch { .. }),
+            "{:?}",
+            err
+        );
+    }
+
+    #[test]
+    fn simple_book() {
+        let magic = 0x1234;
+        let buffer = {
+            let buffer = Cursor::new(Vec::<u8>::new());
+            let book = BookWriter::new(buffer, magic).unwrap();
+            let chapter = book.new_chapter(11);
+            let book = chapter.close().unwrap();
+            let mut chapter = book.new_chapter(22);
+            chapter.write_all(b"This is chapter 22").unwrap();
+            let book = chapter.close().unwrap();
+            let mut chapter = book.new_chapter("🦀");
+            chapter.write_all(b"This is chapter 33").unwrap();
+            let book = chapter.close().unwrap();
+            book.close().unwrap()
+        };
+        let mut book = Book::new(buffer).unwrap();
+        let ch1 = book.exclusive_read_chapter(11).unwrap();
+        assert!(ch1.is_empty());
+
+        assert!(!book.has_chapter(1));
+
+        let ch2 = book.exclusive_read_chapter(22).unwrap();
+        assert_eq!(ch2.as_ref(), b"This is chapter 22");
+
+        let ch2 = book.exclusive_read_chapter("🦀").unwrap();
+        assert_eq!(ch2.as_ref(), b"This is chapter 33");
+    }
+
+    /// Write the same three chapters as `simple_book`, and open the result.
+    fn three_chapter_book() -> Book<Cursor<Vec<u8>>> {
+        let mut book = BookWriter::new_in_memory(0x1234).unwrap();
+        book.write_chapter(11, b"").unwrap();
+        book.write_chapter(22, b"This is chapter 22").unwrap();
+        book.write_chapter("🦀", b"This is chapter 33").unwrap();
+        book.close_to_book().unwrap()
+    }
+
+    #[test]
+    fn user_magic() {
+        let book = BookWriter::new_in_memory(0x1234).unwrap();
+        assert_eq!(book.user_magic(), 0x1234);
+        let book = book.close_to_book().unwrap();
+        assert_eq!(book.user_magic(), 0x1234);
+        assert_eq!(book.magic(), 0x1234);
+    }
+
+    #[test]
+    fn contains_chapter() {
+        let book = three_chapter_book();
+        assert!(!book.contains_chapter(1));
+        assert!(book.contains_chapter(22));
+        assert!(book.contains_chapter("🦀"));
+        assert_eq!(book.count_chapters(1), 0);
+        assert_eq!(book.count_chapters(22), 1);
+    }
+
+    #[test]
+    fn chapter_len() {
+        let book = three_chapter_book();
+        assert_eq!(book.chapter_len(ChapterIndex(0)).unwrap(), 0);
+        assert_eq!(book.chapter_len(ChapterIndex(1)).unwrap(), 18);
+        book.chapter_len(ChapterIndex(3)).unwrap_err();
+        assert_eq!(book.chapter_span(ChapterIndex(0)), None);
+        assert_eq!(
+            book.chapter_span(ChapterIndex(1)),
+            Some((HEADER_SIZE as u64, 18))
+        );
+        assert_eq!(
+            book.chapter_span(ChapterIndex(2)),
+            Some((HEADER_SIZE as u64 + 18, 18))
+        );
+        assert_eq!(book.chapter_span(ChapterIndex(3)), None);
+    }
+
+    #[test]
+    fn book_len() {
+        let book = three_chapter_book();
+        assert_eq!(book.len(), 3);
+        assert!(!book.is_empty());
+
+        let book = BookWriter::new_in_memory(0x1234).unwrap();
+        let book = book.close_to_book().unwrap();
+        assert_eq!(book.len(), 0);
+        assert!(book.is_empty());
+        assert_eq!(book.chapters().count(), 0);
+    }
+
+    #[test]
+    fn describe() {
+        let book = three_chapter_book();
+        let description = book.describe();
+        assert!(description.contains("11"), "{}", description);
+        assert!(description.contains("22"), "{}", description);
+        assert!(description.contains("\"🦀\""), "{}", description);
+        assert_eq!(describe_id(&[0xFF, 0x00]), "ff00");
+    }
+
+    #[test]
+    fn book_stats() {
+        let book = three_chapter_book();
+        assert_eq!(
+            book.stats(),
+            BookStats {
+                chapter_count: 3,
+                total_payload_bytes: 36,
+                toc_offset: Some(HEADER_SIZE as u64 + 36),
+            }
+        );
+    }
+
+    #[test]
+    fn chapter_ids() {
+        let book = three_chapter_book();
+        assert_eq!(book.chapter_ids(), vec![11, 22]);
+        let chapters: Vec<_> = book.chapters().collect();
+        assert_eq!(
+            chapters,
+            vec![
+                (&11u64.to_be_bytes()[..], ChapterIndex(0)),
+                (&22u64.to_be_bytes()[..], ChapterIndex(1)),
+                ("🦀".as_bytes(), ChapterIndex(2)),
+            ]
+        );
+    }
+
+    #[test]
+    fn from_slice() {
+        let buf = three_chapter_book().into_inner().into_inner();
+        let mut book = Book::from_slice(&buf).unwrap();
+        let ch2 = book.exclusive_read_chapter(22).unwrap();
+        assert_eq!(ch2.as_ref(), b"This is chapter 22");
+        let mut book = Book::from_bytes(buf).unwrap();
+        let ch2 = book.exclusive_read_chapter("🦀").unwrap();
+        assert_eq!(ch2.as_ref(), b"This is chapter 33");
+        assert_eq!(book.len(), 3);
+    }
+
+    #[test]
+    fn exclusive_reader_position() {
+        let magic = 0x1234;
+        let buffer = {
+            let buffer = Cursor::new(Vec::<u8>::new());
+            let mut book = BookWriter::new(buffer, magic).unwrap();
+            book.write_chapter(11, b"").unwrap();
+            book.write_chapter(22, b"This is chapter 22").unwrap();
+            book.write_chapter(33, b"This is chapter 33").unwrap();
+            book.close().unwrap()
+        };
+        let mut book = Book::new(buffer).unwrap();
+
+        // Reading an empty chapter, then a non-empty one.
+        let ch1 = book.exclusive_read_chapter(11).unwrap();
+        assert!(ch1.is_empty());
+        let ch2 = book.exclusive_read_chapter(22).unwrap();
+        assert_eq!(ch2.as_ref(), b"This is chapter 22");
+
+        // Leave the stream in the middle of chapter 33.
+        let mut reader = book.exclusive_chapter_reader(33).unwrap();
+        let mut buf = [0u8; 5];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"This ");
+
+        // An empty chapter doesn't disturb later reads.
+        let mut reader = book.exclusive_chapter_reader(11).unwrap();
+        let mut buf = vec![];
+        reader.read_to_end(&mut buf).unwrap();
+        assert!(buf.is_empty());
+
+        let mut reader = book.exclusive_chapter_reader(22).unwrap();
+        let mut buf = vec![];
+        reader.read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, b"This is chapter 22");
+        let ch2 = book.exclusive_read_chapter(22).unwrap();
+        assert_eq!(ch2.as_ref(), b"This is chapter 22");
+    }
+
+    #[test]
+    fn chapter_span_on_close() {
+        let buffer = Cursor::new(Vec::<u8>::new());
+        let book = BookWriter::new(buffer, 0x1234).unwrap();
+        let chapter = book.new_chapter(11);
+        assert_eq!(chapter.offset(), HEADER_SIZE as u64);
+        let (book, span) = chapter.close_with_span().unwrap();
+        assert_eq!(span, (HEADER_SIZE as u64, 0));
+
+        let mut chapter = book.new_chapter(22);
+        assert_eq!(chapter.offset(), HEADER_SIZE as u64);
+        chapter.write_all(b"This is ").unwrap();
+        assert_eq!(chapter.current_len(), 8);
+        chapter.write_all(b"chapter 22").unwrap();
+        assert_eq!(chapter.current_len(), 18);
+        let (book, span) = chapter.close_with_span().unwrap();
+        assert_eq!(span, (HEADER_SIZE as u64, 18));
+
+        let chapter = book.new_chapter(33);
+        assert_eq!(chapter.offset(), HEADER_SIZE as u64 + 18);
+        let buffer = chapter.close().unwrap().close().unwrap();
+
+        let book = Book::new(buffer).unwrap();
+        assert_eq!(
+            book.chapter_span(ChapterIndex(1)),
+            Some((HEADER_SIZE as u64, 18))
+        );
+    }
+
+    #[test]
+    fn large_offsets() {
+        // Pretend 5GB have already been written, which doesn't fit
+        // in a 32-bit usize.
+        let start = 5 << 30;
+        let mut book = BookWriter {
+            writer: CountingWriter::with_count(Vec::<u8>::new(), start),
+            header: FileHeader::new(0x1234),
+            toc: Toc::default(),
+            strict_drop: true,
+            checksum_kind: ChecksumKind::default(),
+            min_length: 0,
+            scratch: Vec::new(),
+            chapter_alignment: 1,
+            io_buffer_size: COPY_BUFFER_SIZE,
+            manifest: None,
+            toc_records: Vec::new(),
+            toc_records_count: 0,
+            dedup: None,
+        };
+        book.write_chapter(11, b"This is chapter 11").unwrap();
+        let mut chapter = book.new_chapter(22);
+        assert_eq!(chapter.offset(), start + 18);
+        chapter.write_all(b"This is chapter 22").unwrap();
+        let (book, span) = chapter.close_with_span().unwrap();
+        assert_eq!(span, (start + 18, 18));
+
+        let spans: Vec<_> = book.toc.iter().map(|entry| entry.span.clone()).collect();
+        assert_eq!(
+            spans,
+            vec![
+                FileSpan::from_offset_length(start, 18),
+                FileSpan::from_offset_length(start + 18, 18),
+            ]
+        );
+        assert_eq!(spans[0].as_ref().unwrap().offset, 5 * 1024 * 1024 * 1024);
+    }
+
+    #[test]
+    fn read_chapter_range() {
+        let buffer = {
+            let buffer = Cursor::new(Vec::<u8>::new());
+            let mut book = BookWriter::new(buffer, 0x1234).unwrap();
+            book.write_chapter(11, b"").unwrap();
+            book.write_chapter(22, b"This is chapter 22").unwrap();
+            book.close().unwrap()
+        };
+        let mut book = Book::new(buffer).unwrap();
+        let range = book.read_chapter_range(ChapterIndex(1), 5, 5).unwrap();
+        assert_eq!(range.as_ref(), b"is ch");
+        let range = book.read_chapter_range(ChapterIndex(1), 16, 2).unwrap();
+        assert_eq!(range.as_ref(), b"22");
+        let range = book.read_chapter_range(ChapterIndex(1), 18, 0).unwrap();
+        assert!(range.is_empty());
+        let range = book.read_chapter_range(ChapterIndex(0), 0, 0).unwrap();
+        assert!(range.is_empty());
+
+        let err = book.read_chapter_range(ChapterIndex(1), 10, 9).unwrap_err();
+        assert!(
+            matches!(
+                err,
+                BookError::OutOfBounds {
+                    start: 10,
+                    len: 9,
+                    chapter_len: 18
+                }
+            ),
+            "{:?}",
+            err
+        );
+        book.read_chapter_range(ChapterIndex(1), u64::MAX, 2)
+            .unwrap_err();
+        book.read_chapter_range(ChapterIndex(0), 0, 1).unwrap_err();
+        let err = book.read_chapter_range(ChapterIndex(2), 0, 0).unwrap_err();
+        assert!(
+            matches!(err, BookError::IndexOutOfRange { index: 2, len: 2 }),
+            "{:?}",
+            err
+        );
+    }
+
+    #[test]
+    fn copy_chapter_from() {
+        let buffer = {
+            let buffer = Cursor::new(Vec::<u8>::new());
+            let mut book = BookWriter::new(buffer, 0x1234).unwrap();
+            book.write_chapter(11, b"This is chapter 11").unwrap();
+            let mut chapter = book.new_chapter_named(22, "twenty-two");
+            chapter.write_all(b"This is chapter 22").unwrap();
+            let mut book = chapter.close().unwrap();
+            book.write_chapter(33, b"").unwrap();
+            book.close().unwrap()
+        };
+        let original = buffer.get_ref().clone();
+        let mut src = Book::new(buffer).unwrap();
+
+        let buffer = {
+            let buffer = Cursor::new(Vec::<u8>::new());
+            let mut book = BookWriter::new(buffer, 0x5678).unwrap();
+            book.write_chapter(44, b"This is chapter 44").unwrap();
+            book.copy_chapter_from(&mut src, ChapterIndex(1)).unwrap();
+            book.copy_chapter_from(&mut src, ChapterIndex(2)).unwrap();
+            book.copy_chapter_from(&mut src, ChapterIndex(3))
+                .unwrap_err();
+            book.close().unwrap()
+        };
+        assert_eq!(src.reader.get_ref(), &original);
+
+        let mut book = Book::new(buffer).unwrap();
+        book.verify().unwrap();
+        assert_eq!(book.len(), 3);
+        let (copied, original) = (&book.toc().0[1], &src.toc().0[1]);
+        assert_eq!(copied.id, original.id);
+        assert_eq!(copied.checksum, original.checksum);
+        assert_eq!(
+            copied.span,
+            FileSpan::from_offset_length(HEADER_SIZE as u64 + 18, 18)
+        );
+        let ch2 = book.exclusive_read_chapter(22).unwrap();
+        assert_eq!(ch2.as_ref(), b"This is chapter 22");
+        assert_eq!(
+            book.find_chapter_by_name("twenty-two"),
+            Some(ChapterIndex(1))
+        );
+        assert!(book.exclusive_read_chapter(33).unwrap().is_empty());
+    }
+
+    #[test]
+    fn chapter_remaining() {
+        let mut book = BookWriter::new_in_memory(0x1234).unwrap();
+        book.write_chapter(11, b"").unwrap();
+        book.write_chapter(22, b"This is chapter 22").unwrap();
+        let mut book = book.close_to_book().unwrap();
+
+        let mut reader = book.exclusive_chapter_reader(22).unwrap();
+        assert_eq!(reader.len(), 18);
+        assert_eq!(reader.remaining(), 18);
+        let mut buf = [0u8; 8];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"This is ");
+        assert_eq!(reader.remaining(), 10);
+        reader.seek(SeekFrom::End(-2)).unwrap();
+        assert_eq!(reader.remaining(), 2);
+        reader.read_to_end(&mut vec![]).unwrap();
+        assert_eq!(reader.remaining(), 0);
+        assert!(!reader.is_empty());
+
+        let reader = book.exclusive_chapter_reader(11).unwrap();
+        assert!(reader.is_empty());
+        assert_eq!(reader.remaining(), 0);
+    }
+
+    #[test]
+    fn chapter_read_line() {
+        use std::io::{BufRead, BufReader};
+
+        let mut book = BookWriter::new_in_memory(0x1234).unwrap();
+        book.write_chapter(22, b"line one\nline two").unwrap();
+        let buffer = book.close().unwrap();
+
+        let mut book = Book::new(BufReader::new(buffer)).unwrap();
+        let mut reader = book.exclusive_chapter_reader(22).unwrap();
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(line, "line one\n");
+
+        // The last line stops at the end of the chapter, rather than
+        // continuing into the TOC.
+        line.clear();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(line, "line two");
+        assert_eq!(reader.read_line(&mut line).unwrap(), 0);
+    }
+
+    #[test]
+    fn content_type() {
+        let book = BookWriter::new_in_memory(0x1234).unwrap();
+        let mut chapter = book.new_chapter_typed(11, 7);
+        chapter.write_all(b"This is chapter 11").unwrap();
+        let mut book = chapter.close().unwrap();
+        book.write_chapter(22, b"This is chapter 22").unwrap();
+        let book = book.close_to_book().unwrap();
+
+        assert_eq!(book.chapter_content_type(ChapterIndex(0)), Some(7));
+        assert_eq!(book.chapter_content_type(ChapterIndex(1)), None);
+        assert_eq!(book.chapter_content_type(ChapterIndex(2)), None);
+    }
+
+    #[test]
+    fn estimate_size() {
+        let chapters = [(11, 0), (22, 18), (33, 18), (1 << 40, 100_000)];
+        let mut book = BookWriter::new_in_memory(0x1234).unwrap();
+        for &(id, length) in &chapters {
+            book.write_chapter(id, &vec![0x55; length.try_into().unwrap()])
+                .unwrap();
+        }
+        let (_, size) = book.close_with_size().unwrap();
+
+        assert_eq!(estimate_book_size(&chapters), size);
+
+        let empty = BookWriter::new_in_memory(0x1234).unwrap();
+        let (_, size) = empty.close_with_size().unwrap();
+        assert_eq!(estimate_book_size(&[]), size);
+    }
+
+    #[test]
+    fn latest_chapter() {
+        let mut book = BookWriter::new_in_memory(0x1234).unwrap();
+        book.write_chapter(5, b"first").unwrap();
+        book.write_chapter(6, b"other").unwrap();
+        let buffer = book.close().unwrap();
+        let mut book = BookWriter::open_append(buffer).unwrap();
+        book.write_chapter(5, b"second").unwrap();
+        let mut book = book.close_to_book().unwrap();
+
+        assert_eq!(book.find_chapter(5), Some(ChapterIndex(0)));
+        let index = book.find_latest_chapter(5).unwrap();
+        assert_eq!(index, ChapterIndex(2));
+        assert_eq!(book.read_index(index).unwrap().as_ref(), b"second");
+        assert_eq!(book.find_latest_chapter(6), Some(ChapterIndex(1)));
+        assert_eq!(book.find_latest_chapter(7), None);
+        assert_eq!(book.chapter_ids(), vec![5, 6]);
+    }
+
+    #[test]
+    fn chapter_value_scratch() {
+        // The scratch buffer must not leak bytes from a larger value
+        // into a smaller one.
+        let mut book = BookWriter::new_in_memory(0x1234).unwrap();
+        book.write_chapter_value(11, &vec![7u32; 100]).unwrap();
+        book.write_chapter_value(22, &"short").unwrap();
+        book.write_chapter_value(33, &vec![9u32; 10]).unwrap();
+        let mut book = book.close_to_book().unwrap();
+        book.verify().unwrap();
+
+        let ch1: Vec<u32> = book.read_chapter_value(ChapterIndex(0)).unwrap();
+        assert_eq!(ch1, vec![7; 100]);
+        let ch2: String = book.read_chapter_value(ChapterIndex(1)).unwrap();
+        assert_eq!(ch2, "short");
+        assert_eq!(book.chapter_len(ChapterIndex(1)).unwrap(), 6);
+        let ch3: Vec<u32> = book.read_chapter_value(ChapterIndex(2)).unwrap();
+        assert_eq!(ch3, vec![9; 10]);
+    }
+
+    /// A reader that counts seeks.
+    struct SeekCounter {
+        inner: Cursor<Vec<u8>>,
+        seeks: usize,
+    }
+
+    impl Read for SeekCounter {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.inner.read(buf)
+        }
+    }
+
+    impl Seek for SeekCounter {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.seeks += 1;
+            self.inner.seek(pos)
+        }
+    }
+
+    #[test]
+    fn chapter_cache() {
+        let mut book = BookWriter::new_in_memory(0x1234).unwrap();
+        book.write_chapter(11, b"This is chapter 11").unwrap();
+        book.write_chapter(22, b"This is chapter 22").unwrap();
+        book.write_chapter(33, b"This is chapter 33").unwrap();
+        let buffer = book.close().unwrap();
+        let reader = SeekCounter {
+            inner: buffer,
+            seeks: 0,
+        };
+        let mut book = Book::new(reader).unwrap().with_cache(40);
+
+        let seeks = book.reader.seeks;
+        let ch2 = book.exclusive_read_chapter(22).unwrap();
+        assert_eq!(ch2.as_ref(), b"This is chapter 22");
+        assert!(book.reader.seeks > seeks);
+
+        // The second read comes from the cache.
+        let seeks = book.reader.seeks;
+        let ch2 = book.exclusive_read_chapter(22).unwrap();
+        assert_eq!(ch2.as_ref(), b"This is chapter 22");
+        assert_eq!(book.reader.seeks, seeks);
+
+        // Unless the cache is bypassed.
+        let ch2 = book.exclusive_read_chapter_uncached(22).unwrap();
+        assert_eq!(ch2.as_ref(), b"This is chapter 22");
+        assert!(book.reader.seeks > seeks);
+
+        // Only two chapters fit, so reading two more evicts chapter 22.
+        book.exclusive_read_chapter(11).unwrap();
+        book.exclusive_read_chapter(33).unwrap();
+        let seeks = book.reader.seeks;
+        book.exclusive_read_chapter(33).unwrap();
+        assert_eq!(book.reader.seeks, seeks);
+        book.exclusive_read_chapter(22).unwrap();
+        assert!(book.reader.seeks > seeks);
+    }
+
+    #[test]
+    fn book_file_shared() {
+        let temp = tempfile::tempfile().unwrap();
+
+        let magic = 0x1234;
+        let file = {
+            let book = BookWriter::new(temp, magic).unwrap();
+            let chapter = book.new_chapter(11);
+            let book = chapter.close().unwrap();
+            let mut chapter = book.new_chapter(22);
+            chapter.write_all(b"This is chapter 22").unwrap();
+            let book = chapter.close().unwrap();
+            let mut chapter = book.new_chapter("🦀");
+            chapter.write_all(b"This is chapter 33").unwrap();
+            let book = chapter.close().unwrap();
+            book.close().unwrap()
+        };
+        let book = Book::new(file).unwrap();
+        let ch1 = book.read_chapter(11).unwrap();
+        assert!(ch1.is_empty());
+
+        assert!(!book.has_chapter(1));
+
+        let ch2 = book.read_chapter(22).unwrap();
+        assert_eq!(ch2.as_ref(), b"This is chapter 22");
+
+        let ch2 = book.read_chapter("🦀").unwrap();
+        assert_eq!(ch2.as_ref(), b"This is chapter 33");
+    }
+
+    #[test]
+    fn durability() {
+        let temp = tempfile::NamedTempFile::new().unwrap();
+        {
+            let mut book = BookWriter::new(temp.as_file(), 0x1234).unwrap();
+            book.write_chapter(11, b"This is chapter 11").unwrap();
+            book.flush_chapter_boundary().unwrap();
+            book.write_chapter(22, b"This is chapter 22").unwrap();
+            book.sync_all().unwrap();
+            book.write_chapter(33, b"This is chapter 33").unwrap();
+            // Dropped without a final sync.
+            book.close().unwrap();
+        }
+
+        let file = std::fs::File::open(temp.path()).unwrap();
+        let mut book = Book::new(file).unwrap();
+        book.verify().unwrap();
+        let ch3 = book.exclusive_read_chapter(33).unwrap();
+        assert_eq!(ch3.as_ref(), b"This is chapter 33");
+
+        // Syncing an in-memory buffer just flushes it.
+        let mut book = BookWriter::new_in_memory(0x1234).unwrap();
+        book.write_chapter(11, b"This is chapter 11").unwrap();
+        book.sync_all().unwrap();
+        let book = book.close_to_book().unwrap();
+        assert!(book.contains_chapter(11));
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn try_clone_reader() {
+        let mut file = tempfile::tempfile().unwrap();
+        {
+            let mut book = BookWriter::new(&mut file, 0x1234).unwrap();
+            for ii in 0..8u8 {
+                book.write_chapter(u64::from(ii), &[ii; 100]).unwrap();
+            }
+            book.close().unwrap();
+        }
+        let mut book = Book::new(file).unwrap();
+        book.verify_checksums(true);
+
+        let handles: Vec<_> = (0..8u8)
+            .map(|ii| {
+                let book = book.try_clone_reader().unwrap();
+                std::thread::spawn(move || {
+                    let chapter = book.read_chapter(u64::from(ii)).unwrap();
+                    assert_eq!(chapter.as_ref(), &[ii; 100][..]);
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn write_chapter() {
+        let magic = 0x1234;
+        let manual = {
+            let buffer = Cursor::new(Vec::<u8>::new());
+            let book = BookWriter::new(buffer, magic).unwrap();
+            let chapter = book.new_chapter(11);
+            let book = chapter.close().unwrap();
+            let mut chapter = book.new_chapter(22);
+            chapter.write_all(b"This is chapter 22").unwrap();
+            let book = chapter.close().unwrap();
+            book.close().unwrap()
+        };
+        let oneshot = {
+            let buffer = Cursor::new(Vec::<u8>::new());
+            let mut book = BookWriter::new(buffer, magic).unwrap();
+            book.write_chapter(11, b"").unwrap();
+            book.write_chapter(22, b"This is chapter 22").unwrap();
+            book.close().unwrap()
+        };
+        assert_eq!(manual.get_ref(), oneshot.get_ref());
+
+        let mut book = Book::new(oneshot).unwrap();
+        let ch2 = book.exclusive_read_chapter(22).unwrap();
+        assert_eq!(ch2.as_ref(), b"This is chapter 22");
+    }
+
+    #[test]
+    fn write_chapter_from() {
+        let magic = 0x1234;
+        let big_chapter: Vec<u8> = (0..=255u8).cycle().take(200_000).collect();
+        let buffer = {
+            let buffer = Cursor::new(Vec::<u8>::new());
+            let mut book = BookWriter::new(buffer, magic).unwrap();
+            let len = book.write_chapter_from(11, &mut io::empty()).unwrap();
+            assert_eq!(len, 0);
+            let len = book.write_chapter_from(22, &mut &big_chapter[..]).unwrap();
+            assert_eq!(len, 200_000);
+            book.write_chapter(33, b"This is chapter 33").unwrap();
+            book.close().unwrap()
+        };
+        let mut book = Book::new(buffer).unwrap();
+        book.verify_checksums(true);
+        let ch1 = book.exclusive_read_chapter(11).unwrap();
+        assert!(ch1.is_empty());
+        let ch2 = book.exclusive_read_chapter(22).unwrap();
+        assert_eq!(ch2.as_ref(), &big_chapter[..]);
+        let ch3 = book.exclusive_read_chapter(33).unwrap();
+        assert_eq!(ch3.as_ref(), b"This is chapter 33");
+    }
+
+    #[test]
+    fn write_chapter_from_error() {
+        // A reader that returns some bytes, and then an error.
+        struct FailingReader(bool);
+
+        impl Read for FailingReader {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                if self.0 {
+                    Err(io::Error::other("source failed"))
+                } else {
+                    self.0 = true;
+                    buf[..4].copy_from_slice(b"junk");
+                    Ok(4)
+                }
+            }
+        }
+
+        let magic = 0x1234;
+        let buffer = {
+            let buffer = Cursor::new(Vec::<u8>::new());
+            let mut book = BookWriter::new(buffer, magic).unwrap();
+            let err = book
+                .write_chapter_from(11, &mut FailingReader(false))
+                .unwrap_err();
+            assert!(matches!(err, BookError::Source(_)));
+            book.write_chapter(22, b"This is chapter 22").unwrap();
+            book.close().unwrap()
+        };
+        let mut book = Book::new(buffer).unwrap();
+        assert!(!book.has_chapter(11));
+        let ch2 = book.exclusive_read_chapter(22).unwrap();
+        assert_eq!(ch2.as_ref(), b"This is chapter 22");
+    }
+
+    #[test]
+    fn chapter_value() {
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Record {
+            name: String,
+            count: u32,
+            tags: Vec<u64>,
+        }
+
+        let record = Record {
+            name: "🦀".into(),
+            count: 33,
+            tags: vec![1, 2, 3],
+        };
+
+        let magic = 0x1234;
+        let buffer = {
+            let buffer = Cursor::new(Vec::<u8>::new());
+            let mut book = BookWriter::new(buffer, magic).unwrap();
+            book.write_chapter(11, b"not a value").unwrap();
+            book.write_chapter_value(22, &record).unwrap();
+            book.close().unwrap()
+        };
+        let mut book = Book::new(buffer).unwrap();
+        let index = book.find_chapter(22).unwrap();
+        let value: Record = book.read_chapter_value(index).unwrap();
+        assert_eq!(value, record);
+
+        let index = book.find_chapter(11).unwrap();
+        book.read_chapter_value::<Record>(index).unwrap_err();
+    }
+
+    #[test]
+    #[should_panic(expected = "dropped without calling close")]
+    fn strict_drop() {
+        let buffer = Cursor::new(Vec::<u8>::new());
+        let book = BookWriter::new(buffer, 0x1234).unwrap();
+        let mut chapter = book.new_chapter(11);
+        chapter.write_all(b"abandoned").unwrap();
+    }
+
+    #[test]
+    fn non_strict_drop() {
+        let mut buffer = Cursor::new(Vec::<u8>::new());
+        {
+            let mut book = BookWriter::new(&mut buffer, 0x1234).unwrap();
+            book.set_strict_drop(false);
+            book.write_chapter(22, b"This is chapter 22").unwrap();
+            let mut chapter = book.new_chapter(11);
+            chapter.write_all(b"abandoned").unwrap();
+        }
+        // The book is closed without the abandoned chapter, whose bytes
+        // are left behind as a gap.
+        let mut book = Book::new(buffer).unwrap();
+        assert_eq!(book.len(), 1);
+        assert!(!book.contains_chapter(11));
+        let ch2 = book.exclusive_read_chapter(22).unwrap();
+        assert_eq!(ch2.as_ref(), b"This is chapter 22");
+        assert_eq!(book.layout_gaps(), vec![(HEADER_SIZE as u64 + 18, 9)]);
+    }
+
+    #[test]
+    fn duplicate_ids() {
+        let magic = 0x1234;
+        let buffer = {
+            let buffer = Cursor::new(Vec::<u8>::new());
+            let mut book = BookWriter::new(buffer, magic).unwrap();
+            for (id, body) in [(7, "first"), (8, "other"), (7, "second"), (7, "third")] {
+                let mut chapter = book.new_chapter(id);
+                chapter.write_all(body.as_bytes()).unwrap();
+                book = chapter.close().unwrap();
+            }
+            book.close().unwrap()
+        };
+        let book = Book::new(buffer).unwrap();
+        assert_eq!(
+            book.find_all_chapters(7),
+            vec![ChapterIndex(0), ChapterIndex(2), ChapterIndex(3)]
+        );
+        assert_eq!(book.find_all_chapters(8), vec![ChapterIndex(1)]);
+        assert!(book.find_all_chapters(9).is_empty());
+        assert_eq!(book.count_chapters(7), 3);
+        assert_eq!(book.count_chapters(9), 0);
+
+        assert_eq!(book.find_chapter(7), Some(ChapterIndex(0)));
+        assert_eq!(book.find_chapter(8), Some(ChapterIndex(1)));
+        assert_eq!(book.find_chapter(9), None);
+    }
+
+    #[test]
+    fn front_toc() {
+        let magic = 0x1234;
+        let buffer = {
+            let buffer = Cursor::new(Vec::<u8>::new());
+            let mut book = BookWriter::with_reserved_toc(buffer, magic, 400).unwrap();
+            book.write_chapter(11, b"").unwrap();
+            book.write_chapter(22, b"This is chapter 22").unwrap();
+            book.write_chapter("🦀", b"This is chapter 33").unwrap();
+            book.close_front_toc().unwrap()
+        };
+        // Chapters start after the reserved region.
+        let book = Book::new(Cursor::new(buffer.get_ref().clone())).unwrap();
+        let span = book.chapter_span(ChapterIndex(1)).unwrap();
+        assert_eq!(span.0, HEADER_SIZE as u64 + 412);
+
+        // The whole book is readable in one forward pass.
+        let stream = ForwardReader::new(buffer.get_ref().as_slice());
+        let mut book = Book::new(stream).unwrap();
+        assert_eq!(book.len(), 3);
+        let ch2 = book.exclusive_read_chapter(22).unwrap();
+        assert_eq!(ch2.as_ref(), b"This is chapter 22");
+        let ch3 = book.exclusive_read_chapter("🦀").unwrap();
+        assert_eq!(ch3.as_ref(), b"This is chapter 33");
+
+        // If close() is used instead, the TOC at the end is used.
+        let buffer = {
+            let buffer = Cursor::new(Vec::<u8>::new());
+            let mut book = BookWriter::with_reserved_toc(buffer, magic, 400).unwrap();
+            book.write_chapter(22, b"This is chapter 22").unwrap();
+            book.close().unwrap()
+        };
+        let mut book = Book::new(buffer).unwrap();
+        let ch2 = book.exclusive_read_chapter(22).unwrap();
+        assert_eq!(ch2.as_ref(), b"This is chapter 22");
+    }
+
+    #[test]
+    fn front_toc_too_large() {
+        let magic = 0x1234;
+        let mut buffer = Cursor::new(Vec::<u8>::new());
+        let mut book = BookWriter::with_reserved_toc(&mut buffer, magic, 10).unwrap();
+        book.write_chapter(22, b"This is chapter 22").unwrap();
+        let err = book.close_front_toc().unwrap_err();
+        assert!(
+            matches!(err, BookError::TocTooLarge { max: 10, .. }),
+            "{:?}",
+            err
+        );
+
+        // The book is still readable using the TOC at the end.
+        buffer.set_position(0);
+        let mut book = Book::new(buffer).unwrap();
+        let ch2 = book.exclusive_read_chapter(22).unwrap();
+        assert_eq!(ch2.as_ref(), b"This is chapter 22");
+    }
+
+    #[test]
+    fn append_book() {
+        let magic = 0x1234;
+        let buffer = {
+            let buffer = Cursor::new(Vec::<u8>::new());
+            let mut book = BookWriter::new(buffer, magic).unwrap();
+            book.write_chapter(11, b"").unwrap();
+            book.write_chapter(22, b"This is chapter 22").unwrap();
+            book.close().unwrap()
+        };
+        let original = buffer.get_ref().clone();
+
+        // Appending nothing rewrites the same file.
+        let book = BookWriter::open_append(Cursor::new(original.clone())).unwrap();
+        let (buffer, size) = book.close_with_size().unwrap();
+        assert_eq!(buffer.get_ref(), &original);
+        assert_eq!(size, original.len() as u64);
+
+        let buffer = {
+            let mut book = BookWriter::open_append(buffer).unwrap();
+            book.write_chapter(33, b"This is chapter 33").unwrap();
+            book.write_chapter(22, b"This is chapter 22 again").unwrap();
+            book.close().unwrap()
+        };
+        let mut book = Book::new(buffer).unwrap();
+        book.verify_checksums(true);
+        assert_eq!(book.magic(), magic);
+        assert_eq!(book.len(), 4);
+        assert!(book.exclusive_read_chapter(11).unwrap().is_empty());
+        let ch2 = book.read_index(ChapterIndex(1)).unwrap();
+        assert_eq!(ch2.as_ref(), b"This is chapter 22");
+        let ch3 = book.exclusive_read_chapter(33).unwrap();
+        assert_eq!(ch3.as_ref(), b"This is chapter 33");
+        let ch4 = book.read_index(ChapterIndex(3)).unwrap();
+        assert_eq!(ch4.as_ref(), b"This is chapter 22 again");
+
+        // A book that can't be parsed is left untouched.
+        let mut truncated = original.clone();
+        truncated.truncate(original.len() - 1);
+        let mut stream = Cursor::new(truncated.clone());
+        BookWriter::open_append(&mut stream).unwrap_err();
+        assert_eq!(stream.get_ref(), &truncated);
+
+        // So is a book whose TOC points past the TOC offset.
+        let mut forged = original[..HEADER_SIZE].to_vec();
+        let mut toc = Toc::default();
+        toc.add(TocEntry {
+            id: ChapterId::from(22).0,
+            name: None,
+            span: FileSpan::from_offset_length(HEADER_SIZE as u64, 1000),
+            checksum: None,
+            encryption: None,
+            codec: None,
+            content_type: None,
+            removed: false,
+        });
+        let toc_buf = toc.to_bytes().unwrap();
+        append_toc(&mut forged, &toc_buf);
+        let mut stream = Cursor::new(forged.clone());
+        let err = BookWriter::open_append(&mut stream).unwrap_err();
+        assert!(
+            matches!(err, BookError::SpanOutOfBounds { .. }),
+            "{:?}",
+            err
+        );
+        assert_eq!(stream.get_ref(), &forged);
+    }
+
+    #[test]
+    fn append_overlap() {
+        let mut book = BookWriter::new_in_memory(0x1234).unwrap();
+        book.write_chapter(22, b"This is chapter 22").unwrap();
+        let buffer = book.close().unwrap();
+
+        // Simulate a bug that rewinds the writer over an existing chapter.
+        let mut book = BookWriter::open_append(buffer).unwrap();
+        let stream = book.writer.into_inner();
+        book.writer = CountingWriter::with_count(stream, HEADER_SIZE as u64 + 10);
+        book.write_chapter(33, b"This is chapter 33").unwrap();
+        let err = book.close().unwrap_err();
+        match err {
+            BookError::SpanOverlap { first, second } => {
+                assert_eq!(first.as_ref(), &22u64.to_be_bytes());
+                assert_eq!(second.as_ref(), &33u64.to_be_bytes());
+            }
+            _ => panic!("unexpected error {:?}", err),
+        }
+    }
+
+    #[test]
+    fn append_front_toc() {
+        let magic = 0x1234;
+        let buffer = {
+            let buffer = Cursor::new(Vec::<u8>::new());
+            let mut book = BookWriter::with_reserved_toc(buffer, magic, 300).unwrap();
+            book.write_chapter(22, b"This is chapter 22").unwrap();
+            book.close_front_toc().unwrap()
+        };
+
+        // Using close(), the stale front TOC must not be used.
+        let closed = {
+            let mut book = BookWriter::open_append(buffer.clone()).unwrap();
+            book.write_chapter(33, b"This is chapter 33").unwrap();
+            book.close().unwrap()
+        };
+        let mut book = Book::new(closed).unwrap();
+        assert_eq!(book.len(), 2);
+        let ch3 = book.exclusive_read_chapter(33).unwrap();
+        assert_eq!(ch3.as_ref(), b"This is chapter 33");
+
+        // Using close_front_toc(), the front TOC is rewritten.
+        let closed = {
+            let mut book = BookWriter::open_append(buffer).unwrap();
+            book.write_chapter(33, b"This is chapter 33").unwrap();
+            book.close_front_toc().unwrap()
+        };
+        let stream = ForwardReader::new(closed.get_ref().as_slice());
+        let mut book = Book::new(stream).unwrap();
+        assert_eq!(book.len(), 2);
+        let ch2 = book.exclusive_read_chapter(22).unwrap();
+        assert_eq!(ch2.as_ref(), b"This is chapter 22");
+        let ch3 = book.exclusive_read_chapter(33).unwrap();
+        assert_eq!(ch3.as_ref(), b"This is chapter 33");
+    }
+
+    #[test]
+    fn raw_toc() {
+        let buffer = {
+            let buffer = Cursor::new(Vec::<u8>::new());
+            let mut book = BookWriter::new(buffer, 0x1234).unwrap();
+            book.write_chapter(22, b"This is chapter 22").unwrap();
+            book.close().unwrap()
+        };
+        let file = buffer.get_ref().clone();
+        let mut book = Book::new(buffer).unwrap();
+        let raw = book.raw_toc().unwrap();
+        assert_eq!(raw.as_ref(), book.toc().to_bytes().unwrap().as_slice());
+        let trailer_offset = file.len() - 12;
+        assert_eq!(&file[trailer_offset - raw.len()..trailer_offset], &*raw);
+
+        // The book is still readable afterwards.
+        let toc = Toc::from_bytes(&raw).unwrap();
+        assert_eq!(toc.0.len(), 1);
+        let ch2 = book.exclusive_read_chapter(22).unwrap();
+        assert_eq!(ch2.as_ref(), b"This is chapter 22");
+    }
+
+    #[test]
+    fn lazy_toc() {
+        let magic = 0x1234;
+        let buffer = {
+            let buffer = Cursor::new(Vec::<u8>::new());
+            let mut book = BookWriter::new_with_meta(buffer, magic, b"lazy").unwrap();
+            book.write_chapter(22, b"This is chapter 22").unwrap();
+            book.write_chapter(33, b"This is chapter 33").unwrap();
+            book.close().unwrap()
+        };
+        let mut book = Book::new_lazy(buffer.clone()).unwrap();
+        assert_eq!(book.magic(), magic);
+        assert_eq!(book.user_meta(), b"lazy");
+        assert_eq!(book.find_chapter(33).unwrap(), Some(ChapterIndex(1)));
+        assert_eq!(book.find_chapter(44).unwrap(), None);
+        let ch2 = book.exclusive_read_chapter(22).unwrap();
+        assert_eq!(ch2.as_ref(), b"This is chapter 22");
+        let book = book.into_book().unwrap();
+        assert_eq!(book.len(), 2);
+        assert!(book.stats().toc_offset.is_some());
+
+        // A corrupt TOC isn't noticed until it's needed.
+        let mut buf = buffer.into_inner();
+        let toc_range = HEADER_SIZE + 36..buf.len() - 8;
+        buf[toc_range].fill(0xFF);
+        Book::new(Cursor::new(buf.clone())).unwrap_err();
+        let mut book = Book::new_lazy(Cursor::new(buf)).unwrap();
+        assert_eq!(book.magic(), magic);
+        let err = book.find_chapter(22).unwrap_err();
+        assert!(matches!(err, BookError::CorruptToc), "{:?}", err);
+    }
+
+    #[test]
+    fn prefetched_toc() {
+        let magic = 0x1234;
+        let buffer = {
+            let buffer = Cursor::new(Vec::<u8>::new());
+            let mut book = BookWriter::new(buffer, magic).unwrap();
+            book.write_chapter(11, b"").unwrap();
+            book.write_chapter(22, b"This is chapter 22").unwrap();
+            book.write_chapter(33, b"This is chapter 33").unwrap();
+            book.close().unwrap()
+        };
+        let book = Book::new(Cursor::new(buffer.get_ref().clone())).unwrap();
+        let header = book.header().clone();
+        let toc_bytes = book.toc().to_bytes().unwrap();
+        let toc = Toc::from_bytes(&toc_bytes).unwrap();
+
+        // Read from a stream that can't seek.
+        let stream = ForwardReader::new(buffer.get_ref().as_slice());
+        let mut book = Book::from_parts(stream, header, toc);
+        assert_eq!(book.magic(), magic);
+        let ch1 = book.exclusive_read_chapter(11).unwrap();
+        assert!(ch1.is_empty());
+        let ch3 = book.exclusive_read_chapter(33).unwrap();
+        assert_eq!(ch3.as_ref(), b"This is chapter 33");
+        // Going backwards isn't possible.
+        let err = book.exclusive_read_chapter(22).unwrap_err();
+        assert!(
+            matches!(
+                err,
+                BookError::IoContext {
+                    op: "reading chapter",
+                    ..
+                }
+            ),
+            "{:?}",
+            err
+        );
+
+        let err = Toc::from_bytes(&toc_bytes[..toc_bytes.len() - 1]).unwrap_err();
+        assert!(matches!(err, BookError::CorruptToc), "{:?}", err);
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn chapter_from_mmap() {
+        let mut map = memmap2::MmapMut::map_anon(64).unwrap();
+        for (byte, value) in map.iter_mut().zip(0u8..) {
+            *byte = value;
+        }
+        let map = map.make_read_only().unwrap();
+
+        let mut book = BookWriter::new_in_memory(0x1234).unwrap();
+        book.write_chapter_mmap(11, &map, 10..30).unwrap();
+        let err = book.write_chapter_mmap(22, &map, 60..70).unwrap_err();
+        assert!(
+            matches!(
+                err,
+                BookError::OutOfBounds {
+                    start: 60,
+                    len: 10,
+                    chapter_len: 64
+                }
+            ),
+            "{:?}",
+            err
+        );
+        let mut book = book.close_to_book().unwrap();
+        assert_eq!(book.len(), 1);
+        assert_eq!(book.chapter_len(ChapterIndex(0)).unwrap(), 20);
+        let ch1 = book.exclusive_read_chapter(11).unwrap();
+        assert_eq!(ch1.as_ref(), &map[10..30]);
+        book.verify().unwrap();
+    }
+
+    #[test]
+    fn manifest() {
+        let mut book = BookWriter::new_in_memory(0x1234).unwrap();
+        book.set_manifest(b"an old manifest");
+        book.write_chapter(11, b"This is chapter 11").unwrap();
+        book.set_manifest(b"key1=11");
+        book.write_chapter(22, b"This is chapter 22").unwrap();
+        let mut book = book.close_to_book().unwrap();
+        assert_eq!(book.len(), 3);
+        assert_eq!(
+            book.find_chapter(MANIFEST_CHAPTER_ID),
+            Some(ChapterIndex(2))
+        );
+        let manifest = book.manifest().unwrap().unwrap();
+        assert_eq!(manifest.as_ref(), b"key1=11");
+
+        // An appended manifest supersedes the original.
+        let mut book = BookWriter::open_append(book.into_inner()).unwrap();
+        book.set_manifest(b"key1=11,key2=33");
+        book.write_chapter(33, b"This is chapter 33").unwrap();
+        let buf = book.close().unwrap().into_inner();
+        let mut book = Book::new(Cursor::new(buf)).unwrap();
+        let manifest = book.manifest().unwrap().unwrap();
+        assert_eq!(manifest.as_ref(), b"key1=11,key2=33");
+
+        let mut book = BookWriter::new_in_memory(0x1234).unwrap();
+        book.write_chapter(11, b"This is chapter 11").unwrap();
+        let mut book = book.close_to_book().unwrap();
+        assert!(book.manifest().unwrap().is_none());
+    }
+
+    #[test]
+    fn read_all_chapters() {
+        let mut book = BookWriter::new_in_memory(0x1234).unwrap();
+        book.write_chapter(9, b"first").unwrap();
+        book.write_chapter(10, b"other").unwrap();
+        book.write_chapter(9, b"").unwrap();
+        book.write_chapter(9, b"third").unwrap();
+        let mut book = book.close_to_book().unwrap();
+
+        let chapters = book.read_all_chapters(9).unwrap();
+        let chapters: Vec<&[u8]> = chapters.iter().map(|ch| ch.as_ref()).collect();
+        assert_eq!(chapters, [&b"first"[..], b"", b"third"]);
+        assert!(book.read_all_chapters(11).unwrap().is_empty());
+    }
+
+    #[test]
+    fn dedup() {
+        let text = b"This chapter is stored once. ".repeat(10);
+        let write = |dedup| {
+            let mut book = BookBuilder::new()
+                .dedup(dedup)
+                .build(Cursor::new(Vec::<u8>::new()))
+                .unwrap();
+            book.write_chapter(1, &text).unwrap();
+            book.write_chapter(2, b"This one is different").unwrap();
+            book.write_chapter(3, &text).unwrap();
+            book.write_chapter(4, b"").unwrap();
+            book.write_chapter(5, b"").unwrap();
+            book.close().unwrap().into_inner()
+        };
+        let plain = write(false);
+        let buf = write(true);
+        assert_eq!(plain.len() - buf.len(), text.len());
+
+        let mut book = Book::new(Cursor::new(buf.clone())).unwrap();
+        book.verify().unwrap();
+        assert_eq!(book.len(), 5);
+        let span = |book: &Book<_>, index: usize| book.toc.0[index].span.clone();
+        assert_eq!(span(&book, 0), span(&book, 2));
+        assert_eq!(book.exclusive_read_chapter(1).unwrap().as_ref(), text);
+        assert_eq!(book.exclusive_read_chapter(3).unwrap().as_ref(), text);
+        assert!(book.layout_gaps().is_empty());
+        verify_sequential(buf.as_slice(), book.toc()).unwrap();
+
+        // Overwriting a shared chapter changes both.
+        let mut editor = BookEditor::open(Cursor::new(buf)).unwrap();
+        let edited = b"This chapter was overwritten. ".repeat(10);
+        let edited = &edited[..text.len()];
+        editor.overwrite_chapter(ChapterIndex(2), edited).unwrap();
+        let buf = editor.close().unwrap().into_inner();
+        let mut book = Book::new(Cursor::new(buf)).unwrap();
+        book.verify().unwrap();
+        assert_eq!(book.exclusive_read_chapter(1).unwrap().as_ref(), edited);
+
+        // Compacting one chapter leaves the shared bytes in place.
+        let mut editor = BookEditor::open(book.into_inner()).unwrap();
+        editor.remove_chapter_compact(ChapterIndex(0)).unwrap();
+        let buf = editor.close().unwrap().into_inner();
+        let mut book = Book::new(Cursor::new(buf)).unwrap();
+        book.verify().unwrap();
+        assert_eq!(book.len(), 4);
+        assert_eq!(book.exclusive_read_chapter(3).unwrap().as_ref(), edited);
+    }
+
+    #[test]
+    fn remove_chapter() {
+        let mut book = BookWriter::new_in_memory(0x1234).unwrap();
+        book.write_chapter(11, b"This is chapter 11").unwrap();
+        book.write_chapter(22, b"This is chapter 22").unwrap();
+        book.write_chapter(33, b"").unwrap();
+        book.write_chapter(44, &[0xAA; 1000]).unwrap();
+        let stream = book.close().unwrap();
+        let original_len = stream.get_ref().len();
+
+        // Tombstone: the bytes stay behind as a gap.
+        let mut editor = BookEditor::open(stream.clone()).unwrap();
+        editor.remove_chapter(ChapterIndex(1)).unwrap();
+        let err = editor.remove_chapter(ChapterIndex(1)).unwrap_err();
+        assert!(
+            matches!(err, BookError::ChapterRemoved { index: 1 }),
+            "{:?}",
+            err
+        );
+        let err = editor.remove_chapter(ChapterIndex(4)).unwrap_err();
+        assert!(
+            matches!(err, BookError::IndexOutOfRange { index: 4, len: 4 }),
+            "{:?}",
+            err
+        );
+        let mut book = Book::new(editor.close().unwrap()).unwrap();
+        book.verify().unwrap();
+        // The entry stays, so no indices change, but lookups skip it.
+        assert_eq!(book.len(), 4);
+        assert_eq!(book.find_chapter(22), None);
+        assert!(!book.contains_chapter(22));
+        assert_eq!(book.count_chapters(22), 0);
+        assert_eq!(book.chapter_ids(), vec![11, 33, 44]);
+        let err = book.exclusive_read_chapter(22).unwrap_err();
+        assert!(matches!(err, BookError::NoChapter), "{:?}", err);
+        let err = book.chapter_len(ChapterIndex(1)).unwrap_err();
+        assert!(
+            matches!(err, BookError::ChapterRemoved { index: 1 }),
+            "{:?}",
+            err
+        );
+        assert_eq!(book.find_chapter(33), Some(ChapterIndex(2)));
+        assert_eq!(book.find_chapter(44), Some(ChapterIndex(3)));
+        // The new TOC is padded to keep the file at its original length;
+        // that padding is also a gap.
+        let gaps = book.layout_gaps();
+        assert_eq!(gaps.len(), 2);
+        assert_eq!(gaps[0], (HEADER_SIZE as u64 + 18, 18));
+        let ch4 = book.exclusive_read_chapter(44).unwrap();
+        assert_eq!(ch4.as_ref(), [0xAA; 1000]);
+
+        // Removing the latest copy of an id uncovers the older one.
+        let mut book = BookWriter::new_in_memory(0x1234).unwrap();
+        book.write_chapter(11, b"old").unwrap();
+        book.write_chapter(11, b"new").unwrap();
+        let mut editor = BookEditor::open(book.close().unwrap()).unwrap();
+        editor.remove_chapter(ChapterIndex(1)).unwrap();
+        let mut book = Book::new(editor.close().unwrap()).unwrap();
+        assert_eq!(book.find_latest_chapter(11), Some(ChapterIndex(0)));
+        assert_eq!(book.find_all_chapters(11), vec![ChapterIndex(0)]);
+        let mut seen = Vec::new();
+        book.try_for_each_chapter(|_, data| {
+            seen.push(data.to_vec());
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(seen, vec![b"old".to_vec()]);
+
+        // Compact: the entries are dropped, later chapters move down, and
+        // the freed space ends up just before the TOC.
+        let mut editor = BookEditor::open(stream).unwrap();
+        editor.book.io_buffer_size = 7;
+        editor.remove_chapter_compact(ChapterIndex(0)).unwrap();
+        editor.remove_chapter_compact(ChapterIndex(1)).unwrap();
+        let stream = editor.close().unwrap();
+        assert_eq!(stream.get_ref().len(), original_len);
+        let mut book = Book::new(stream).unwrap();
+        book.verify().unwrap();
+        book.verify_checksums(true);
+        assert_eq!(book.len(), 2);
+        assert_eq!(book.find_chapter(11), None);
+        assert_eq!(book.find_chapter(33), None);
+        assert_eq!(
+            book.chapter_span(ChapterIndex(0)),
+            Some((HEADER_SIZE as u64, 18))
+        );
+        assert_eq!(
+            book.chapter_span(ChapterIndex(1)),
+            Some((HEADER_SIZE as u64 + 18, 1000))
+        );
+        let gaps = book.layout_gaps();
+        assert_eq!(gaps.len(), 1);
+        assert_eq!(gaps[0].0, HEADER_SIZE as u64 + 1018);
+        let ch2 = book.exclusive_read_chapter(22).unwrap();
+        assert_eq!(ch2.as_ref(), b"This is chapter 22");
+        let ch4 = book.exclusive_read_chapter(44).unwrap();
+        assert_eq!(ch4.as_ref(), [0xAA; 1000]);
+
+        // A tombstone's entry can be dropped by compacting it.
+        let mut editor = BookEditor::open(book.into_inner()).unwrap();
+        editor.remove_chapter(ChapterIndex(0)).unwrap();
+        editor.remove_chapter_compact(ChapterIndex(0)).unwrap();
+        let book = Book::new(editor.close().unwrap()).unwrap();
+        assert_eq!(book.len(), 1);
+        assert_eq!(book.find_chapter(44), Some(ChapterIndex(0)));
+    }
+
+    #[test]
+    fn overwrite_chapter() {
+        let mut book = BookWriter::new_in_memory(0x1234).unwrap();
+        book.write_chapter(11, b"This is chapter 11").unwrap();
+        book.write_chapter(22, b"This is chapter 22").unwrap();
+        book.set_checksum_kind(ChecksumKind::None);
+        book.write_chapter(33, b"This is chapter 33").unwrap();
+        let stream = book.close().unwrap();
+        let original_len = stream.get_ref().len();
+
+        let mut editor = BookEditor::open(stream).unwrap();
+        editor
+            .overwrite_chapter(ChapterIndex(0), b"THIS IS CHAPTER 11")
+            .unwrap();
+        editor
+            .overwrite_chapter(ChapterIndex(2), b"THIS IS CHAPTER 33")
+            .unwrap();
+        let err = editor
+            .overwrite_chapter(ChapterIndex(1), b"too short")
+            .unwrap_err();
+        assert!(
+            matches!(
+                err,
+                BookError::SizeMismatch {
+                    expected: 18,
+                    actual: 9
+                }
+            ),
+            "{:?}",
+            err
+        );
+        let stream = editor.close().unwrap();
+        assert_eq!(stream.get_ref().len(), original_len);
+
+        let mut book = Book::new(stream).unwrap();
+        book.verify().unwrap();
+        book.verify_checksums(true);
+        assert_eq!(book.len(), 3);
+        let ch1 = book.exclusive_read_chapter(11).unwrap();
+        assert_eq!(ch1.as_ref(), b"THIS IS CHAPTER 11");
+        let ch2 = book.exclusive_read_chapter(22).unwrap();
+        assert_eq!(ch2.as_ref(), b"This is chapter 22");
+        let ch3 = book.exclusive_read_chapter(33).unwrap();
+        assert_eq!(ch3.as_ref(), b"THIS IS CHAPTER 33");
+        assert_eq!(book.toc().0[2].checksum, None);
+    }
+
+    #[test]
+    fn sequential_verify() {
+        let mut book = BookWriter::new_in_memory(0x1234).unwrap();
+        book.write_chapter(11, b"This is chapter 11").unwrap();
+        book.write_chapter(22, b"").unwrap();
+        book.set_checksum_kind(ChecksumKind::None);
+        book.write_chapter(33, b"This is chapter 33").unwrap();
+        book.set_checksum_kind(ChecksumKind::Crc32);
+        book.write_chapter(44, b"This is chapter 44").unwrap();
+        let book = book.close_to_book().unwrap();
+        let toc = book.toc().clone();
+        let buf = book.into_inner().into_inner();
+
+        verify_sequential(buf.as_slice(), &toc).unwrap();
+
+        // A damaged chapter is detected, unless it has no checksum.
+        let ch4_offset = HEADER_SIZE + 36;
+        let mut corrupt = buf.clone();
+        corrupt[ch4_offset] ^= 0x01;
+        let err = verify_sequential(corrupt.as_slice(), &toc).unwrap_err();
+        match err {
+            BookError::ChecksumMismatch { id, .. } => assert_eq!(id, ChapterId::from(44).0),
+            _ => panic!("unexpected error {:?}", err),
+        }
+        let mut corrupt = buf.clone();
+        corrupt[HEADER_SIZE + 18] ^= 0x01;
+        verify_sequential(corrupt.as_slice(), &toc).unwrap();
+
+        // The stream ends partway through a chapter.
+        let err = verify_sequential(&buf[..ch4_offset + 5], &toc).unwrap_err();
+        assert!(matches!(err, BookError::Eof), "{:?}", err);
+    }
+
+    #[test]
+    fn probe_book() {
+        let mut book = BookWriter::new_in_memory(0x1234).unwrap();
+        for id in 0..30 {
+            book.write_chapter(id, b"chapter").unwrap();
+        }
+        let mut stream = book.close().unwrap();
+
+        let probed = probe(&mut stream).unwrap();
+        assert_eq!(
+            probed,
+            BookProbe {
+                bookwriter_magic: BOOK_V1_MAGIC,
+                user_magic: 0x1234,
+                header_version: FileHeader::VER,
+                toc_len: probed.toc_len,
+                chapter_count: 30,
+            }
+        );
+
+        // The stream can be rewound and read normally.
+        stream.seek(SeekFrom::Start(0)).unwrap();
+        let mut book = Book::new(stream).unwrap();
+        assert_eq!(book.raw_toc().unwrap().len() as u64, probed.toc_len);
+
+        let mut empty = BookWriter::new_in_memory(0x1234).unwrap().close().unwrap();
+        assert_eq!(probe(&mut empty).unwrap().chapter_count, 0);
+
+        let mut garbage = Cursor::new(vec![0xFFu8; HEADER_SIZE]);
+        let err = probe(&mut garbage).unwrap_err();
+        assert!(matches!(err, BookError::BadMagic { .. }), "{:?}", err);
+    }
+
+    #[test]
+    fn book_into_inner() {
+        let mut book = BookWriter::new_in_memory(0x1234).unwrap();
+        book.write_chapter(22, b"This is chapter 22").unwrap();
+        let buffer = book.close().unwrap().into_inner();
+
+        let mut book = Book::new(Cursor::new(buffer.clone())).unwrap();
+        assert_eq!(book.get_ref().get_ref(), &buffer);
+        book.get_mut().set_position(0);
+        let ch2 = book.exclusive_read_chapter(22).unwrap();
+        assert_eq!(ch2.as_ref(), b"This is chapter 22");
+        let cursor = book.into_inner();
+        assert_eq!(cursor.into_inner(), buffer);
+    }
+
+    #[test]
+    fn io_buffer_size() {
+        let data: Vec<u8> = (0..100).collect();
+        let mut book = BookBuilder::new()
+            .io_buffer_size(7)
+            .build(Cursor::new(Vec::<u8>::new()))
+            .unwrap();
+        let len = book.write_chapter_from(11, &mut data.as_slice()).unwrap();
+        assert_eq!(len, 100);
+        let mut src = book.close_to_book().unwrap();
+
+        let mut book = BookBuilder::new()
+            .io_buffer_size(7)
+            .build(Cursor::new(Vec::<u8>::new()))
+            .unwrap();
+        book.copy_chapter_from(&mut src, ChapterIndex(0)).unwrap();
+        let mut book = book.close_to_book().unwrap();
+        book.verify_checksums(true);
+        let ch1 = book.exclusive_read_chapter(11).unwrap();
+        assert_eq!(ch1.as_ref(), data.as_slice());
+    }
+
+    #[test]
+    fn write_progress() {
+        let data: Vec<u8> = (0..100).collect();
+        let mut book = BookBuilder::new()
+            .io_buffer_size(7)
+            .build(Cursor::new(Vec::<u8>::new()))
+            .unwrap();
+        let mut calls = Vec::new();
+        let len = book
+            .write_chapter_from_with_progress(11, &mut data.as_slice(), &mut |n| calls.push(n))
+            .unwrap();
+        assert_eq!(len, 100);
+        assert!(calls.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(calls.last(), Some(&100));
+        assert_eq!(calls.len(), 15);
+
+        // An empty chapter reports no progress.
+        calls.clear();
+        let len = book
+            .write_chapter_from_with_progress(22, &mut io::empty(), &mut |n| calls.push(n))
+            .unwrap();
+        assert_eq!(len, 0);
+        assert!(calls.is_empty());
+
+        let mut book = book.close_to_book().unwrap();
+        let ch1 = book.exclusive_read_chapter(11).unwrap();
+        assert_eq!(ch1.as_ref(), data.as_slice());
+    }
+
+    #[test]
+    fn try_for_each_chapter() {
+        let mut book = BookWriter::new_in_memory(0x1234).unwrap();
+        book.write_chapter(1, b"This is chapter 1").unwrap();
+        book.write_chapter(2, b"This is chapter 2").unwrap();
+        book.write_chapter(3, b"This is chapter 3").unwrap();
+        let mut book = book.close_to_book().unwrap();
+
+        let mut seen = Vec::new();
+        book.try_for_each_chapter(|id, data| {
+            seen.push((id.to_vec(), data.to_vec()));
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(seen.len(), 3);
+        assert_eq!(seen[2].0, ChapterId::from(3).0.as_ref());
+        assert_eq!(seen[2].1, b"This is chapter 3");
+
+        // Stop at an error returned by the closure.
+        let mut seen = Vec::new();
+        let err = book
+            .try_for_each_chapter(|id, _| {
+                if id == ChapterId::from(2).0.as_ref() {
+                    return Err(BookError::NoChapter);
+                }
+                seen.push(id.to_vec());
+                Ok(())
+            })
+            .unwrap_err();
+        assert!(matches!(err, BookError::NoChapter));
+        assert_eq!(seen, vec![ChapterId::from(1).0.to_vec()]);
+    }
+
+    #[test]
+    fn chapter_read_stops_at_bound() {
+        let mut book = BookWriter::new_in_memory(0x1234).unwrap();
+        book.write_chapter(22, b"This is chapter 22").unwrap();
+        book.write_chapter(33, b"This is chapter 33").unwrap();
+        let mut book = book.close_to_book().unwrap();
+
+        // The next chapter and the TOC follow chapter 22 in the stream,
+        // but a single large read must not return any of them.
+        let mut reader = book.exclusive_chapter_reader(22).unwrap();
+        let mut buf = [0u8; 1000];
+        let n = reader.read(&mut buf).unwrap();
+        assert_eq!(n, 18);
+        assert_eq!(&buf[..n], b"This is chapter 22");
+        assert_eq!(reader.read(&mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn extract_chapter() {
+        let mut book = BookWriter::new_in_memory(0x1234).unwrap();
+        book.write_chapter(11, b"This is chapter 11").unwrap();
+        book.write_chapter(22, &[0xAA; 100_000]).unwrap();
+        book.write_chapter(33, b"").unwrap();
+        let mut book = book.close_to_book().unwrap();
+
+        let index = book.find_chapter(22).unwrap();
+        let mut out = Vec::new();
+        let len = book.extract_chapter(index, &mut out).unwrap();
+        assert_eq!(len, 100_000);
+        assert_eq!(out, [0xAA; 100_000]);
+
+        // Output is appended to whatever the writer already holds.
+        let len = book.extract_chapter(ChapterIndex(0), &mut out).unwrap();
+        assert_eq!(len, 18);
+        assert_eq!(&out[100_000..], b"This is chapter 11");
+
+        let len = book.extract_chapter(ChapterIndex(2), &mut out).unwrap();
+        assert_eq!(len, 0);
+        let err = book.extract_chapter(ChapterIndex(3), &mut out).unwrap_err();
+        assert!(
+            matches!(err, BookError::IndexOutOfRange { index: 3, len: 3 }),
+            "{:?}",
+            err
+        );
+    }
+
+    #[test]
+    fn index_out_of_range() {
+        let mut book = BookWriter::new_in_memory(0x1234).unwrap();
+        book.write_chapter(11, b"This is chapter 11").unwrap();
+        book.write_chapter(22, b"This is chapter 22").unwrap();
+        let mut book = book.close_to_book().unwrap();
+
+        let err = book
+            .read_chapter_value::<u32>(ChapterIndex(999))
+            .unwrap_err();
+        assert!(
+            matches!(err, BookError::IndexOutOfRange { index: 999, len: 2 }),
+            "{:?}",
+            err
+        );
+        let err = book.chapter_len(ChapterIndex(2)).unwrap_err();
+        assert!(
+            matches!(err, BookError::IndexOutOfRange { index: 2, len: 2 }),
+            "{:?}",
+            err
+        );
+        // A missing id is still reported as a missing chapter.
+        let err = book.exclusive_read_chapter(999).unwrap_err();
+        assert!(matches!(err, BookError::NoChapter), "{:?}", err);
+    }
+
+    #[test]
+    fn concat_reader() {
+        let mut book = BookWriter::new_in_memory(0x1234).unwrap();
+        book.write_chapter(11, b"").unwrap();
+        book.write_chapter(22, b"This is chapter 22").unwrap();
+        book.write_chapter(33, b"This is chapter 33").unwrap();
+        let mut book = book.close_to_book().unwrap();
+
+        let mut out = Vec::new();
+        book.concat_reader(&[ChapterIndex(0), ChapterIndex(1)])
+            .unwrap()
+            .read_to_end(&mut out)
+            .unwrap();
+        assert_eq!(out, b"This is chapter 22");
+
+        // Chapters are read in the order given, and may repeat.
+        let indices = [
+            ChapterIndex(2),
+            ChapterIndex(0),
+            ChapterIndex(1),
+            ChapterIndex(2),
+        ];
+        let mut out = Vec::new();
+        {
+            let mut reader = book.concat_reader(&indices).unwrap();
+            let mut chunk = [0u8; 7];
+            loop {
+                let bytes_read = reader.read(&mut chunk).unwrap();
+                if bytes_read == 0 {
+                    break;
+                }
+                out.extend_from_slice(&chunk[..bytes_read]);
+            }
+        }
+        let expected = [
+            &b"This is chapter 33"[..],
+            b"This is chapter 22",
+            b"This is chapter 33",
+        ]
+        .concat();
+        assert_eq!(out, expected);
+
+        let mut out = Vec::new();
+        book.concat_reader(&[])
+            .unwrap()
+            .read_to_end(&mut out)
+            .unwrap();
+        assert!(out.is_empty());
+        let err = book
+            .concat_reader(&[ChapterIndex(1), ChapterIndex(3)])
+            .err()
+            .unwrap();
+        assert!(
+            matches!(err, BookError::IndexOutOfRange { index: 3, len: 3 }),
+            "{:?}",
+            err
+        );
+    }
+
+    #[test]
+    fn chapter_reader_checked() {
+        let text = b"This is chapter 22. ".repeat(10);
+        let mut book = BookWriter::new_in_memory(0x1234).unwrap();
+        book.set_checksum_kind(ChecksumKind::Crc32);
+        book.write_chapter(11, b"").unwrap();
+        book.write_chapter(22, &text).unwrap();
+        let book = book.close_to_book().unwrap();
+        let mut buf = book.into_inner().into_inner();
+
+        let mut book = Book::new(Cursor::new(buf.clone())).unwrap();
+        let mut out = Vec::new();
+        let mut reader = book.chapter_reader_checked(ChapterIndex(1)).unwrap();
+        reader.read_to_end(&mut out).unwrap();
+        assert_eq!(out, text);
+        let mut reader = book.chapter_reader_checked(ChapterIndex(0)).unwrap();
+        assert_eq!(reader.read(&mut [0u8; 16]).unwrap(), 0);
+
+        // Damage the first byte of chapter 22. Reads succeed until the
+        // one that reaches the end of the chapter.
+        buf[HEADER_SIZE] ^= 0x01;
+        let mut book = Book::new(Cursor::new(buf)).unwrap();
+        let mut reader = book.chapter_reader_checked(ChapterIndex(1)).unwrap();
+        let mut chunk = [0u8; 50];
+        for _ in 0..3 {
+            reader.read_exact(&mut chunk).unwrap();
+        }
+        let err = reader.read(&mut chunk).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let inner = err.into_inner().unwrap().downcast::<BookError>().unwrap();
+        match *inner {
+            BookError::ChecksumMismatch { id, .. } => assert_eq!(id, ChapterId::from(22).0),
+            other => panic!("unexpected error {:?}", other),
+        }
+    }
+
+    #[test]
+    fn chapter_readers() {
+        let mut book = BookWriter::new_in_memory(0x1234).unwrap();
+        book.write_chapter(11, b"This is chapter 11").unwrap();
+        book.write_chapter(22, b"").unwrap();
+        book.write_chapter(b"third".as_ref(), b"This is the third chapter")
+            .unwrap();
+        let mut book = book.close_to_book().unwrap();
+
+        // Transform every chapter into a new book.
+        let mut output = BookWriter::new_in_memory(0x1234).unwrap();
+        let mut readers = book.chapter_readers();
+        while let Some(result) = readers.next() {
+            let (id, mut reader) = result.unwrap();
+            let mut contents = Vec::new();
+            reader.read_to_end(&mut contents).unwrap();
+            contents.make_ascii_uppercase();
+            output.write_chapter(id, &contents).unwrap();
+        }
+        assert!(readers.next().is_none());
+
+        let mut output = output.close_to_book().unwrap();
+        assert_eq!(output.len(), 3);
+        let ch1 = output.exclusive_read_chapter(11).unwrap();
+        assert_eq!(ch1.as_ref(), b"THIS IS CHAPTER 11");
+        assert!(output.exclusive_read_chapter(22).unwrap().is_empty());
+        let ch3 = output.exclusive_read_chapter(b"third".as_ref()).unwrap();
+        assert_eq!(ch3.as_ref(), b"THIS IS THE THIRD CHAPTER");
+    }
+
+    #[test]
+    fn chapter_write_vectored() {
+        let book = BookWriter::new_in_memory(0x1234).unwrap();
+        let mut chapter = book.new_chapter(22);
+        let bufs = [
+            IoSlice::new(b"This "),
+            IoSlice::new(b""),
+            IoSlice::new(b"is chapter 22"),
+        ];
+        assert_eq!(chapter.write_vectored(&bufs).unwrap(), 18);
+        assert_eq!(chapter.current_len(), 18);
+        let book = chapter.close().unwrap();
+
+        let mut book = book.close_to_book().unwrap();
+        book.verify_checksums(true);
+        let ch2 = book.exclusive_read_chapter(22).unwrap();
+        assert_eq!(ch2.as_ref(), b"This is chapter 22");
+        book.verify().unwrap();
+    }
+
+    #[test]
+    fn format_versions() {
+        let mut book = BookWriter::new_in_memory(0x1234).unwrap();
+        book.write_chapter(22, b"This is chapter 22").unwrap();
+        let book = book.close_to_book().unwrap();
+        assert_eq!(book.format_versions(), FormatVersions::default());
+
+        // A file with a V1 header and a V1 TOC.
+        let mut buf = Vec::<u8>::new();
+        let v1_header = FileHeaderV1 {
+            bookwriter_magic: BOOK_V1_MAGIC,
+            user_magic: 0x1234,
+        };
+        CborData::new(&mut buf).write_message(&v1_header).unwrap();
+        buf.resize(HEADER_SIZE, 0);
+        buf.extend_from_slice(b"This is chapter 22");
+        let toc = TocV1(vec![TocEntryV1 {
+            id: 22,
+            span: Some(FileSpanV1 {
+                length: 18.try_into().unwrap(),
+                offset: HEADER_SIZE as u64,
+            }),
+        }]);
+        let mut toc_writer = CborData::new(Vec::<u8>::new());
+        toc_writer.write_message(&toc).unwrap();
+        let toc_buf = toc_writer.into_inner();
+        buf.extend_from_slice(&toc_buf);
+        buf.write_u64::<BigEndian>(toc_buf.len() as u64).unwrap();
+
+        let mut book = Book::new(Cursor::new(buf)).unwrap();
+        assert_eq!(book.format_versions(), FormatVersions { header: 1, toc: 1 });
+        let ch2 = book.exclusive_read_chapter(22).unwrap();
+        assert_eq!(ch2.as_ref(), b"This is chapter 22");
+    }
+
+    #[test]
+    fn toc_compat() {
+        let toc = vec![TocEntryV1 {
+            id: 1234,
+            span: Some(FileSpanV1 {
+                length: 33.try_into().unwrap(),
+                offset: 44,
+            }),
+        }];
+        let toc = TocV1(toc);
         let toc = TocV2::from_version(toc);
         assert_eq!(toc.0.len(), 1);
         assert_eq!(
@@ -693,6 +6831,538 @@ mod tests {
                     offset: 44,
                 }),
             }
-        )
+        );
+        let toc = TocV3::from_version(toc);
+        assert_eq!(toc.0.len(), 1);
+        assert_eq!(toc.0[0].crc32, None);
+        let toc = TocV4::from_version(toc);
+        assert_eq!(toc.0.len(), 1);
+        assert_eq!(toc.0[0].name, None);
+        assert_eq!(toc.0[0].crc32, None);
+        let toc = TocV5::from_version(toc);
+        assert_eq!(toc.0.len(), 1);
+        assert_eq!(toc.0[0].checksum, None);
+        let toc = TocV6::from_version(toc);
+        assert_eq!(toc.0.len(), 1);
+        assert_eq!(toc.0[0].encryption, None);
+        let toc = TocV7::from_version(toc);
+        assert_eq!(toc.0.len(), 1);
+        assert_eq!(toc.0[0].codec, None);
+        let toc = TocV8::from_version(toc);
+        assert_eq!(toc.0.len(), 1);
+        assert_eq!(toc.0[0].content_type, None);
+        let toc = TocV9::from_version(toc);
+        assert_eq!(toc.0.len(), 1);
+        assert!(!toc.0[0].removed);
+    }
+
+    #[test]
+    fn named_chapters() {
+        let magic = 0x1234;
+        let buffer = {
+            let buffer = Cursor::new(Vec::<u8>::new());
+            let mut book = BookWriter::new(buffer, magic).unwrap();
+            book.write_chapter(11, b"unnamed").unwrap();
+            let mut chapter = book.new_chapter_named(22, "twenty-two");
+            chapter.write_all(b"This is chapter 22").unwrap();
+            let book = chapter.close().unwrap();
+            book.close().unwrap()
+        };
+        let book = Book::new(buffer).unwrap();
+        assert_eq!(
+            book.find_chapter_by_name("twenty-two"),
+            Some(ChapterIndex(1))
+        );
+        assert_eq!(book.find_chapter_by_name("eleven"), None);
+        assert_eq!(book.find_chapter_by_name(""), None);
+    }
+
+    #[test]
+    fn chapter_checksum() {
+        let magic = 0x1234;
+        let mut buffer = {
+            let buffer = Cursor::new(Vec::<u8>::new());
+            let mut book = BookWriter::new(buffer, magic).unwrap();
+            book.set_checksum_kind(ChecksumKind::Crc32);
+            let chapter = book.new_chapter(11);
+            let book = chapter.close().unwrap();
+            let mut chapter = book.new_chapter(22);
+            chapter.write_all(b"This is chapter 22").unwrap();
+            let book = chapter.close().unwrap();
+            book.close().unwrap()
+        };
+
+        // Corrupt the first byte of chapter 22.
+        buffer.get_mut()[HEADER_SIZE] ^= 0xFF;
+
+        let mut book = Book::new(buffer).unwrap();
+        // Verification is off by default.
+        let ch2 = book.exclusive_read_chapter(22).unwrap();
+        assert_ne!(ch2.as_ref(), b"This is chapter 22");
+
+        book.verify_checksums(true);
+        let ch1 = book.exclusive_read_chapter(11).unwrap();
+        assert!(ch1.is_empty());
+        let err = book.exclusive_read_chapter(22).unwrap_err();
+        match err {
+            BookError::ChecksumMismatch {
+                id,
+                expected,
+                actual,
+            } => {
+                assert_eq!(id.as_ref(), &22u64.to_be_bytes());
+                let expected_crc = Crc32::checksum(b"This is chapter 22");
+                assert_eq!(expected, u64::from(expected_crc));
+                assert_eq!(actual, u64::from(Crc32::checksum(&ch2)));
+            }
+            _ => panic!("unexpected error {:?}", err),
+        }
+    }
+
+    #[test]
+    fn default_checksum() {
+        let crc = Crc32::checksum(b"This is chapter 22");
+
+        let mut book = BookWriter::new_in_memory(0x1234).unwrap();
+        book.write_chapter(22, b"This is chapter 22").unwrap();
+        let book = book.close_to_book().unwrap();
+        assert_eq!(book.toc().0[0].checksum, None);
+
+        let mut book = BookBuilder::new()
+            .build(Cursor::new(Vec::<u8>::new()))
+            .unwrap();
+        book.write_chapter(22, b"This is chapter 22").unwrap();
+        let book = book.close_to_book().unwrap();
+        assert_eq!(book.toc().0[0].checksum, Some(Checksum::Crc32(crc)));
+    }
+
+    #[test]
+    fn checksum_kinds() {
+        let mut buffer = {
+            let buffer = Cursor::new(Vec::<u8>::new());
+            let mut book = BookWriter::new(buffer, 0x1234).unwrap();
+            book.set_checksum_kind(ChecksumKind::None);
+            book.write_chapter(11, b"This is chapter 11").unwrap();
+            book.set_checksum_kind(ChecksumKind::Crc32);
+            let mut chapter = book.new_chapter(22);
+            chapter.write_all(b"This is chapter 22").unwrap();
+            chapter.close().unwrap().close().unwrap()
+        };
+        let book = Book::new(Cursor::new(buffer.get_ref().clone())).unwrap();
+        assert_eq!(book.toc().0[0].checksum, None);
+        let crc = Crc32::checksum(b"This is chapter 22");
+        assert_eq!(book.toc().0[1].checksum, Some(Checksum::Crc32(crc)));
+
+        // Corrupting a chapter without a checksum goes unnoticed.
+        buffer.get_mut()[HEADER_SIZE] ^= 0xFF;
+        let mut book = Book::new(buffer).unwrap();
+        book.verify_checksums(true);
+        book.exclusive_read_chapter(11).unwrap();
+        book.exclusive_read_chapter(22).unwrap();
+    }
+
+    #[cfg(feature = "xxhash")]
+    #[test]
+    fn xxhash_checksum() {
+        use crate::checksum::XxHash64;
+
+        let mut buffer = {
+            let buffer = Cursor::new(Vec::<u8>::new());
+            let mut book = BookWriter::new(buffer, 0x1234).unwrap();
+            book.set_checksum_kind(ChecksumKind::XxHash64);
+            let mut chapter = book.new_chapter(11);
+            chapter.write_all(b"This is ").unwrap();
+            chapter.write_all(b"chapter 11").unwrap();
+            let mut book = chapter.close().unwrap();
+            book.write_chapter_from(22, &mut &b"This is chapter 22"[..])
+                .unwrap();
+            book.set_checksum_kind(ChecksumKind::Crc32);
+            book.write_chapter(33, b"This is chapter 33").unwrap();
+            book.close().unwrap()
+        };
+        let book = Book::new(Cursor::new(buffer.get_ref().clone())).unwrap();
+        let hash = XxHash64::checksum(b"This is chapter 11");
+        assert_eq!(book.toc().0[0].checksum, Some(Checksum::XxHash64(hash)));
+        let hash = XxHash64::checksum(b"This is chapter 22");
+        assert_eq!(book.toc().0[1].checksum, Some(Checksum::XxHash64(hash)));
+        let crc = Crc32::checksum(b"This is chapter 33");
+        assert_eq!(book.toc().0[2].checksum, Some(Checksum::Crc32(crc)));
+
+        // Corrupt chapter 11.
+        buffer.get_mut()[HEADER_SIZE] ^= 0xFF;
+        let mut book = Book::new(buffer).unwrap();
+        book.verify_checksums(true);
+        let err = book.exclusive_read_chapter(11).unwrap_err();
+        match err {
+            BookError::ChecksumMismatch { expected, .. } => {
+                assert_eq!(expected, XxHash64::checksum(b"This is chapter 11"));
+            }
+            _ => panic!("unexpected error {:?}", err),
+        }
+        book.exclusive_read_chapter(22).unwrap();
+        book.exclusive_read_chapter(33).unwrap();
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn encrypted_chapter() {
+        let key = [0x42; 32];
+        let buffer = {
+            let buffer = Cursor::new(Vec::<u8>::new());
+            let mut book = BookWriter::new(buffer, 0x1234).unwrap();
+            book.write_chapter(11, b"This is chapter 11").unwrap();
+            let mut chapter = book.new_chapter_encrypted(22, &key);
+            chapter.write_all(b"This is ").unwrap();
+            chapter.write_all(b"chapter 22").unwrap();
+            let book = chapter.close().unwrap();
+            let chapter = book.new_chapter_encrypted(33, &key);
+            let book = chapter.close().unwrap();
+            book.close().unwrap()
+        };
+        let original = buffer.into_inner();
+        let mut book = Book::new(Cursor::new(original.clone())).unwrap();
+        book.verify().unwrap();
+
+        // The span covers the ciphertext, which is the same size as the
+        // plaintext; the tag is stored in the TOC.
+        assert_eq!(
+            book.chapter_span(ChapterIndex(1)),
+            Some((HEADER_SIZE as u64 + 18, 18))
+        );
+        let ciphertext = book.exclusive_read_chapter(22).unwrap();
+        assert_ne!(ciphertext.as_ref(), b"This is chapter 22");
+
+        let mut reader = book
+            .chapter_reader_decrypted(ChapterIndex(1), &key)
+            .unwrap();
+        let mut plaintext = vec![];
+        reader.read_to_end(&mut plaintext).unwrap();
+        assert_eq!(plaintext, b"This is chapter 22");
+        let reader = book
+            .chapter_reader_decrypted(ChapterIndex(2), &key)
+            .unwrap();
+        assert!(reader.into_inner().is_empty());
+
+        let err = book
+            .chapter_reader_decrypted(ChapterIndex(0), &key)
+            .unwrap_err();
+        assert!(matches!(err, BookError::NotEncrypted), "{:?}", err);
+        let err = book
+            .chapter_reader_decrypted(ChapterIndex(1), &[0x43; 32])
+            .unwrap_err();
+        assert!(matches!(err, BookError::DecryptionFailed), "{:?}", err);
+
+        // Tampering with the ciphertext is detected.
+        let mut tampered = original;
+        tampered[HEADER_SIZE + 18] ^= 0x01;
+        let mut book = Book::new(Cursor::new(tampered)).unwrap();
+        let err = book
+            .chapter_reader_decrypted(ChapterIndex(1), &key)
+            .unwrap_err();
+        assert!(matches!(err, BookError::DecryptionFailed), "{:?}", err);
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn compressed_toc() {
+        let write = |compress| {
+            let mut book = BookBuilder::new()
+                .compress_toc(compress)
+                .build(Cursor::new(Vec::<u8>::new()))
+                .unwrap();
+            for id in 0..10_000u64 {
+                book.write_chapter(id, b"x").unwrap();
+            }
+            book.close().unwrap().into_inner()
+        };
+        let plain = write(false);
+        let compressed = write(true);
+        assert!(compressed.len() * 2 < plain.len());
+
+        let mut plain = Book::new(Cursor::new(plain)).unwrap();
+        let mut book = Book::new(Cursor::new(compressed)).unwrap();
+        book.verify().unwrap();
+        assert_eq!(book.len(), 10_000);
+        assert_eq!(book.raw_toc().unwrap(), plain.raw_toc().unwrap());
+        let ch = book.exclusive_read_chapter(9_999).unwrap();
+        assert_eq!(ch.as_ref(), b"x");
+        let probed = probe(book.get_mut()).unwrap();
+        assert_eq!(probed.chapter_count, 10_000);
+
+        // Appending keeps the TOC compressed.
+        let mut book = BookWriter::open_append(book.into_inner()).unwrap();
+        book.write_chapter(10_000, b"y").unwrap();
+        let buf = book.close().unwrap().into_inner();
+        let mut book = Book::new(Cursor::new(buf)).unwrap();
+        assert_eq!(book.len(), 10_001);
+        let ch = book.exclusive_read_chapter(10_000).unwrap();
+        assert_eq!(ch.as_ref(), b"y");
+    }
+
+    #[test]
+    fn too_many_chapters() {
+        // Replace the empty entry array with a forged length.
+        let empty = Toc::default().to_bytes().unwrap();
+        assert_eq!(empty.last(), Some(&0x80));
+        let forge = |count: u64| {
+            let mut buf = empty[..empty.len() - 1].to_vec();
+            buf.push(0x9B);
+            buf.extend_from_slice(&count.to_be_bytes());
+            buf
+        };
+        let err = Toc::from_bytes(&forge(u64::MAX)).unwrap_err();
+        assert!(
+            matches!(err, BookError::TooManyChapters { count: u64::MAX }),
+            "{:?}",
+            err
+        );
+        let err = Toc::from_bytes(&forge(MAX_CHAPTERS as u64 + 1)).unwrap_err();
+        assert!(
+            matches!(err, BookError::TooManyChapters { .. }),
+            "{:?}",
+            err
+        );
+
+        // A length under the limit fails when the entries run out.
+        let err = Toc::from_bytes(&forge(MAX_CHAPTERS as u64)).unwrap_err();
+        assert!(matches!(err, BookError::CorruptToc), "{:?}", err);
+    }
+
+    #[test]
+    fn record_toc() {
+        let mut book = BookBuilder::new()
+            .record_toc(true)
+            .build(Cursor::new(Vec::<u8>::new()))
+            .unwrap();
+        for id in 0..100u64 {
+            book.write_chapter(id, format!("chapter {}", id).as_bytes())
+                .unwrap();
+        }
+        let buf = book.close().unwrap().into_inner();
+
+        let mut book = Book::new(Cursor::new(buf)).unwrap();
+        book.verify().unwrap();
+        assert_eq!(book.len(), 100);
+        let ch = book.exclusive_read_chapter(42).unwrap();
+        assert_eq!(ch.as_ref(), b"chapter 42");
+        let raw = book.raw_toc().unwrap();
+        assert_eq!(raw[..2], TOC_RECORDS_ID.to_be_bytes());
+        let toc = Toc::from_bytes(&raw).unwrap();
+        assert_eq!(toc.0.len(), 100);
+        assert_eq!(*toc.0[42].id, *book.toc.0[42].id);
+        let probed = probe(book.get_mut()).unwrap();
+        assert_eq!(probed.chapter_count, 100);
+
+        // Appending copies the existing records and adds new ones.
+        let mut writer = BookWriter::open_append(book.into_inner()).unwrap();
+        writer.write_chapter(100u64, b"appended").unwrap();
+        let buf = writer.close().unwrap().into_inner();
+        let mut book = Book::new(Cursor::new(buf)).unwrap();
+        book.verify().unwrap();
+        assert_eq!(book.len(), 101);
+        let appended = book.raw_toc().unwrap();
+        assert!(appended.starts_with(&raw));
+        let ch = book.exclusive_read_chapter(100).unwrap();
+        assert_eq!(ch.as_ref(), b"appended");
+
+        // Editing an existing chapter rewrites every record.
+        let mut editor = BookEditor::open(book.into_inner()).unwrap();
+        editor
+            .overwrite_chapter(ChapterIndex(0), b"edited 00")
+            .unwrap();
+        let buf = editor.close().unwrap().into_inner();
+        let mut book = Book::new(Cursor::new(buf)).unwrap();
+        book.verify().unwrap();
+        let ch = book.exclusive_read_chapter(0).unwrap();
+        assert_eq!(ch.as_ref(), b"edited 00");
+
+        // A truncated record is rejected.
+        let mut raw = raw.into_vec();
+        raw.pop();
+        assert!(matches!(Toc::from_bytes(&raw), Err(BookError::CorruptToc)));
+
+        // Records of an older entry version are upgraded.
+        let entry = TocEntryV8 {
+            id: ChapterId::from(22).0,
+            name: None,
+            span: FileSpan::from_offset_length(0, 18),
+            checksum: None,
+            encryption: None,
+            codec: None,
+            content_type: Some(7),
+        };
+        let record = serde_cbor::to_vec(&entry).unwrap();
+        let mut raw = Vec::new();
+        raw.write_u16::<BigEndian>(TOC_RECORDS_ID).unwrap();
+        raw.write_u16::<BigEndian>(TocV8::VER).unwrap();
+        raw.write_u32::<BigEndian>(record.len().try_into().unwrap())
+            .unwrap();
+        raw.extend_from_slice(&record);
+        let toc = Toc::from_bytes(&raw).unwrap();
+        assert_eq!(toc.0.len(), 1);
+        assert_eq!(toc.0[0].content_type, Some(7));
+        assert!(!toc.0[0].removed);
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn gzip_chapter() {
+        let text = b"This is chapter 22. ".repeat(20);
+        let buffer = {
+            let buffer = Cursor::new(Vec::<u8>::new());
+            let mut book = BookWriter::new(buffer, 0x1234).unwrap();
+            book.write_chapter(11, b"This is chapter 11").unwrap();
+            let mut chapter = book.new_chapter_compressed(22, Compression::Gzip { level: 6 });
+            chapter.write_all(&text).unwrap();
+            let book = chapter.close().unwrap();
+            let chapter = book.new_chapter_compressed(33, Compression::None);
+            let book = chapter.close().unwrap();
+            book.close().unwrap()
+        };
+        let mut book = Book::new(Cursor::new(buffer.into_inner())).unwrap();
+        book.verify().unwrap();
+        assert_eq!(book.toc().0[0].codec, None);
+        assert_eq!(book.toc().0[1].codec, Some(Codec::Gzip));
+        assert_eq!(book.toc().0[2].codec, None);
+
+        // The stored chapter is a gzip member that other tools can decode.
+        let raw = book.exclusive_read_chapter(22).unwrap();
+        assert!(raw.len() < text.len());
+        assert_eq!(&raw[..2], &[0x1f, 0x8b]);
+        let mut decoded = vec![];
+        flate2::read::GzDecoder::new(raw.as_ref())
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, text);
+        let mut raw_read = vec![];
+        book.chapter_reader_raw(ChapterIndex(1))
+            .unwrap()
+            .read_to_end(&mut raw_read)
+            .unwrap();
+        assert_eq!(raw_read, raw.as_ref());
+        let mut extracted = vec![];
+        let len = book
+            .extract_chapter(ChapterIndex(1), &mut extracted)
+            .unwrap();
+        assert_eq!(len, text.len() as u64);
+        assert_eq!(extracted, text);
+
+        let mut decoded = vec![];
+        book.decoded_chapter_reader(ChapterIndex(1))
+            .unwrap()
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, text);
+
+        // Uncompressed chapters are read as-is.
+        let mut plain = vec![];
+        book.decoded_chapter_reader(ChapterIndex(0))
+            .unwrap()
+            .read_to_end(&mut plain)
+            .unwrap();
+        assert_eq!(plain, b"This is chapter 11");
+        assert_eq!(book.chapter_len(ChapterIndex(2)).unwrap(), 0);
+    }
+
+    #[test]
+    fn sorted_ids() {
+        let mut book = BookBuilder::new()
+            .sorted_ids(true)
+            .build(Cursor::new(Vec::<u8>::new()))
+            .unwrap();
+        for id in [11u64, 22, 22, 33, 300] {
+            book.write_chapter(id, &id.to_be_bytes()).unwrap();
+        }
+        let buffer = book.close().unwrap();
+        let book = Book::new(buffer).unwrap();
+        assert_ne!(book.header().flags & FLAG_SORTED_IDS, 0);
+        assert_eq!(book.find_chapter(11), Some(ChapterIndex(0)));
+        assert_eq!(book.find_chapter(22), Some(ChapterIndex(1)));
+        assert_eq!(book.find_chapter(33), Some(ChapterIndex(3)));
+        assert_eq!(book.find_chapter(300), Some(ChapterIndex(4)));
+        assert_eq!(book.find_chapter(0), None);
+        assert_eq!(book.find_chapter(23), None);
+        assert_eq!(book.find_chapter(301), None);
+
+        // Without the flag, unsorted ids are found by a linear scan.
+        let mut book = BookWriter::new_in_memory(0x1234).unwrap();
+        for id in [33u64, 11, 22] {
+            book.write_chapter(id, &id.to_be_bytes()).unwrap();
+        }
+        let book = book.close_to_book().unwrap();
+        assert_eq!(book.header().flags & FLAG_SORTED_IDS, 0);
+        assert_eq!(book.find_chapter(11), Some(ChapterIndex(1)));
+        assert_eq!(book.find_chapter(22), Some(ChapterIndex(2)));
+        assert_eq!(book.find_chapter(33), Some(ChapterIndex(0)));
+        assert_eq!(book.find_chapter(44), None);
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic(expected = "ascending order")]
+    fn sorted_ids_violated() {
+        let mut book = BookBuilder::new()
+            .sorted_ids(true)
+            .build(Vec::<u8>::new())
+            .unwrap();
+        book.write_chapter(22, b"").unwrap();
+        book.write_chapter(11, b"").unwrap();
+    }
+
+    #[test]
+    fn book_builder() {
+        // With strict drop disabled, this doesn't panic.
+        let book = BookBuilder::new()
+            .strict_drop(false)
+            .build(Vec::<u8>::new())
+            .unwrap();
+        drop(book.new_chapter(33));
+
+        let buffer = {
+            let mut book = BookBuilder::new()
+                .user_magic(0x5678)
+                .user_meta(b"built")
+                .checksum(ChecksumKind::None)
+                .reserved_toc(200)
+                .build(Cursor::new(Vec::<u8>::new()))
+                .unwrap();
+            book.write_chapter(22, b"This is chapter 22").unwrap();
+            book.close_front_toc().unwrap()
+        };
+
+        // The front TOC can be read in one forward pass.
+        let stream = ForwardReader::new(buffer.get_ref().as_slice());
+        let mut book = Book::new(stream).unwrap();
+        assert_eq!(book.magic(), 0x5678);
+        assert_eq!(book.user_meta(), b"built");
+        assert_eq!(book.toc().0[0].checksum, None);
+        let ch2 = book.exclusive_read_chapter(22).unwrap();
+        assert_eq!(ch2.as_ref(), b"This is chapter 22");
+    }
+
+    #[test]
+    fn chapter_checksum_compat() {
+        // Build a book with a V2 TOC, which has no checksums.
+        let mut buffer = Cursor::new(Vec::<u8>::new());
+        {
+            let book = BookWriter::new(&mut buffer, 0x1234).unwrap();
+            book.close().unwrap();
+        }
+        let mut buf = buffer.into_inner();
+        buf.truncate(HEADER_SIZE);
+        buf.extend_from_slice(b"This is chapter 22");
+        let toc = TocV2(vec![TocEntryV2 {
+            id: Box::new(22u64.to_be_bytes()),
+            span: FileSpan::from_offset_length(HEADER_SIZE as u64, 18),
+        }]);
+        let mut toc_writer = CborData::new(Vec::<u8>::new());
+        toc_writer.write_message(&toc).unwrap();
+        let toc_buf = toc_writer.into_inner();
+        append_toc(&mut buf, &toc_buf);
+
+        let mut book = Book::new(Cursor::new(buf)).unwrap();
+        book.verify_checksums(true);
+        let ch2 = book.exclusive_read_chapter(22).unwrap();
+        assert_eq!(ch2.as_ref(), b"This is chapter 22");
     }
 }
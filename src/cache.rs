@@ -0,0 +1,100 @@
+use crate::ChapterIndex;
+use std::collections::{BTreeMap, HashMap};
+
+/// A least-recently-used cache of chapter contents.
+///
+/// The cache holds at most `capacity` bytes of chapter data. When it's
+/// full, the least recently used chapters are evicted to make room.
+#[derive(Debug)]
+pub(crate) struct ChapterCache {
+    capacity: usize,
+    size: usize,
+    /// The cached chapters, and the time each was last used.
+    entries: HashMap<ChapterIndex, (Box<[u8]>, u64)>,
+    /// The cached chapters, ordered by the time each was last used.
+    by_use: BTreeMap<u64, ChapterIndex>,
+    clock: u64,
+}
+
+impl ChapterCache {
+    /// Create a new `ChapterCache` that holds up to `capacity` bytes.
+    pub(crate) fn new(capacity: usize) -> Self {
+        ChapterCache {
+            capacity,
+            size: 0,
+            entries: HashMap::new(),
+            by_use: BTreeMap::new(),
+            clock: 0,
+        }
+    }
+
+    /// Return the capacity, in bytes.
+    pub(crate) fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Look up a chapter, marking it as recently used.
+    pub(crate) fn get(&mut self, index: ChapterIndex) -> Option<Box<[u8]>> {
+        let now = self.tick();
+        let (data, last_used) = self.entries.get_mut(&index)?;
+        self.by_use.remove(last_used);
+        self.by_use.insert(now, index);
+        *last_used = now;
+        Some(data.clone())
+    }
+
+    /// Add a chapter, evicting others if necessary.
+    ///
+    /// Chapters larger than the whole cache aren't stored.
+    pub(crate) fn insert(&mut self, index: ChapterIndex, data: &[u8]) {
+        if data.len() > self.capacity || self.entries.contains_key(&index) {
+            return;
+        }
+        while self.size + data.len() > self.capacity {
+            self.evict();
+        }
+        let now = self.tick();
+        self.size += data.len();
+        self.entries.insert(index, (data.into(), now));
+        self.by_use.insert(now, index);
+    }
+
+    /// Remove the least recently used chapter.
+    fn evict(&mut self) {
+        let oldest = self.by_use.keys().next().copied();
+        if let Some(index) = oldest.and_then(|oldest| self.by_use.remove(&oldest)) {
+            if let Some((data, _)) = self.entries.remove(&index) {
+                self.size -= data.len();
+            }
+        }
+    }
+
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn eviction() {
+        let mut cache = ChapterCache::new(10);
+        cache.insert(ChapterIndex(0), b"aaaa");
+        cache.insert(ChapterIndex(1), b"bbbb");
+        // Touch chapter 0, so that chapter 1 is the oldest.
+        assert_eq!(cache.get(ChapterIndex(0)).unwrap().as_ref(), b"aaaa");
+        cache.insert(ChapterIndex(2), b"cccc");
+        assert!(cache.get(ChapterIndex(1)).is_none());
+        assert!(cache.get(ChapterIndex(0)).is_some());
+        assert!(cache.get(ChapterIndex(2)).is_some());
+        assert_eq!(cache.size, 8);
+
+        // Too big to cache.
+        cache.insert(ChapterIndex(3), &[0; 11]);
+        assert!(cache.get(ChapterIndex(3)).is_none());
+        assert_eq!(cache.size, 8);
+    }
+}
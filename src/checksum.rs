@@ -0,0 +1,394 @@
+//! Checksum algorithms used to verify chapter contents.
+
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "xxhash")]
+use std::convert::TryInto;
+
+/// The CRC-32 (IEEE 802.3) polynomial, in reversed bit order.
+const CRC32_POLY: u32 = 0xEDB8_8320;
+
+/// A lookup table for computing CRC-32 one byte at a time.
+const CRC32_TABLE: [u32; 256] = crc32_table();
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut ii: u32 = 0;
+    while ii < 256 {
+        let mut crc = ii;
+        let mut bit = 0;
+        while bit < 8 {
+            if crc & 1 == 1 {
+                crc = (crc >> 1) ^ CRC32_POLY;
+            } else {
+                crc >>= 1;
+            }
+            bit += 1;
+        }
+        table[ii as usize] = crc;
+        ii += 1;
+    }
+    table
+}
+
+/// An incremental CRC-32 hasher.
+///
+/// Bytes may be fed in any number of calls to [`update`]; the result
+/// is the same as if they had all been supplied at once.
+///
+/// [`update`]: Self::update
+#[derive(Debug, Clone)]
+pub(crate) struct Crc32 {
+    state: u32,
+}
+
+impl Crc32 {
+    /// Create a new `Crc32` hasher.
+    pub(crate) fn new() -> Self {
+        Crc32 { state: !0 }
+    }
+
+    /// Add some bytes to the checksum.
+    pub(crate) fn update(&mut self, buf: &[u8]) {
+        let mut crc = self.state;
+        for &byte in buf {
+            let index = (crc ^ u32::from(byte)) & 0xFF;
+            crc = (crc >> 8) ^ CRC32_TABLE[index as usize];
+        }
+        self.state = crc;
+    }
+
+    /// Return the checksum of all bytes seen so far.
+    pub(crate) fn finish(&self) -> u32 {
+        !self.state
+    }
+
+    /// Compute the checksum of a single buffer.
+    pub(crate) fn checksum(buf: &[u8]) -> u32 {
+        let mut crc = Crc32::new();
+        crc.update(buf);
+        crc.finish()
+    }
+}
+
+// xxHash64 primes.
+#[cfg(feature = "xxhash")]
+const XXH_PRIME64_1: u64 = 0x9E37_79B1_85EB_CA87;
+#[cfg(feature = "xxhash")]
+const XXH_PRIME64_2: u64 = 0xC2B2_AE3D_27D4_EB4F;
+#[cfg(feature = "xxhash")]
+const XXH_PRIME64_3: u64 = 0x1656_67B1_9E37_79F9;
+#[cfg(feature = "xxhash")]
+const XXH_PRIME64_4: u64 = 0x85EB_CA77_C2B2_AE63;
+#[cfg(feature = "xxhash")]
+const XXH_PRIME64_5: u64 = 0x27D4_EB2F_1656_67C5;
+
+/// An incremental xxHash64 hasher, with a seed of 0.
+///
+/// Like [`Crc32`], bytes may be fed in any number of calls to [`update`].
+///
+/// [`update`]: Self::update
+#[cfg(feature = "xxhash")]
+#[derive(Debug, Clone)]
+pub(crate) struct XxHash64 {
+    acc: [u64; 4],
+    /// Bytes that don't yet fill a 32-byte stripe.
+    buf: [u8; 32],
+    buf_len: usize,
+    total_len: u64,
+}
+
+#[cfg(feature = "xxhash")]
+fn xxh_round(acc: u64, input: u64) -> u64 {
+    acc.wrapping_add(input.wrapping_mul(XXH_PRIME64_2))
+        .rotate_left(31)
+        .wrapping_mul(XXH_PRIME64_1)
+}
+
+#[cfg(feature = "xxhash")]
+fn xxh_merge(acc: u64, val: u64) -> u64 {
+    (acc ^ xxh_round(0, val))
+        .wrapping_mul(XXH_PRIME64_1)
+        .wrapping_add(XXH_PRIME64_4)
+}
+
+#[cfg(feature = "xxhash")]
+fn read_u64(buf: &[u8]) -> u64 {
+    u64::from_le_bytes(buf[..8].try_into().unwrap())
+}
+
+#[cfg(feature = "xxhash")]
+fn read_u32(buf: &[u8]) -> u32 {
+    u32::from_le_bytes(buf[..4].try_into().unwrap())
+}
+
+#[cfg(feature = "xxhash")]
+impl XxHash64 {
+    /// Create a new `XxHash64` hasher.
+    pub(crate) fn new() -> Self {
+        XxHash64 {
+            acc: [
+                XXH_PRIME64_1.wrapping_add(XXH_PRIME64_2),
+                XXH_PRIME64_2,
+                0,
+                XXH_PRIME64_1.wrapping_neg(),
+            ],
+            buf: [0; 32],
+            buf_len: 0,
+            total_len: 0,
+        }
+    }
+
+    /// Consume one 32-byte stripe.
+    fn stripe(&mut self, stripe: &[u8]) {
+        for (ii, acc) in self.acc.iter_mut().enumerate() {
+            *acc = xxh_round(*acc, read_u64(&stripe[ii * 8..]));
+        }
+    }
+
+    /// Add some bytes to the hash.
+    pub(crate) fn update(&mut self, mut buf: &[u8]) {
+        self.total_len += buf.len() as u64;
+
+        // Top up a partial stripe first.
+        if self.buf_len > 0 {
+            let fill = buf.len().min(32 - self.buf_len);
+            self.buf[self.buf_len..self.buf_len + fill].copy_from_slice(&buf[..fill]);
+            self.buf_len += fill;
+            buf = &buf[fill..];
+            if self.buf_len < 32 {
+                return;
+            }
+            let stripe = self.buf;
+            self.stripe(&stripe);
+            self.buf_len = 0;
+        }
+
+        let mut stripes = buf.chunks_exact(32);
+        for stripe in &mut stripes {
+            self.stripe(stripe);
+        }
+        let rest = stripes.remainder();
+        self.buf[..rest.len()].copy_from_slice(rest);
+        self.buf_len = rest.len();
+    }
+
+    /// Return the hash of all bytes seen so far.
+    pub(crate) fn finish(&self) -> u64 {
+        let mut hash = if self.total_len >= 32 {
+            let [v1, v2, v3, v4] = self.acc;
+            let mut hash = v1
+                .rotate_left(1)
+                .wrapping_add(v2.rotate_left(7))
+                .wrapping_add(v3.rotate_left(12))
+                .wrapping_add(v4.rotate_left(18));
+            for acc in self.acc {
+                hash = xxh_merge(hash, acc);
+            }
+            hash
+        } else {
+            XXH_PRIME64_5
+        };
+        hash = hash.wrapping_add(self.total_len);
+
+        let mut rest = &self.buf[..self.buf_len];
+        while rest.len() >= 8 {
+            hash ^= xxh_round(0, read_u64(rest));
+            hash = hash
+                .rotate_left(27)
+                .wrapping_mul(XXH_PRIME64_1)
+                .wrapping_add(XXH_PRIME64_4);
+            rest = &rest[8..];
+        }
+        if rest.len() >= 4 {
+            hash ^= u64::from(read_u32(rest)).wrapping_mul(XXH_PRIME64_1);
+            hash = hash
+                .rotate_left(23)
+                .wrapping_mul(XXH_PRIME64_2)
+                .wrapping_add(XXH_PRIME64_3);
+            rest = &rest[4..];
+        }
+        for &byte in rest {
+            hash ^= u64::from(byte).wrapping_mul(XXH_PRIME64_5);
+            hash = hash.rotate_left(11).wrapping_mul(XXH_PRIME64_1);
+        }
+
+        // Final avalanche.
+        hash ^= hash >> 33;
+        hash = hash.wrapping_mul(XXH_PRIME64_2);
+        hash ^= hash >> 29;
+        hash = hash.wrapping_mul(XXH_PRIME64_3);
+        hash ^= hash >> 32;
+        hash
+    }
+
+    /// Compute the hash of a single buffer.
+    pub(crate) fn checksum(buf: &[u8]) -> u64 {
+        let mut hasher = XxHash64::new();
+        hasher.update(buf);
+        hasher.finish()
+    }
+}
+
+/// A chapter checksum algorithm.
+///
+/// This selects the checksum that [`BookWriter`] stores for each new
+/// chapter; see [`BookWriter::set_checksum_kind`].
+///
+/// [`BookWriter`]: crate::BookWriter
+/// [`BookWriter::set_checksum_kind`]: crate::BookWriter::set_checksum_kind
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum ChecksumKind {
+    /// Don't store a checksum.
+    #[default]
+    None,
+    /// CRC-32 (IEEE 802.3).
+    Crc32,
+    /// xxHash64, which is much faster for large chapters.
+    #[cfg(feature = "xxhash")]
+    XxHash64,
+}
+
+/// A checksum stored in the table of contents.
+///
+/// All algorithms are always present here, so that a TOC can be parsed
+/// even if the algorithm used to write it isn't enabled.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Checksum {
+    /// A CRC-32 checksum.
+    Crc32(u32),
+    /// An xxHash64 hash.
+    XxHash64(u64),
+}
+
+impl Checksum {
+    /// Return the checksum value.
+    pub(crate) fn value(&self) -> u64 {
+        match *self {
+            Checksum::Crc32(crc) => crc.into(),
+            Checksum::XxHash64(hash) => hash,
+        }
+    }
+
+    /// Return the algorithm used for this checksum.
+    ///
+    /// Returns `None` if the algorithm isn't enabled.
+    pub(crate) fn kind(&self) -> Option<ChecksumKind> {
+        match self {
+            Checksum::Crc32(_) => Some(ChecksumKind::Crc32),
+            #[cfg(feature = "xxhash")]
+            Checksum::XxHash64(_) => Some(ChecksumKind::XxHash64),
+            #[cfg(not(feature = "xxhash"))]
+            Checksum::XxHash64(_) => None,
+        }
+    }
+
+    /// Compute a checksum of `buf` using the same algorithm as `self`.
+    ///
+    /// Returns `None` if the algorithm isn't enabled.
+    pub(crate) fn recompute(&self, buf: &[u8]) -> Option<Checksum> {
+        match self {
+            Checksum::Crc32(_) => Some(Checksum::Crc32(Crc32::checksum(buf))),
+            #[cfg(feature = "xxhash")]
+            Checksum::XxHash64(_) => Some(Checksum::XxHash64(XxHash64::checksum(buf))),
+            #[cfg(not(feature = "xxhash"))]
+            Checksum::XxHash64(_) => None,
+        }
+    }
+}
+
+/// An incremental hasher for any [`ChecksumKind`].
+#[derive(Debug, Clone)]
+pub(crate) enum ChapterHasher {
+    None,
+    Crc32(Crc32),
+    #[cfg(feature = "xxhash")]
+    XxHash64(XxHash64),
+}
+
+impl ChapterHasher {
+    /// Create a new hasher for a particular algorithm.
+    pub(crate) fn new(kind: ChecksumKind) -> Self {
+        match kind {
+            ChecksumKind::None => ChapterHasher::None,
+            ChecksumKind::Crc32 => ChapterHasher::Crc32(Crc32::new()),
+            #[cfg(feature = "xxhash")]
+            ChecksumKind::XxHash64 => ChapterHasher::XxHash64(XxHash64::new()),
+        }
+    }
+
+    /// Add some bytes to the checksum.
+    pub(crate) fn update(&mut self, buf: &[u8]) {
+        match self {
+            ChapterHasher::None => {}
+            ChapterHasher::Crc32(crc) => crc.update(buf),
+            #[cfg(feature = "xxhash")]
+            ChapterHasher::XxHash64(hasher) => hasher.update(buf),
+        }
+    }
+
+    /// Return the checksum of all bytes seen so far.
+    pub(crate) fn finish(&self) -> Option<Checksum> {
+        match self {
+            ChapterHasher::None => None,
+            ChapterHasher::Crc32(crc) => Some(Checksum::Crc32(crc.finish())),
+            #[cfg(feature = "xxhash")]
+            ChapterHasher::XxHash64(hasher) => Some(Checksum::XxHash64(hasher.finish())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc32_known_values() {
+        assert_eq!(Crc32::checksum(b""), 0);
+        assert_eq!(Crc32::checksum(b"123456789"), 0xCBF4_3926);
+        assert_eq!(
+            Crc32::checksum(b"The quick brown fox jumps over the lazy dog"),
+            0x414F_A339
+        );
+    }
+
+    #[test]
+    fn crc32_incremental() {
+        let mut crc = Crc32::new();
+        crc.update(b"1234");
+        crc.update(b"");
+        crc.update(b"56789");
+        assert_eq!(crc.finish(), 0xCBF4_3926);
+    }
+
+    #[cfg(feature = "xxhash")]
+    #[test]
+    fn xxhash64_known_values() {
+        assert_eq!(XxHash64::checksum(b""), 0xEF46_DB37_51D8_E999);
+        assert_eq!(XxHash64::checksum(b"a"), 0xD24E_C4F1_A98C_6E5B);
+        assert_eq!(XxHash64::checksum(b"abc"), 0x44BC_2CF5_AD77_0999);
+        assert_eq!(
+            XxHash64::checksum(b"The quick brown fox jumps over the lazy dog"),
+            0x0B24_2D36_1FDA_71BC
+        );
+    }
+
+    #[cfg(feature = "xxhash")]
+    #[test]
+    fn xxhash64_incremental() {
+        let data: Vec<u8> = (0..200u8).collect();
+        let expected = XxHash64::checksum(&data);
+        for split in [0, 1, 7, 31, 32, 33, 64, 100, 199, 200] {
+            let mut hasher = XxHash64::new();
+            hasher.update(&data[..split]);
+            hasher.update(b"");
+            hasher.update(&data[split..]);
+            assert_eq!(hasher.finish(), expected, "split at {}", split);
+        }
+        let mut hasher = XxHash64::new();
+        for byte in &data {
+            hasher.update(std::slice::from_ref(byte));
+        }
+        assert_eq!(hasher.finish(), expected);
+    }
+}
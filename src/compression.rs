@@ -0,0 +1,146 @@
+//! Chapter compression.
+
+use crate::Result;
+use serde::{Deserialize, Serialize};
+use std::io::Read;
+#[cfg(feature = "gzip")]
+use {
+    crate::book::{BookWriter, ChapterWriter},
+    flate2::{read::GzDecoder, write::GzEncoder},
+    std::io::{self, Write},
+};
+
+/// A chapter compression setting.
+///
+/// This is passed to [`BookWriter::new_chapter_compressed`].
+///
+/// [`BookWriter::new_chapter_compressed`]: crate::BookWriter::new_chapter_compressed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Compression {
+    /// Don't compress.
+    None,
+    /// gzip, at a level from 0 (none) to 9 (best).
+    #[cfg(feature = "gzip")]
+    Gzip {
+        /// The compression level.
+        level: u32,
+    },
+}
+
+/// The codec used to compress a chapter, as stored in the TOC.
+///
+/// All codecs are always present here, so that a TOC can be parsed
+/// even if the codec used to write it isn't enabled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Codec {
+    /// A single gzip member.
+    Gzip,
+}
+
+/// Wrap a chapter reader in a decoder for `codec`.
+pub(crate) fn decoder<'a, R>(codec: Option<Codec>, reader: R) -> Result<Box<dyn Read + 'a>>
+where
+    R: Read + 'a,
+{
+    match codec {
+        None => Ok(Box::new(reader)),
+        #[cfg(feature = "gzip")]
+        Some(Codec::Gzip) => Ok(Box::new(GzDecoder::new(reader))),
+        #[cfg(not(feature = "gzip"))]
+        Some(Codec::Gzip) => Err(crate::BookError::UnsupportedCodec),
+    }
+}
+
+/// Compress a serialized TOC.
+///
+/// If the `gzip` feature isn't enabled, [`BookError::UnsupportedCodec`]
+/// is returned.
+///
+/// [`BookError::UnsupportedCodec`]: crate::BookError::UnsupportedCodec
+pub(crate) fn compress_toc(toc: &[u8]) -> Result<Vec<u8>> {
+    #[cfg(feature = "gzip")]
+    {
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(toc)?;
+        Ok(encoder.finish()?)
+    }
+    #[cfg(not(feature = "gzip"))]
+    {
+        let _ = toc;
+        Err(crate::BookError::UnsupportedCodec)
+    }
+}
+
+/// A writer for a compressed chapter.
+///
+/// This is created by [`BookWriter::new_chapter_compressed`]. It works
+/// like a [`ChapterWriter`], but bytes are compressed before being
+/// written to the chapter.
+///
+/// Attempting to drop a `CompressedChapterWriter` without calling
+/// [`close()`] behaves the same as dropping a `ChapterWriter`.
+///
+/// [`close()`]: Self::close
+#[cfg(feature = "gzip")]
+pub struct CompressedChapterWriter<W: Write> {
+    inner: Encoder<W>,
+}
+
+#[cfg(feature = "gzip")]
+enum Encoder<W: Write> {
+    None(ChapterWriter<W>),
+    Gzip(GzEncoder<ChapterWriter<W>>),
+}
+
+#[cfg(feature = "gzip")]
+impl<W> CompressedChapterWriter<W>
+where
+    W: Write,
+{
+    /// Create a new `CompressedChapterWriter`.
+    pub(crate) fn new(chapter: ChapterWriter<W>, compression: Compression) -> Self {
+        let inner = match compression {
+            Compression::None => Encoder::None(chapter),
+            Compression::Gzip { level } => {
+                Encoder::Gzip(GzEncoder::new(chapter, flate2::Compression::new(level)))
+            }
+        };
+        CompressedChapterWriter { inner }
+    }
+
+    /// Complete the chapter.
+    ///
+    /// This finishes the compressed stream, and returns the original
+    /// BookWriter after updating its TOC.
+    pub fn close(self) -> Result<BookWriter<W>> {
+        match self.inner {
+            Encoder::None(chapter) => chapter.close(),
+            Encoder::Gzip(encoder) => {
+                let mut chapter = encoder.finish()?;
+                chapter.set_codec(Codec::Gzip);
+                chapter.close()
+            }
+        }
+    }
+}
+
+#[cfg(feature = "gzip")]
+impl<W> Write for CompressedChapterWriter<W>
+where
+    W: Write,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.inner {
+            Encoder::None(chapter) => chapter.write(buf),
+            Encoder::Gzip(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.inner {
+            Encoder::None(chapter) => chapter.flush(),
+            Encoder::Gzip(encoder) => encoder.flush(),
+        }
+    }
+}
@@ -0,0 +1,91 @@
+//! AES-256-GCM chapter encryption.
+
+use crate::book::{BookWriter, ChapterWriter, EncryptionV1};
+use crate::{BookError, Result};
+use aes_gcm::aead::{AeadCore, AeadInPlace, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Key, Nonce, Tag};
+use std::io::{self, Write};
+
+/// A writer for an encrypted chapter.
+///
+/// This is created by [`BookWriter::new_chapter_encrypted`]. It works
+/// like a [`ChapterWriter`], but the chapter contents are encrypted
+/// with AES-256-GCM when [`close()`] is called.
+///
+/// AES-GCM authenticates the whole chapter at once, so the plaintext
+/// is buffered in memory until the chapter is closed.
+///
+/// Attempting to drop an `EncryptedChapterWriter` without calling
+/// `close` behaves the same as dropping a `ChapterWriter`.
+///
+/// [`close()`]: Self::close
+pub struct EncryptedChapterWriter<W: Write> {
+    chapter: ChapterWriter<W>,
+    cipher: Aes256Gcm,
+    buf: Vec<u8>,
+}
+
+impl<W> EncryptedChapterWriter<W>
+where
+    W: Write,
+{
+    /// Create a new `EncryptedChapterWriter`.
+    pub(crate) fn new(chapter: ChapterWriter<W>, key: &[u8; 32]) -> Self {
+        EncryptedChapterWriter {
+            chapter,
+            cipher: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key)),
+            buf: Vec::new(),
+        }
+    }
+
+    /// Complete the chapter.
+    ///
+    /// This encrypts the chapter contents, writes the ciphertext, and
+    /// returns the original BookWriter after updating its TOC.
+    pub fn close(mut self) -> Result<BookWriter<W>> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let tag = self
+            .cipher
+            .encrypt_in_place_detached(&nonce, self.chapter.id(), &mut self.buf)
+            .map_err(|_| {
+                io::Error::new(io::ErrorKind::InvalidInput, "chapter too large to encrypt")
+            })?;
+        self.chapter.write_all(&self.buf)?;
+        self.chapter.set_encryption(EncryptionV1 {
+            nonce: nonce.into(),
+            tag: tag.into(),
+        });
+        self.chapter.close()
+    }
+}
+
+impl<W: Write> Write for EncryptedChapterWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Decrypt a chapter in place.
+///
+/// `id` is the chapter id, which was used as associated data.
+pub(crate) fn decrypt(
+    key: &[u8; 32],
+    id: &[u8],
+    encryption: &EncryptionV1,
+    buf: &mut [u8],
+) -> Result<()> {
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+    cipher
+        .decrypt_in_place_detached(
+            Nonce::from_slice(&encryption.nonce),
+            id,
+            buf,
+            Tag::from_slice(&encryption.tag),
+        )
+        .map_err(|_| BookError::DecryptionFailed)
+}
@@ -28,28 +28,213 @@ use std::io;
 use thiserror::Error;
 
 mod book;
+mod cache;
+mod checksum;
 #[doc(inline)]
-pub use book::{Book, BookWriter, ChapterId, ChapterWriter};
+pub use book::{
+    estimate_book_size, probe, verify_sequential, Book, BookBuilder, BookEditor, BookProbe,
+    BookStats, BookWriter, ChapterId, ChapterIndex, ChapterReaders, ChapterWriter, FileHeader,
+    FormatVersions, LazyBook, Toc, MANIFEST_CHAPTER_ID, MAX_CHAPTERS,
+};
+#[doc(inline)]
+pub use checksum::ChecksumKind;
+
+mod compression;
+#[cfg(feature = "gzip")]
+#[doc(inline)]
+pub use compression::CompressedChapterWriter;
+#[doc(inline)]
+pub use compression::Compression;
+
+#[cfg(feature = "encryption")]
+mod encryption;
+#[cfg(feature = "encryption")]
+#[doc(inline)]
+pub use encryption::EncryptedChapterWriter;
+
+#[cfg(feature = "mmap")]
+mod mapped;
+#[cfg(feature = "mmap")]
+#[doc(inline)]
+pub use mapped::MappedBook;
+
+mod parallel;
+#[doc(inline)]
+pub use parallel::ParallelBookWriter;
 
 mod read;
 #[doc(inline)]
-pub use read::BoundedReader;
+pub use read::{BoundedReader, CheckedChapterReader, ForwardReader, FramedChapterReader, TryClone};
+
+mod shared;
+#[doc(inline)]
+pub use shared::SharedBook;
+
+mod write;
+#[doc(inline)]
+pub use write::Durable;
 
 /// Book error type
 #[derive(Debug, Error)]
 pub enum BookError {
     /// A `std::io::Error` occurred while reading or writing data.
     #[error("IO Error")]
-    Io(Option<io::Error>),
+    Io(#[source] Option<io::Error>),
+    /// A `std::io::Error` occurred during a particular operation.
+    ///
+    /// `op` describes what was happening, e.g. "reading TOC".
+    #[error("IO error while {op}")]
+    IoContext {
+        /// The operation that failed.
+        op: &'static str,
+        /// The underlying error.
+        #[source]
+        source: io::Error,
+    },
     /// An EOF happened while attempting to read data.
     #[error("Premature EOF")]
     Eof,
     /// An error occurred while serializing or deserializing data.
     #[error("Serialize/Deserialize Error")]
     Serializer,
-    /// The requested chapter was not found.
+    /// The table of contents could not be parsed.
+    #[error("Corrupt table of contents")]
+    CorruptToc,
+    /// The file is too small to contain a header and TOC length.
+    #[error("File too small ({len} bytes)")]
+    TooSmall {
+        /// The length of the file.
+        len: u64,
+    },
+    /// The file is not in `Book` format.
+    ///
+    /// `found` is the format magic number found in the file header, or 0
+    /// if no header could be parsed at all.
+    #[error("Not a bookfile (magic {found:#x}, expected {expected:#x})")]
+    BadMagic {
+        /// The expected magic number.
+        expected: u32,
+        /// The magic number found in the file.
+        found: u32,
+    },
+    /// The file is a bookfile, but in a format version this library
+    /// can't read.
+    #[error("Unsupported bookfile version (magic {magic:#x})")]
+    UnsupportedVersion {
+        /// The format magic number found in the file header.
+        magic: u32,
+    },
+    /// The file's user-defined magic number isn't the one expected.
+    #[error("Wrong user magic {found:#x}, expected {expected:#x}")]
+    WrongUserMagic {
+        /// The expected magic number.
+        expected: u32,
+        /// The magic number found in the file.
+        found: u32,
+    },
+    /// No chapter with the requested id was found.
     #[error("Chapter not found")]
     NoChapter,
+    /// A [`ChapterIndex`] is past the end of the table of contents.
+    #[error("Chapter index {index} out of range ({len} chapters)")]
+    IndexOutOfRange {
+        /// The requested index.
+        index: usize,
+        /// The number of chapters in the book.
+        len: usize,
+    },
+    /// The chapter at a [`ChapterIndex`] has been removed.
+    ///
+    /// See [`BookEditor::remove_chapter`].
+    #[error("Chapter {index} has been removed")]
+    ChapterRemoved {
+        /// The requested index.
+        index: usize,
+    },
+    /// The requested range extends past the end of the chapter.
+    #[error("Range out of bounds ({start}+{len}, chapter length {chapter_len})")]
+    OutOfBounds {
+        /// The start of the requested range.
+        start: u64,
+        /// The length of the requested range.
+        len: u64,
+        /// The length of the chapter.
+        chapter_len: u64,
+    },
+    /// The serialized file header is too large.
+    #[error("Header too large ({size} bytes, maximum {max})")]
+    HeaderTooLarge {
+        /// The size of the serialized header.
+        size: usize,
+        /// The maximum header size.
+        max: usize,
+    },
+    /// The header block size is outside the allowed range.
+    #[error("Invalid header size ({size} bytes)")]
+    InvalidHeaderSize {
+        /// The requested or recorded header size.
+        size: u64,
+    },
+    /// The serialized table of contents doesn't fit in the space reserved for it.
+    #[error("TOC too large ({size} bytes, maximum {max})")]
+    TocTooLarge {
+        /// The size of the serialized TOC.
+        size: usize,
+        /// The space reserved for the TOC.
+        max: usize,
+    },
+    /// The book has more chapters than [`MAX_CHAPTERS`].
+    #[error("Too many chapters ({count}, maximum {max})", max = MAX_CHAPTERS)]
+    TooManyChapters {
+        /// The number of chapters, or the number claimed by the TOC.
+        count: u64,
+    },
+    /// An error occurred while reading from a chapter's data source.
+    #[error("Error reading chapter source")]
+    Source(#[source] io::Error),
+    /// The chapter was compressed with a codec that isn't enabled.
+    #[error("Unsupported compression codec")]
+    UnsupportedCodec,
+    /// The chapter isn't encrypted.
+    #[error("Chapter is not encrypted")]
+    NotEncrypted,
+    /// An encrypted chapter couldn't be decrypted.
+    ///
+    /// Either the key is wrong, or the chapter has been modified.
+    #[error("Chapter decryption failed")]
+    DecryptionFailed,
+    /// A chapter's span lies outside the region reserved for chapters.
+    #[error("Chapter span out of bounds")]
+    SpanOutOfBounds {
+        /// The id of the chapter.
+        id: Box<[u8]>,
+    },
+    /// Two chapters' spans overlap.
+    #[error("Chapter spans overlap")]
+    SpanOverlap {
+        /// The id of the chapter that starts first.
+        first: Box<[u8]>,
+        /// The id of the chapter that overlaps it.
+        second: Box<[u8]>,
+    },
+    /// New chapter contents aren't the same size as the chapter they replace.
+    #[error("Size mismatch ({actual} bytes, expected {expected})")]
+    SizeMismatch {
+        /// The length of the existing chapter.
+        expected: u64,
+        /// The length of the new contents.
+        actual: u64,
+    },
+    /// A chapter's contents did not match its stored checksum.
+    #[error("Chapter checksum mismatch")]
+    ChecksumMismatch {
+        /// The id of the chapter that failed verification.
+        id: Box<[u8]>,
+        /// The checksum stored in the table of contents.
+        expected: u64,
+        /// The checksum of the chapter contents.
+        actual: u64,
+    },
 }
 
 impl From<CborDataError> for BookError {
@@ -62,11 +247,44 @@ impl From<CborDataError> for BookError {
     }
 }
 
+impl From<serde_cbor::Error> for BookError {
+    fn from(e: serde_cbor::Error) -> Self {
+        CborDataError::from(e).into()
+    }
+}
+
 impl From<io::Error> for BookError {
     fn from(e: io::Error) -> Self {
         BookError::Io(Some(e))
     }
 }
 
+impl BookError {
+    /// Attach a description of the current operation to an IO error.
+    ///
+    /// Errors other than [`BookError::Io`] are returned unchanged.
+    pub(crate) fn context(self, op: &'static str) -> Self {
+        match self {
+            BookError::Io(Some(source)) => BookError::IoContext { op, source },
+            other => other,
+        }
+    }
+}
+
 /// A Result type for things that may return [`BookError`].
 pub type Result<T> = std::result::Result<T, BookError>;
+
+/// Extension trait for attaching context to errors.
+pub(crate) trait ResultExt<T> {
+    /// Attach a description of the current operation to an IO error.
+    fn context(self, op: &'static str) -> Result<T>;
+}
+
+impl<T, E> ResultExt<T> for std::result::Result<T, E>
+where
+    E: Into<BookError>,
+{
+    fn context(self, op: &'static str) -> Result<T> {
+        self.map_err(|e| e.into().context(op))
+    }
+}
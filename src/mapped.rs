@@ -0,0 +1,129 @@
+//! Reading a `Book` from a memory-mapped file.
+
+use crate::book::{Book, ChapterId, ChapterIndex, FileHeader};
+use crate::{BookError, Result};
+use memmap2::Mmap;
+use std::convert::TryInto;
+
+/// A `Book` stored in a memory-mapped file.
+///
+/// Chapters are borrowed directly from the mapping, rather than being
+/// copied into a buffer as by [`Book::read_chapter`]. The header and TOC
+/// are parsed once, in the same way as [`Book::from_slice`].
+///
+/// The mapping is created by the caller. If the file is modified while
+/// it's mapped, chapter contents may change underneath the borrowed
+/// slices; see the `memmap2` documentation.
+#[derive(Debug)]
+pub struct MappedBook {
+    map: Mmap,
+    book: Book<()>,
+}
+
+impl MappedBook {
+    /// Create a new `MappedBook`, parsing the header and TOC.
+    pub fn new(map: Mmap) -> Result<Self> {
+        let parsed = Book::from_slice(&map)?;
+        let book = Book::from_parts((), parsed.header().clone(), parsed.toc().clone());
+        Ok(MappedBook { map, book })
+    }
+
+    /// Return the file header.
+    pub fn header(&self) -> &FileHeader {
+        self.book.header()
+    }
+
+    /// Return the number of chapters in the book.
+    pub fn len(&self) -> usize {
+        self.book.len()
+    }
+
+    /// Return `true` if the book contains no chapters.
+    pub fn is_empty(&self) -> bool {
+        self.book.is_empty()
+    }
+
+    /// Find a chapter by its id.
+    ///
+    /// See [`Book::find_chapter`].
+    pub fn find_chapter<Id>(&self, id: Id) -> Option<ChapterIndex>
+    where
+        Id: Into<ChapterId>,
+    {
+        self.book.find_chapter(id)
+    }
+
+    /// Return a chapter's bytes, borrowed from the mapping.
+    ///
+    /// The bytes are returned as stored: compressed or encrypted chapters
+    /// aren't decoded, and checksums aren't verified. A removed chapter
+    /// returns [`BookError::ChapterRemoved`].
+    pub fn chapter_bytes(&self, index: ChapterIndex) -> Result<&[u8]> {
+        let entry = self.book.toc().get_index(index)?;
+        let span = match &entry.span {
+            None => return Ok(&[]),
+            Some(span) => span,
+        };
+        // Spans were checked against the file length when the TOC was
+        // parsed, so this only fails if they don't fit in a `usize`.
+        let out_of_bounds = || BookError::SpanOutOfBounds {
+            id: entry.id.clone(),
+        };
+        let start: usize = span.offset.try_into().map_err(|_| out_of_bounds())?;
+        let length: usize = span.length.get().try_into().map_err(|_| out_of_bounds())?;
+        start
+            .checked_add(length)
+            .and_then(|end| self.map.get(start..end))
+            .ok_or_else(out_of_bounds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BookEditor, BookWriter};
+    use memmap2::MmapMut;
+    use std::io::Cursor;
+
+    #[test]
+    fn mapped_book() {
+        let mut book = BookWriter::new_in_memory(0x1234).unwrap();
+        book.write_chapter(11, b"").unwrap();
+        book.write_chapter(22, b"This is chapter 22").unwrap();
+        let buf = book.close().unwrap().into_inner();
+
+        let mut map = MmapMut::map_anon(buf.len()).unwrap();
+        map.copy_from_slice(&buf);
+        let book = MappedBook::new(map.make_read_only().unwrap()).unwrap();
+        assert_eq!(book.len(), 2);
+        assert_eq!(book.header().user_magic, 0x1234);
+        let index = book.find_chapter(22).unwrap();
+        assert_eq!(book.chapter_bytes(index).unwrap(), b"This is chapter 22");
+        let index = book.find_chapter(11).unwrap();
+        assert_eq!(book.chapter_bytes(index).unwrap(), b"");
+        let err = book.chapter_bytes(ChapterIndex(2)).unwrap_err();
+        assert!(
+            matches!(err, BookError::IndexOutOfRange { index: 2, len: 2 }),
+            "{:?}",
+            err
+        );
+
+        // A removed chapter isn't read as empty.
+        let mut editor = BookEditor::open(Cursor::new(buf)).unwrap();
+        editor.remove_chapter(ChapterIndex(1)).unwrap();
+        let buf = editor.close().unwrap().into_inner();
+        let mut map = MmapMut::map_anon(buf.len()).unwrap();
+        map.copy_from_slice(&buf);
+        let book = MappedBook::new(map.make_read_only().unwrap()).unwrap();
+        let err = book.chapter_bytes(ChapterIndex(1)).unwrap_err();
+        assert!(
+            matches!(err, BookError::ChapterRemoved { index: 1 }),
+            "{:?}",
+            err
+        );
+
+        // A mapping that isn't a book is rejected.
+        let map = MmapMut::map_anon(16).unwrap();
+        assert!(MappedBook::new(map.make_read_only().unwrap()).is_err());
+    }
+}
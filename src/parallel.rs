@@ -0,0 +1,125 @@
+//! Building a book from chapters produced concurrently.
+
+use crate::book::{BookBuilder, ChapterId};
+use crate::Result;
+use std::io::Write;
+use std::sync::Mutex;
+
+/// A chapter's id and contents.
+type PendingChapter = (Box<[u8]>, Vec<u8>);
+
+/// A chapter's id and `(offset, length)` span.
+type ChapterSpan = (Box<[u8]>, (u64, u64));
+
+/// A tool for building a `Book` from chapters produced in parallel.
+///
+/// A [`BookWriter`] must write chapters one at a time, because each
+/// chapter's offset depends on the ones before it. A `ParallelBookWriter`
+/// instead collects complete chapters from any number of threads, holding
+/// each in memory, and writes them all in [`finish()`].
+///
+/// Chapters are written in ascending id order (comparing ids as byte
+/// strings), so the output doesn't depend on which thread finished first.
+/// Chapters with equal ids are written in the order they were added.
+///
+/// [`BookWriter`]: crate::BookWriter
+/// [`finish()`]: Self::finish
+#[derive(Debug)]
+pub struct ParallelBookWriter {
+    builder: BookBuilder,
+    chapters: Mutex<Vec<PendingChapter>>,
+}
+
+impl ParallelBookWriter {
+    /// Create a new `ParallelBookWriter`.
+    ///
+    /// `builder` configures the `BookWriter` used by [`finish()`].
+    ///
+    /// [`finish()`]: Self::finish
+    pub fn new(builder: BookBuilder) -> Self {
+        ParallelBookWriter {
+            builder,
+            chapters: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Add a complete chapter.
+    ///
+    /// This may be called from several threads at once.
+    pub fn add_chapter<Id>(&self, id: Id, data: Vec<u8>)
+    where
+        Id: Into<ChapterId>,
+    {
+        let id: ChapterId = id.into();
+        // Adding a chapter can't leave the list in an inconsistent state,
+        // so a poisoned lock can be used as-is.
+        let mut chapters = self
+            .chapters
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        chapters.push((id.0, data));
+    }
+
+    /// Write the `Book`.
+    ///
+    /// This writes the header, each chapter, and the table of contents.
+    /// On success, it returns the original writer stream, along with each
+    /// chapter's id and `(offset, length)` span, in the order they were
+    /// written.
+    pub fn finish<W: Write>(self, writer: W) -> Result<(W, Vec<ChapterSpan>)> {
+        let mut chapters = self
+            .chapters
+            .into_inner()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        // A stable sort, so equal ids keep the order they were added in.
+        chapters.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        let mut book = self.builder.build(writer)?;
+        let mut spans = Vec::with_capacity(chapters.len());
+        for (id, data) in chapters {
+            let mut chapter = book.new_chapter(id.clone());
+            chapter.write_all(&data)?;
+            let (closed, span) = chapter.close_with_span()?;
+            book = closed;
+            spans.push((id, span));
+        }
+        let writer = book.close()?;
+        Ok((writer, spans))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Book, ChapterIndex};
+    use std::io::Cursor;
+    use std::thread;
+
+    #[test]
+    fn parallel_chapters() {
+        let writer = ParallelBookWriter::new(BookBuilder::new().user_magic(0x1234));
+        thread::scope(|scope| {
+            for id in (1..=4u64).rev() {
+                let writer = &writer;
+                scope.spawn(move || {
+                    let data = format!("This is chapter {}", id).into_bytes();
+                    writer.add_chapter(id, data);
+                });
+            }
+        });
+        let (buffer, spans) = writer.finish(Cursor::new(Vec::new())).unwrap();
+
+        let ids: Vec<_> = spans.iter().map(|(id, _)| id.clone()).collect();
+        let expected: Vec<_> = (1..=4u64).map(|id| ChapterId::from(id).0).collect();
+        assert_eq!(ids, expected);
+
+        let mut book = Book::new(buffer).unwrap();
+        book.verify().unwrap();
+        for (index, (id, span)) in spans.iter().enumerate() {
+            assert_eq!(book.chapter_span(ChapterIndex(index)), Some(*span));
+            let data = book.exclusive_read_chapter(id.as_ref()).unwrap();
+            let expected = format!("This is chapter {}", index + 1);
+            assert_eq!(data.as_ref(), expected.as_bytes());
+        }
+    }
+}
@@ -1,4 +1,8 @@
+use crate::checksum::{ChapterHasher, Checksum, ChecksumKind};
+use crate::{BookError, Result};
+use byteorder::{BigEndian, ReadBytesExt};
 use std::convert::TryInto;
+use std::fs::File;
 use std::io::{self, BufRead, Read, Seek, SeekFrom};
 #[cfg(target_family = "unix")]
 use std::os::unix::fs::FileExt;
@@ -18,12 +22,22 @@ fn to_usize(x: u64) -> usize {
 /// well. If the `Book` is a `File`, then `BoundedReader` implements a
 /// [`read_at`] function that permits reading from a shared reference.
 ///
+/// If the underlying reader implements [`BufRead`], then `BoundedReader`
+/// does too, and buffered bytes past the end of the region are hidden.
+///
 /// [`read_at`]: BoundedReader::read_at
 pub struct BoundedReader<R> {
     reader: R,
     start: u64,
     length: u64,
+    /// The position of the inner reader, relative to `start`.
     pos: Option<u64>,
+    /// Bytes read by [`peek`](Self::peek) but not yet returned.
+    ///
+    /// The unread bytes are `peeked[peek_offset..]`; they lie just before
+    /// `pos`.
+    peeked: Vec<u8>,
+    peek_offset: usize,
 }
 
 impl<R> BoundedReader<R> {
@@ -34,6 +48,8 @@ impl<R> BoundedReader<R> {
             start,
             length,
             pos: None,
+            peeked: Vec::new(),
+            peek_offset: 0,
         }
     }
 
@@ -47,6 +63,8 @@ impl<R> BoundedReader<R> {
             start: 0,
             length: 0,
             pos: None,
+            peeked: Vec::new(),
+            peek_offset: 0,
         }
     }
 
@@ -54,6 +72,31 @@ impl<R> BoundedReader<R> {
     pub fn len(&self) -> u64 {
         self.length
     }
+
+    /// Return `true` if the bounded region is empty.
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+
+    /// Return the number of bytes between the current position and the
+    /// end of the bounded region.
+    pub fn remaining(&self) -> u64 {
+        self.length - self.pos.unwrap_or(0) + self.buffered() as u64
+    }
+
+    /// The number of peeked bytes that haven't been read yet.
+    fn buffered(&self) -> usize {
+        self.peeked.len() - self.peek_offset
+    }
+
+    /// Mark `amt` peeked bytes as read.
+    fn consume_peeked(&mut self, amt: usize) {
+        self.peek_offset += amt;
+        if self.peek_offset == self.peeked.len() {
+            self.peeked.clear();
+            self.peek_offset = 0;
+        }
+    }
 }
 
 impl<R> BoundedReader<R>
@@ -76,6 +119,32 @@ where
     fn move_pos(&mut self, delta: usize) {
         *self.pos.as_mut().expect("uninitialized pos") += delta as u64;
     }
+
+    /// Return up to `n` upcoming bytes, without consuming them.
+    ///
+    /// The bytes are held in an internal buffer and returned again by the
+    /// next `read`, so nothing is lost. Fewer than `n` bytes are returned
+    /// only if the end of the bounded region is reached. This works on
+    /// forward-only streams, since the inner reader is never rewound.
+    pub fn peek(&mut self, n: usize) -> io::Result<&[u8]> {
+        if self.peek_offset > 0 {
+            self.peeked.drain(..self.peek_offset);
+            self.peek_offset = 0;
+        }
+        if self.length > 0 {
+            let pos = self.initialize_pos()?;
+            let wanted = n.saturating_sub(self.peeked.len()) as u64;
+            let wanted = wanted.min(self.length - pos);
+            let old_len = self.peeked.len();
+            (&mut self.reader)
+                .take(wanted)
+                .read_to_end(&mut self.peeked)?;
+            let bytes_read = self.peeked.len() - old_len;
+            self.move_pos(bytes_read);
+        }
+        let len = n.min(self.peeked.len());
+        Ok(&self.peeked[..len])
+    }
 }
 
 impl<R> Read for BoundedReader<R>
@@ -87,6 +156,14 @@ where
         if self.length == 0 {
             return Ok(0);
         }
+        // Return any peeked bytes first.
+        if self.buffered() > 0 {
+            let len = buf.len().min(self.buffered());
+            let start = self.peek_offset;
+            buf[..len].copy_from_slice(&self.peeked[start..start + len]);
+            self.consume_peeked(len);
+            return Ok(len);
+        }
         let pos = self.initialize_pos()?;
         if pos == self.length {
             // EOF for the bounded range.
@@ -110,36 +187,50 @@ impl<R> Seek for BoundedReader<R>
 where
     R: Seek,
 {
+    /// Seek to a position within the bounded region.
+    ///
+    /// All positions are relative to the start of the bounded region;
+    /// `SeekFrom::End` is relative to the end of the bounded region.
+    /// Seeking past the end will position the reader at the end.
+    /// Seeking to a position before the start returns an error.
     fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
         fn seek_error() -> io::Error {
             io::Error::new(io::ErrorKind::InvalidInput, "seek out of bounds")
         }
 
-        let real_pos = match pos {
-            SeekFrom::Start(s) => self.start.checked_add(s).ok_or_else(seek_error)?,
-            SeekFrom::End(e) => {
-                let end = self.start + self.length;
-                let e: u64 = (-e).try_into().map_err(|_| seek_error())?;
-                end.checked_sub(e).ok_or_else(seek_error)?
-            }
-            SeekFrom::Current(c) => {
-                let real_pos = self.reader.stream_position()?;
-                let real_pos: i64 = real_pos.try_into().map_err(|_| seek_error())?;
-                let real_pos = real_pos.checked_add(c).ok_or_else(seek_error)?;
-                let real_pos: u64 = real_pos.try_into().map_err(|_| seek_error())?;
-                real_pos
+        // Apply a signed offset to a position, failing if the result
+        // would be negative.
+        fn offset_pos(base: u64, offset: i64) -> io::Result<u64> {
+            if offset >= 0 {
+                Ok(base.saturating_add(offset.unsigned_abs()))
+            } else {
+                base.checked_sub(offset.unsigned_abs())
+                    .ok_or_else(seek_error)
             }
+        }
+
+        let bounded_pos = match pos {
+            SeekFrom::Start(s) => s,
+            SeekFrom::End(e) => offset_pos(self.length, e)?,
+            // If we haven't done any IO yet, the current position is 0.
+            SeekFrom::Current(c) => offset_pos(self.length - self.remaining(), c)?,
         };
-        if real_pos > self.start + self.length {
-            return Err(seek_error());
+        // Peeked bytes will be read again from the inner reader.
+        self.peeked.clear();
+        self.peek_offset = 0;
+        // Clamp the position to the end of the bounded region.
+        let bounded_pos = bounded_pos.min(self.length);
+
+        if self.length == 0 {
+            // There is nothing to read, so there is no need to move
+            // the underlying reader.
+            self.pos = Some(0);
+            return Ok(0);
         }
-        self.reader.seek(SeekFrom::Start(real_pos)).map(|new_pos| {
-            let bounded_pos = new_pos
-                .checked_sub(self.start)
-                .expect("allowed seek to bad position");
-            self.pos = Some(bounded_pos);
-            bounded_pos
-        })
+        let real_pos = self.start.checked_add(bounded_pos).ok_or_else(seek_error)?;
+        self.reader.seek(SeekFrom::Start(real_pos))?;
+        self.pos = Some(bounded_pos);
+        Ok(bounded_pos)
     }
 }
 
@@ -149,6 +240,9 @@ where
     R: BufRead + Seek,
 {
     fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.buffered() > 0 {
+            return Ok(&self.peeked[self.peek_offset..]);
+        }
         let pos = self.initialize_pos()?;
         let buf = self.reader.fill_buf()?;
         let max_len = self.length - pos;
@@ -162,6 +256,17 @@ where
     }
 
     fn consume(&mut self, amt: usize) {
+        if self.buffered() > 0 {
+            if amt > self.buffered() {
+                panic!(
+                    "consume({}) exceeds buffer; only {} bytes buffered",
+                    amt,
+                    self.buffered()
+                );
+            }
+            self.consume_peeked(amt);
+            return;
+        }
         // The act of reading should have caused self.pos to be Some(_).
         let max_len = self.length - self.pos.unwrap();
         if amt as u64 > max_len {
@@ -175,6 +280,178 @@ where
     }
 }
 
+/// An iterator over length-prefixed records in a chapter.
+///
+/// Each record is stored as a big-endian `u32` length, followed by that
+/// many bytes. Iteration stops cleanly at the end of the chapter.
+///
+/// If a record is truncated, or its length extends past the end of the
+/// chapter, an error is returned and iteration stops.
+pub struct FramedChapterReader<R> {
+    reader: BoundedReader<R>,
+    pos: u64,
+    done: bool,
+}
+
+impl<R> FramedChapterReader<R> {
+    /// Create a new `FramedChapterReader`.
+    ///
+    /// The reader should be positioned at the start of the chapter.
+    pub fn new(reader: BoundedReader<R>) -> Self {
+        FramedChapterReader {
+            reader,
+            pos: 0,
+            done: false,
+        }
+    }
+
+    /// Consume the `FramedChapterReader`, returning the inner reader.
+    pub fn into_inner(self) -> BoundedReader<R> {
+        self.reader
+    }
+}
+
+impl<R> FramedChapterReader<R>
+where
+    R: Read + Seek,
+{
+    fn read_record(&mut self) -> Result<Box<[u8]>> {
+        let chapter_len = self.reader.len();
+        if chapter_len - self.pos < 4 {
+            return Err(BookError::Eof);
+        }
+        let len = u64::from(self.reader.read_u32::<BigEndian>()?);
+        self.pos += 4;
+        if len > chapter_len - self.pos {
+            return Err(BookError::OutOfBounds {
+                start: self.pos,
+                len,
+                chapter_len,
+            });
+        }
+        let mut record = vec![0u8; to_usize(len)];
+        self.reader.read_exact(&mut record)?;
+        self.pos += len;
+        Ok(record.into_boxed_slice())
+    }
+}
+
+impl<R> Iterator for FramedChapterReader<R>
+where
+    R: Read + Seek,
+{
+    type Item = Result<Box<[u8]>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done || self.pos == self.reader.len() {
+            return None;
+        }
+        let result = self.read_record();
+        self.done = result.is_err();
+        Some(result)
+    }
+}
+
+/// An I/O wrapper that allows forward-only seeking on a `Read` stream.
+///
+/// Some streams (e.g. network sockets or pipes) can't seek. `ForwardReader`
+/// implements `Seek` for such streams by reading and discarding bytes,
+/// which allows a [`Book`] to read chapters in ascending offset order.
+///
+/// Seeking backwards, or relative to the end of the stream, will
+/// return an error.
+///
+/// [`Book`]: crate::Book
+pub struct ForwardReader<R> {
+    reader: R,
+    pos: u64,
+}
+
+impl<R> ForwardReader<R> {
+    /// Create a new `ForwardReader`.
+    ///
+    /// The stream is assumed to be positioned at offset 0.
+    pub fn new(reader: R) -> Self {
+        ForwardReader { reader, pos: 0 }
+    }
+
+    /// Consume the `ForwardReader`, returning the inner stream.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<R> Read for ForwardReader<R>
+where
+    R: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let bytes_read = self.reader.read(buf)?;
+        self.pos += bytes_read as u64;
+        Ok(bytes_read)
+    }
+}
+
+impl<R> Seek for ForwardReader<R>
+where
+    R: Read,
+{
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(s) => Some(s),
+            SeekFrom::Current(c) if c >= 0 => self.pos.checked_add(c.unsigned_abs()),
+            SeekFrom::Current(_) => None,
+            SeekFrom::End(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "ForwardReader can't seek relative to the end",
+                ));
+            }
+        };
+        let target = match target {
+            Some(target) if target >= self.pos => target,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "ForwardReader can't seek backwards",
+                ));
+            }
+        };
+        let skip = target - self.pos;
+        let skipped = io::copy(&mut self.reader.by_ref().take(skip), &mut io::sink())?;
+        self.pos += skipped;
+        if skipped < skip {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "ForwardReader seek past end of stream",
+            ));
+        }
+        Ok(self.pos)
+    }
+}
+
+/// A stream that can be duplicated.
+///
+/// The duplicate refers to the same underlying data. This is used by
+/// [`Book::try_clone_reader`].
+///
+/// Note that a duplicated `File` shares its cursor with the original, so
+/// seeking one will move the other. Concurrent readers should use
+/// positioned reads (e.g. [`Book::read_chapter`]) rather than `Seek`.
+///
+/// [`Book::try_clone_reader`]: crate::Book::try_clone_reader
+/// [`Book::read_chapter`]: crate::Book::read_chapter
+pub trait TryClone: Sized {
+    /// Create a new handle to the same data.
+    fn try_clone(&self) -> io::Result<Self>;
+}
+
+impl TryClone for File {
+    fn try_clone(&self) -> io::Result<Self> {
+        File::try_clone(self)
+    }
+}
+
 // This is a half implementation of the FileExt trait, but since that trait
 // is os-specific, and we don't support `write_at`, supplying a function with
 // the same name seems like an acceptable compromise.
@@ -186,17 +463,17 @@ where
 {
     /// Compute the maximum read length is for a given offset.
     fn cap_length(&self, len: usize, offset: u64) -> usize {
-        let bound_end = self.start.checked_add(self.length).unwrap();
-        if offset > bound_end {
-            // Trying to read past the end of the bounded region.
-            return 0;
-        }
-        let requested_end = self.start + offset + len as u64;
-        let end_reduction = requested_end.saturating_sub(bound_end);
-        // Will always succeed, since end_reduction can never be larger than buf.len()
-        let end_reduction: usize = end_reduction.try_into().unwrap();
-        let capped_len: usize = len.checked_sub(end_reduction).unwrap();
-        capped_len
+        // Work relative to the bounded region, so nothing can overflow.
+        let remaining = self.length.saturating_sub(offset);
+        let remaining: usize = remaining.try_into().unwrap_or(usize::MAX);
+        remaining.min(len)
+    }
+
+    /// Convert an offset within the bounded region to a file offset.
+    fn file_offset(&self, offset: u64) -> io::Result<u64> {
+        self.start
+            .checked_add(offset)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "read offset out of bounds"))
     }
 
     /// Read some bytes from a fixed offset.
@@ -216,9 +493,7 @@ where
         }
         let capped_buf = &mut buf[..capped_len];
 
-        // Will always succeed, since we already checked that the offset
-        // fits within the bounded range.
-        let adjusted_offset = self.start.checked_add(offset).unwrap();
+        let adjusted_offset = self.file_offset(offset)?;
         self.reader.read_at(capped_buf, adjusted_offset)
     }
 
@@ -247,13 +522,78 @@ where
             ));
         }
 
-        // Will always succeed, since we already checked that the offset
-        // fits within the bounded range.
-        let adjusted_offset = self.start.checked_add(offset).unwrap();
+        let adjusted_offset = self.file_offset(offset)?;
         self.reader.read_exact_at(buf, adjusted_offset)
     }
 }
 
+/// A chapter reader that verifies the chapter's checksum as it reads.
+///
+/// This is returned by [`Book::chapter_reader_checked`]. Bytes are passed
+/// through unchanged, and hashed along the way. The `read` call that
+/// reaches the end of the chapter compares the result with the checksum
+/// stored in the TOC; if they differ, it returns an error of kind
+/// `InvalidData` whose inner error is a [`BookError::ChecksumMismatch`].
+/// Bytes returned by earlier reads shouldn't be trusted until then.
+///
+/// If the chapter has no checksum, or its algorithm isn't enabled,
+/// nothing is verified.
+///
+/// [`Book::chapter_reader_checked`]: crate::Book::chapter_reader_checked
+pub struct CheckedChapterReader<R> {
+    reader: BoundedReader<R>,
+    hasher: ChapterHasher,
+    expected: Option<Checksum>,
+    id: Box<[u8]>,
+}
+
+impl<R> CheckedChapterReader<R> {
+    /// Create a new `CheckedChapterReader`.
+    ///
+    /// `reader` must be positioned at the start of the chapter.
+    pub(crate) fn new(reader: BoundedReader<R>, expected: Option<Checksum>, id: Box<[u8]>) -> Self {
+        let kind = expected.and_then(|checksum| checksum.kind());
+        CheckedChapterReader {
+            reader,
+            hasher: ChapterHasher::new(kind.unwrap_or(ChecksumKind::None)),
+            expected,
+            id,
+        }
+    }
+
+    /// Compare the checksum of everything read with the stored one.
+    fn check(&self) -> io::Result<()> {
+        if let (Some(expected), Some(actual)) = (self.expected, self.hasher.finish()) {
+            if actual != expected {
+                let err = BookError::ChecksumMismatch {
+                    id: self.id.clone(),
+                    expected: expected.value(),
+                    actual: actual.value(),
+                };
+                return Err(io::Error::new(io::ErrorKind::InvalidData, err));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<R> Read for CheckedChapterReader<R>
+where
+    R: Read + Seek,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let bytes_read = self.reader.read(buf)?;
+        self.hasher.update(&buf[..bytes_read]);
+        if self.reader.remaining() == 0 {
+            self.check()?;
+        }
+        Ok(bytes_read)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -282,7 +622,17 @@ mod tests {
         assert_eq!(read_buf, [0, 0]);
 
         // Seek past the end
-        let err = subcursor.seek(SeekFrom::Start(6)).unwrap_err();
+        let pos = subcursor.seek(SeekFrom::Start(6)).unwrap();
+        assert_eq!(pos, 5);
+        let bytes_read = subcursor.read(&mut read_buf).unwrap();
+        assert_eq!(bytes_read, 0);
+        let pos = subcursor.seek(SeekFrom::End(100)).unwrap();
+        assert_eq!(pos, 5);
+
+        // Seek before the start
+        let err = subcursor.seek(SeekFrom::End(-6)).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        let err = subcursor.seek(SeekFrom::Current(-6)).unwrap_err();
         assert_eq!(err.kind(), ErrorKind::InvalidInput);
 
         // Seek relative to the end
@@ -301,6 +651,143 @@ mod tests {
         assert_eq!(read_buf, [6, 7, 8, 9]);
     }
 
+    #[test]
+    fn seek_before_read() {
+        let buf: Vec<u8> = (0..128).collect();
+        let mut cursor = Cursor::new(buf);
+        // Leave the underlying reader somewhere outside the bounded range.
+        cursor.seek(SeekFrom::Start(100)).unwrap();
+        let mut subcursor = BoundedReader::new(&mut cursor, 5, 5);
+
+        let pos = subcursor.seek(SeekFrom::Current(2)).unwrap();
+        assert_eq!(pos, 2);
+        let mut read_buf = [0u8; 8];
+        let bytes_read = subcursor.read(&mut read_buf).unwrap();
+        assert_eq!(bytes_read, 3);
+        assert_eq!(read_buf[..3], [7, 8, 9]);
+
+        // Seeking within an empty range never moves the underlying reader.
+        let mut empty = BoundedReader::empty(&mut cursor);
+        assert_eq!(empty.seek(SeekFrom::Start(10)).unwrap(), 0);
+        assert_eq!(empty.seek(SeekFrom::End(0)).unwrap(), 0);
+        assert_eq!(cursor.position(), 10);
+    }
+
+    #[test]
+    fn peek() {
+        let buf: Vec<u8> = (0..128).collect();
+        let mut cursor = Cursor::new(buf);
+        let mut subcursor = BoundedReader::new(&mut cursor, 5, 10);
+
+        // Peeking doesn't consume anything.
+        assert_eq!(subcursor.peek(4).unwrap(), [5, 6, 7, 8]);
+        assert_eq!(subcursor.peek(2).unwrap(), [5, 6]);
+        assert_eq!(subcursor.remaining(), 10);
+        let mut read_buf = [0u8; 2];
+        subcursor.read_exact(&mut read_buf).unwrap();
+        assert_eq!(read_buf, [5, 6]);
+        assert_eq!(subcursor.stream_position().unwrap(), 2);
+
+        // Peeking near the end returns fewer bytes.
+        subcursor.seek(SeekFrom::Start(7)).unwrap();
+        assert_eq!(subcursor.peek(8).unwrap(), [12, 13, 14]);
+        assert_eq!(subcursor.fill_buf().unwrap(), [12, 13, 14]);
+        subcursor.consume(1);
+        let mut rest = Vec::new();
+        subcursor.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, [13, 14]);
+        assert_eq!(subcursor.peek(4).unwrap(), []);
+
+        // No bytes are lost on a forward-only stream.
+        let buf: Vec<u8> = (0..128).collect();
+        let mut reader = ForwardReader::new(buf.as_slice());
+        let mut subreader = BoundedReader::new(&mut reader, 5, 100);
+        assert_eq!(subreader.peek(4).unwrap(), [5, 6, 7, 8]);
+        let mut all = Vec::new();
+        subreader.read_to_end(&mut all).unwrap();
+        assert_eq!(all, buf[5..105]);
+    }
+
+    #[test]
+    fn forward_reader() {
+        let buf: Vec<u8> = (0..128).collect();
+        let mut reader = ForwardReader::new(buf.as_slice());
+
+        assert_eq!(reader.seek(SeekFrom::Start(5)).unwrap(), 5);
+        let mut read_buf = [0u8; 3];
+        reader.read_exact(&mut read_buf).unwrap();
+        assert_eq!(read_buf, [5, 6, 7]);
+        assert_eq!(reader.seek(SeekFrom::Current(2)).unwrap(), 10);
+        assert_eq!(reader.seek(SeekFrom::Start(10)).unwrap(), 10);
+        reader.read_exact(&mut read_buf).unwrap();
+        assert_eq!(read_buf, [10, 11, 12]);
+
+        let err = reader.seek(SeekFrom::Start(0)).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        let err = reader.seek(SeekFrom::Current(-1)).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        let err = reader.seek(SeekFrom::End(0)).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Unsupported);
+        let err = reader.seek(SeekFrom::Start(200)).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn framed_records() {
+        use byteorder::WriteBytesExt;
+
+        let mut buf = vec![0xFF; 5];
+        for record in [&b"first"[..], b"", b"third record"] {
+            buf.write_u32::<BigEndian>(record.len().try_into().unwrap())
+                .unwrap();
+            buf.write_all(record).unwrap();
+        }
+        let chapter_len = buf.len() as u64 - 5;
+        buf.extend_from_slice(&[0xFF; 5]);
+        let mut cursor = Cursor::new(buf);
+
+        let reader = BoundedReader::new(&mut cursor, 5, chapter_len);
+        let records: Vec<_> = FramedChapterReader::new(reader)
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(
+            records,
+            vec![
+                b"first".to_vec().into_boxed_slice(),
+                Box::default(),
+                b"third record".to_vec().into_boxed_slice(),
+            ]
+        );
+
+        // Stop before the end of the last record.
+        let reader = BoundedReader::new(&mut cursor, 5, chapter_len - 1);
+        let mut framed = FramedChapterReader::new(reader);
+        framed.next().unwrap().unwrap();
+        framed.next().unwrap().unwrap();
+        let err = framed.next().unwrap().unwrap_err();
+        assert!(
+            matches!(
+                err,
+                BookError::OutOfBounds {
+                    start: 17,
+                    len: 12,
+                    chapter_len: 28
+                }
+            ),
+            "{:?}",
+            err
+        );
+        assert!(framed.next().is_none());
+
+        // Stop in the middle of a length prefix.
+        let reader = BoundedReader::new(&mut cursor, 5, 11);
+        let mut framed = FramedChapterReader::new(reader);
+        framed.next().unwrap().unwrap();
+        let err = framed.next().unwrap().unwrap_err();
+        assert!(matches!(err, BookError::Eof), "{:?}", err);
+        assert!(framed.next().is_none());
+    }
+
     #[test]
     fn bufread() {
         let mut buf = Vec::<u8>::new();
@@ -347,5 +834,17 @@ mod tests {
         let bytes_read = reader.read_at(&mut read_buf, 5).unwrap();
         assert_eq!(bytes_read, 0);
         assert_eq!(read_buf, [0, 0]);
+
+        // Offsets past the end, including ones that would overflow.
+        for offset in [7, u64::MAX - 5, u64::MAX] {
+            let bytes_read = reader.read_at(&mut read_buf, offset).unwrap();
+            assert_eq!(bytes_read, 0);
+            assert!(reader.read_exact_at(&mut read_buf, offset).is_err());
+        }
+
+        // A region whose end would overflow.
+        let reader = BoundedReader::new(&file, u64::MAX - 1, 5);
+        let err = reader.read_at(&mut read_buf, 3).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
     }
 }
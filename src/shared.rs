@@ -0,0 +1,173 @@
+//! Sharing one `Book` between threads.
+
+use crate::book::{Book, ChapterId, ChapterIndex};
+use crate::Result;
+use std::sync::Arc;
+#[cfg(not(target_family = "unix"))]
+use {
+    std::io::{Read, Seek},
+    std::sync::{Mutex, MutexGuard, PoisonError},
+};
+
+/// A `Book` that can be read from several threads at once.
+///
+/// Cloning a `SharedBook` is cheap: all clones share the same stream and
+/// the same parsed header and TOC.
+///
+/// On Unix, chapters are read with positioned I/O (see
+/// [`Book::read_chapter`]), so readers never seek and never wait for one
+/// another. On other platforms, the stream is protected by a `Mutex`, so
+/// reads are serialized.
+#[derive(Debug)]
+pub struct SharedBook<R> {
+    #[cfg(target_family = "unix")]
+    book: Arc<Book<R>>,
+    #[cfg(not(target_family = "unix"))]
+    book: Arc<Mutex<Book<R>>>,
+}
+
+// A derived `Clone` would require `R: Clone`.
+impl<R> Clone for SharedBook<R> {
+    fn clone(&self) -> Self {
+        SharedBook {
+            book: Arc::clone(&self.book),
+        }
+    }
+}
+
+#[cfg(target_family = "unix")]
+impl<R> SharedBook<R>
+where
+    R: std::os::unix::fs::FileExt,
+{
+    /// Create a new `SharedBook`.
+    pub fn new(book: Book<R>) -> Self {
+        SharedBook {
+            book: Arc::new(book),
+        }
+    }
+
+    /// Return the number of chapters in the book.
+    pub fn len(&self) -> usize {
+        self.book.len()
+    }
+
+    /// Return `true` if the book contains no chapters.
+    pub fn is_empty(&self) -> bool {
+        self.book.is_empty()
+    }
+
+    /// Find a chapter by its id.
+    ///
+    /// See [`Book::find_chapter`].
+    pub fn find_chapter<Id>(&self, id: Id) -> Option<ChapterIndex>
+    where
+        Id: Into<ChapterId>,
+    {
+        self.book.find_chapter(id)
+    }
+
+    /// Read all bytes in a chapter.
+    ///
+    /// See [`Book::read_chapter`].
+    pub fn read_chapter<Id>(&self, id: Id) -> Result<Box<[u8]>>
+    where
+        Id: Into<ChapterId>,
+    {
+        self.book.read_chapter(id)
+    }
+}
+
+#[cfg(not(target_family = "unix"))]
+impl<R> SharedBook<R>
+where
+    R: Read + Seek,
+{
+    /// Create a new `SharedBook`.
+    pub fn new(book: Book<R>) -> Self {
+        SharedBook {
+            book: Arc::new(Mutex::new(book)),
+        }
+    }
+
+    /// Lock the book.
+    ///
+    /// Every read seeks first, so a panic while the lock was held can't
+    /// leave the book in a state that affects later reads.
+    fn lock(&self) -> MutexGuard<'_, Book<R>> {
+        self.book.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Return the number of chapters in the book.
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    /// Return `true` if the book contains no chapters.
+    pub fn is_empty(&self) -> bool {
+        self.lock().is_empty()
+    }
+
+    /// Find a chapter by its id.
+    ///
+    /// See [`Book::find_chapter`].
+    pub fn find_chapter<Id>(&self, id: Id) -> Option<ChapterIndex>
+    where
+        Id: Into<ChapterId>,
+    {
+        self.lock().find_chapter(id)
+    }
+
+    /// Read all bytes in a chapter.
+    ///
+    /// See [`Book::exclusive_read_chapter`].
+    pub fn read_chapter<Id>(&self, id: Id) -> Result<Box<[u8]>>
+    where
+        Id: Into<ChapterId>,
+    {
+        self.lock().exclusive_read_chapter(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BookWriter, ChapterWriter};
+    use std::fs::File;
+    use std::thread;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn send_sync() {
+        assert_send_sync::<SharedBook<File>>();
+        assert_send_sync::<Book<File>>();
+        assert_send_sync::<BookWriter<File>>();
+        assert_send_sync::<ChapterWriter<File>>();
+    }
+
+    #[test]
+    fn shared_reads() {
+        let file = tempfile::tempfile().unwrap();
+        let mut book = BookWriter::new(file, 0x1234).unwrap();
+        for id in 1..=8u64 {
+            let data = format!("This is chapter {}", id);
+            book.write_chapter(id, data.as_bytes()).unwrap();
+        }
+        let file = book.close().unwrap();
+        let shared = SharedBook::new(Book::new(file).unwrap());
+        assert_eq!(shared.len(), 8);
+
+        thread::scope(|scope| {
+            for id in 1..=8u64 {
+                let shared = shared.clone();
+                scope.spawn(move || {
+                    let data = shared.read_chapter(id).unwrap();
+                    let expected = format!("This is chapter {}", id);
+                    assert_eq!(data.as_ref(), expected.as_bytes());
+                });
+            }
+        });
+        assert!(shared.find_chapter(9).is_none());
+    }
+}
@@ -0,0 +1,141 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Cursor, IoSlice, Write};
+
+/// An I/O wrapper that counts the number of bytes written.
+///
+/// `BookWriter` uses this to keep track of the current file offset,
+/// so that every byte written to the underlying stream is accounted for.
+#[derive(Debug)]
+pub(crate) struct CountingWriter<W> {
+    writer: W,
+    count: u64,
+}
+
+impl<W> CountingWriter<W> {
+    /// Create a new `CountingWriter`.
+    pub(crate) fn new(writer: W) -> Self {
+        CountingWriter { writer, count: 0 }
+    }
+
+    /// Create a new `CountingWriter`, starting from a given count.
+    ///
+    /// This is used when the stream already contains `count` bytes.
+    pub(crate) fn with_count(writer: W, count: u64) -> Self {
+        CountingWriter { writer, count }
+    }
+
+    /// Return the total number of bytes written.
+    pub(crate) fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Replace the byte count.
+    ///
+    /// This is used after the inner writer has been repositioned.
+    pub(crate) fn set_count(&mut self, count: u64) {
+        self.count = count;
+    }
+
+    /// Return a mutable reference to the inner writer.
+    ///
+    /// Writing through this reference would corrupt the count.
+    pub(crate) fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    /// Consume the `CountingWriter`, returning the inner writer.
+    pub(crate) fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W> Write for CountingWriter<W>
+where
+    W: Write,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let bytes_written = self.writer.write(buf)?;
+        self.count += bytes_written as u64;
+        Ok(bytes_written)
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        let bytes_written = self.writer.write_vectored(bufs)?;
+        self.count += bytes_written as u64;
+        Ok(bytes_written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// A stream that can be persisted to stable storage.
+///
+/// This is used by [`BookWriter::sync_all`]. The default implementation
+/// does nothing, which is appropriate for in-memory buffers or streams
+/// with no notion of durability.
+///
+/// [`BookWriter::sync_all`]: crate::BookWriter::sync_all
+pub trait Durable {
+    /// Ask the OS to persist all data written so far.
+    ///
+    /// Any buffered data should already have been flushed.
+    fn sync(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Durable for File {
+    fn sync(&mut self) -> io::Result<()> {
+        self.sync_all()
+    }
+}
+
+impl Durable for &File {
+    fn sync(&mut self) -> io::Result<()> {
+        self.sync_all()
+    }
+}
+
+impl<W> Durable for &mut W
+where
+    W: Durable + ?Sized,
+{
+    fn sync(&mut self) -> io::Result<()> {
+        (**self).sync()
+    }
+}
+
+impl<W> Durable for BufWriter<W>
+where
+    W: Durable + Write,
+{
+    fn sync(&mut self) -> io::Result<()> {
+        self.flush()?;
+        self.get_mut().sync()
+    }
+}
+
+impl Durable for Vec<u8> {}
+
+impl<T> Durable for Cursor<T> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counting() {
+        let mut writer = CountingWriter::new(Vec::<u8>::new());
+        assert_eq!(writer.count(), 0);
+        writer.write_all(b"hello").unwrap();
+        writer.write_all(b"").unwrap();
+        writer.write_all(b", world").unwrap();
+        assert_eq!(writer.count(), 12);
+        let bufs = [IoSlice::new(b"!"), IoSlice::new(b"!!")];
+        assert_eq!(writer.write_vectored(&bufs).unwrap(), 3);
+        assert_eq!(writer.count(), 15);
+        assert_eq!(writer.into_inner(), b"hello, world!!!");
+    }
+}